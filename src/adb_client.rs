@@ -0,0 +1,882 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+
+pub static DEFAULT_ADB_SERVER_PORT: u16 = 5037;
+
+// largest payload the adb daemon accepts in a single sync DATA packet
+static SYNC_DATA_MAX: usize = 64 * 1024;
+
+static S_IFMT: u32 = 0o170000;
+static S_IFDIR: u32 = 0o040000;
+static S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Fail)]
+pub enum AdbClientError {
+    #[fail(display = "adb server rejected '{}': {}", request, message)]
+    RequestFailed {
+        request: String,
+        message: String,
+    },
+
+    #[fail(display = "unexpected response from adb server: {}", response)]
+    UnexpectedResponse {
+        response: String,
+    },
+
+    #[fail(display = "remote path '{}' does not exist", path)]
+    RemotePathNotFound {
+        path: String,
+    },
+
+    #[fail(display = "adb command '{}' is not supported by the native client", command)]
+    UnsupportedCommand {
+        command: String,
+    },
+}
+
+/// Client for the adb host protocol, talking directly to the adb server on
+/// localhost instead of spawning the `adb` binary.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AdbClient {
+    address: String,
+}
+
+impl AdbClient {
+    pub fn new() -> Self {
+        let port = env::var("ANDROID_ADB_SERVER_PORT")
+            .ok()
+            .and_then(|port| port.parse::<u16>().ok())
+            .unwrap_or(DEFAULT_ADB_SERVER_PORT);
+
+        AdbClient::with_address(&format!("127.0.0.1:{}", port))
+    }
+
+    pub fn with_address(address: &str) -> Self {
+        AdbClient {
+            address: String::from(address),
+        }
+    }
+
+    pub fn devices(&self) -> Result<String, Error> {
+        let mut stream = self.connect()?;
+        Self::send_request(&mut stream, "host:devices-l")?;
+
+        let payload = Self::read_length_prefixed(&mut stream)?;
+        Ok(String::from_utf8_lossy(&payload).to_string())
+    }
+
+    pub fn shell(&self, device_id: Option<&str>, command: &str) -> Result<Vec<u8>, Error> {
+        let mut stream = self.open_service(device_id, &format!("shell:{}", command))?;
+
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output)?;
+
+        Ok(output)
+    }
+
+    /// Streams the backup the device sends into `local`. The file is only created once
+    /// the device accepted the backup, so a failed connection leaves an existing file alone.
+    pub fn backup(&self, device_id: Option<&str>, args: &[&str], local: &Path) -> Result<(), Error> {
+        let service = args
+            .iter()
+            .fold(String::from("backup:"), |service, arg| format!("{} {}", service, arg));
+        let mut stream = self.open_service(device_id, &service)?;
+
+        let mut output = File::create(local)?;
+        copy_stream(&mut stream, &mut output)?;
+
+        Ok(())
+    }
+
+    pub fn restore<R: Read>(&self, device_id: Option<&str>, input: &mut R) -> Result<(), Error> {
+        let mut stream = self.open_service(device_id, "restore:")?;
+
+        copy_stream(input, &mut stream)?;
+
+        // wait until the device has consumed everything before hanging up
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        Ok(())
+    }
+
+    pub fn pull(&self, device_id: Option<&str>, remote: &str, local: &Path) -> Result<(), Error> {
+        let mut sync = SyncConnection::open(self, device_id)?;
+        sync.pull(remote, local)?;
+        sync.quit()
+    }
+
+    pub fn push(&self, device_id: Option<&str>, local: &Path, remote: &str) -> Result<(), Error> {
+        let mut sync = SyncConnection::open(self, device_id)?;
+
+        let remote = match sync.stat(remote)? {
+            Some(stat) if stat.is_dir() => join_remote(remote, &file_name(local)),
+            _ => String::from(remote),
+        };
+
+        sync.push(local, &remote)?;
+        sync.quit()
+    }
+
+    /// Installs the base and split apks of an app in one session of the package manager,
+    /// as `adb install-multiple` does. `options`, e.g. `-r`, are passed on to it. A session
+    /// which fails is abandoned, so nothing of the app is installed.
+    pub fn install_multiple(&self, device_id: Option<&str>, options: &[&str], apks: &[&Path]) -> Result<String, Error> {
+        let size = apks
+            .iter()
+            .map(|apk| Ok(fs::metadata(apk)?.len()))
+            .sum::<Result<u64, Error>>()?;
+        let create = options
+            .iter()
+            .fold(String::from("install-create"), |create, option| format!("{} {}", create, option));
+
+        let created = self.package_manager(device_id, &format!("{} -S {}", create, size), None)?;
+        let session = created
+            .rsplit('[')
+            .next()
+            .and_then(|rest| rest.split(']').next())
+            .filter(|session| !session.is_empty() && session.chars().all(|c| c.is_ascii_digit()))
+            .map(String::from)
+            .ok_or_else(|| Error::from(AdbClientError::UnexpectedResponse { response: String::from(created.trim()) }))?;
+
+        let installed = apks
+            .iter()
+            .enumerate()
+            .map(|(index, apk)| {
+                let write = format!("install-write -S {} {} {}_{} -", fs::metadata(apk)?.len(), session, index, file_name(apk));
+                self.package_manager(device_id, &write, Some(&mut File::open(apk)?))
+            })
+            .collect::<Result<Vec<String>, Error>>()
+            .and_then(|_| self.package_manager(device_id, &format!("install-commit {}", session), None));
+
+        if installed.is_err() {
+            let _ = self.package_manager(device_id, &format!("install-abandon {}", session), None);
+        }
+
+        installed
+    }
+
+    // runs `cmd package <command>` on the device with `input` as its standard input, the
+    // package manager answers a command it carried out with `Success`
+    fn package_manager(&self, device_id: Option<&str>, command: &str, input: Option<&mut dyn Read>) -> Result<String, Error> {
+        let mut stream = self.open_service(device_id, &format!("exec:cmd package {}", command))?;
+        if let Some(input) = input {
+            copy_stream(input, &mut stream)?;
+        }
+
+        let mut output = String::new();
+        stream.read_to_string(&mut output)?;
+
+        match output.starts_with("Success") {
+            true => Ok(output),
+            false => Err(Error::from(AdbClientError::RequestFailed {
+                request: format!("cmd package {}", command),
+                message: String::from(output.trim()),
+            })),
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Error> {
+        trace!("Connecting to adb server at {}", self.address);
+        Ok(TcpStream::connect(self.address.as_str())?)
+    }
+
+    fn open_service(&self, device_id: Option<&str>, service: &str) -> Result<TcpStream, Error> {
+        let mut stream = self.connect()?;
+
+        let transport = match device_id {
+            Some(device_id) => format!("host:transport:{}", device_id),
+            None => String::from("host:transport-any"),
+        };
+        Self::send_request(&mut stream, &transport)?;
+        Self::send_request(&mut stream, service)?;
+
+        Ok(stream)
+    }
+
+    fn send_request(stream: &mut TcpStream, request: &str) -> Result<(), Error> {
+        trace!("Sending adb request: {}", request);
+        stream.write_all(format!("{:04x}{}", request.len(), request).as_bytes())?;
+
+        let mut status = [0u8; 4];
+        stream.read_exact(&mut status)?;
+
+        match &status {
+            b"OKAY" => Ok(()),
+            b"FAIL" => {
+                let message = Self::read_length_prefixed(stream)?;
+                Err(Error::from(AdbClientError::RequestFailed {
+                    request: String::from(request),
+                    message: String::from_utf8_lossy(&message).to_string(),
+                }))
+            }
+            _ => Err(Error::from(AdbClientError::UnexpectedResponse {
+                response: String::from_utf8_lossy(&status).to_string(),
+            })),
+        }
+    }
+
+    fn read_length_prefixed(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length)?;
+
+        let length = String::from_utf8_lossy(&length).to_string();
+        let length = usize::from_str_radix(&length, 16).map_err(|_| {
+            Error::from(AdbClientError::UnexpectedResponse { response: length.clone() })
+        })?;
+
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload)?;
+
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct RemoteStat {
+    mode: u32,
+    size: u32,
+    mtime: u32,
+}
+
+impl RemoteStat {
+    fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// A connection switched into the adb `sync:` file transfer protocol.
+struct SyncConnection {
+    stream: TcpStream,
+}
+
+impl SyncConnection {
+    fn open(client: &AdbClient, device_id: Option<&str>) -> Result<SyncConnection, Error> {
+        let stream = client.open_service(device_id, "sync:")?;
+        Ok(SyncConnection { stream })
+    }
+
+    fn stat(&mut self, remote: &str) -> Result<Option<RemoteStat>, Error> {
+        self.send_packet(b"STAT", remote.as_bytes())?;
+        self.expect_id(b"STAT")?;
+
+        let stat = RemoteStat {
+            mode: read_u32_le(&mut self.stream)?,
+            size: read_u32_le(&mut self.stream)?,
+            mtime: read_u32_le(&mut self.stream)?,
+        };
+
+        match stat.mode {
+            0 => Ok(None),
+            _ => Ok(Some(stat)),
+        }
+    }
+
+    fn list(&mut self, remote: &str) -> Result<Vec<(String, RemoteStat)>, Error> {
+        self.send_packet(b"LIST", remote.as_bytes())?;
+
+        let mut entries = Vec::new();
+        loop {
+            let id = self.read_id()?;
+            let stat = RemoteStat {
+                mode: read_u32_le(&mut self.stream)?,
+                size: read_u32_le(&mut self.stream)?,
+                mtime: read_u32_le(&mut self.stream)?,
+            };
+            let name_length = read_u32_le(&mut self.stream)? as usize;
+
+            match &id {
+                b"DENT" => {
+                    let mut name = vec![0u8; name_length];
+                    self.stream.read_exact(&mut name)?;
+                    let name = String::from_utf8_lossy(&name).to_string();
+
+                    if name != "." && name != ".." {
+                        entries.push((name, stat));
+                    }
+                }
+                b"DONE" => return Ok(entries),
+                _ => return Err(unexpected_id(&id)),
+            }
+        }
+    }
+
+    fn pull(&mut self, remote: &str, local: &Path) -> Result<(), Error> {
+        let stat = match self.stat(remote)? {
+            Some(stat) => stat,
+            None => {
+                return Err(Error::from(AdbClientError::RemotePathNotFound {
+                    path: String::from(remote),
+                }))
+            }
+        };
+
+        if stat.is_dir() {
+            fs::create_dir_all(local)?;
+
+            for (name, _) in self.list(remote)? {
+                self.pull(&join_remote(remote, &name), &local.join(&name))?;
+            }
+
+            return Ok(());
+        }
+
+        trace!("Pulling {} ({} bytes) to {}", remote, stat.size, local.display());
+        let mut file = File::create(local)?;
+        self.send_packet(b"RECV", remote.as_bytes())?;
+
+        loop {
+            let id = self.read_id()?;
+            let length = read_u32_le(&mut self.stream)? as usize;
+
+            match &id {
+                b"DATA" => {
+                    let mut data = vec![0u8; length];
+                    self.stream.read_exact(&mut data)?;
+                    file.write_all(&data)?;
+                }
+                b"DONE" => return Ok(()),
+                b"FAIL" => return Err(self.read_failure(remote, length)),
+                _ => return Err(unexpected_id(&id)),
+            }
+        }
+    }
+
+    fn push(&mut self, local: &Path, remote: &str) -> Result<(), Error> {
+        if local.is_dir() {
+            for entry in fs::read_dir(local)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                self.push(&entry.path(), &join_remote(remote, &name))?;
+            }
+
+            return Ok(());
+        }
+
+        trace!("Pushing {} to {}", local.display(), remote);
+        let mut file = File::open(local)?;
+        let mtime = fs::metadata(local)?
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(::std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as u32)
+            .unwrap_or(0);
+
+        let path_and_mode = format!("{},{}", remote, S_IFREG | 0o644);
+        self.send_packet(b"SEND", path_and_mode.as_bytes())?;
+
+        let mut buffer = vec![0u8; SYNC_DATA_MAX];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.send_packet(b"DATA", &buffer[..read])?;
+        }
+
+        let mut done = Vec::from(&b"DONE"[..]);
+        write_u32_le(&mut done, mtime);
+        self.stream.write_all(&done)?;
+
+        let id = self.read_id()?;
+        let length = read_u32_le(&mut self.stream)? as usize;
+        match &id {
+            b"OKAY" => Ok(()),
+            b"FAIL" => Err(self.read_failure(remote, length)),
+            _ => Err(unexpected_id(&id)),
+        }
+    }
+
+    fn quit(mut self) -> Result<(), Error> {
+        self.send_packet(b"QUIT", &[])?;
+        self.stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    fn send_packet(&mut self, id: &[u8; 4], payload: &[u8]) -> Result<(), Error> {
+        let mut packet = Vec::with_capacity(8 + payload.len());
+        packet.extend_from_slice(id);
+        write_u32_le(&mut packet, payload.len() as u32);
+        packet.extend_from_slice(payload);
+
+        self.stream.write_all(&packet)?;
+        Ok(())
+    }
+
+    fn read_id(&mut self) -> Result<[u8; 4], Error> {
+        let mut id = [0u8; 4];
+        self.stream.read_exact(&mut id)?;
+        Ok(id)
+    }
+
+    fn expect_id(&mut self, expected: &[u8; 4]) -> Result<(), Error> {
+        let id = self.read_id()?;
+        if &id != expected {
+            return Err(unexpected_id(&id));
+        }
+        Ok(())
+    }
+
+    fn read_failure(&mut self, path: &str, length: usize) -> Error {
+        let mut message = vec![0u8; length];
+        if let Err(e) = self.stream.read_exact(&mut message) {
+            return Error::from(e);
+        }
+
+        Error::from(AdbClientError::RequestFailed {
+            request: String::from(path),
+            message: String::from_utf8_lossy(&message).to_string(),
+        })
+    }
+}
+
+fn copy_stream<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> Result<u64, Error> {
+    Ok(::std::io::copy(reader, writer)?)
+}
+
+fn unexpected_id(id: &[u8; 4]) -> Error {
+    Error::from(AdbClientError::UnexpectedResponse {
+        response: String::from_utf8_lossy(id).to_string(),
+    })
+}
+
+fn join_remote(parent: &str, name: &str) -> String {
+    format!("{}/{}", parent.trim_end_matches('/'), name)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Local destination `adb pull` uses for a remote path: its last component,
+/// relative to the current directory.
+pub fn local_pull_target(remote: &str) -> PathBuf {
+    let name = remote
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(remote);
+
+    PathBuf::from(name)
+}
+
+fn write_u32_le(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&[
+        value as u8,
+        (value >> 8) as u8,
+        (value >> 16) as u8,
+        (value >> 24) as u8,
+    ]);
+}
+
+fn read_u32_le<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0u32, |value, byte| (value << 8) | u32::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use adb_client::{local_pull_target, read_u32_le, write_u32_le, AdbClient};
+    use adb_runner::{AdbOutput, AdbRunner, ServerRunner};
+    use std::fs::{create_dir_all, remove_dir_all, remove_file, File};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::Duration;
+
+    fn fake_server<F>(handler: F) -> (AdbClient, thread::JoinHandle<()>)
+    where
+        F: FnOnce(TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handler(stream);
+        });
+
+        (AdbClient::with_address(&address.to_string()), server)
+    }
+
+    // answers one connection after another, `handler` is told which one it is
+    fn fake_server_connections<F>(connections: usize, mut handler: F) -> (AdbClient, thread::JoinHandle<()>)
+    where
+        F: FnMut(usize, TcpStream) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            for connection in 0..connections {
+                let (stream, _) = listener.accept().unwrap();
+                handler(connection, stream);
+            }
+        });
+
+        (AdbClient::with_address(&address.to_string()), server)
+    }
+
+    fn expect_request(stream: &mut TcpStream, expected: &str) {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length).unwrap();
+        let length = usize::from_str_radix(&String::from_utf8_lossy(&length), 16).unwrap();
+
+        let mut request = vec![0u8; length];
+        stream.read_exact(&mut request).unwrap();
+        assert_that!(String::from_utf8(request).unwrap(), is(equal_to(expected.to_string())));
+    }
+
+    fn expect_packet(stream: &mut TcpStream, expected_id: &[u8], expected_payload: &[u8]) {
+        let mut id = [0u8; 4];
+        stream.read_exact(&mut id).unwrap();
+        assert_eq!(&id[..], expected_id);
+
+        let length = read_u32_le(stream).unwrap() as usize;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload).unwrap();
+        assert_eq!(&payload[..], expected_payload);
+    }
+
+    fn sync_packet(id: &[u8], values: &[u32]) -> Vec<u8> {
+        let mut packet = Vec::from(id);
+        values.iter().for_each(|value| write_u32_le(&mut packet, *value));
+        packet
+    }
+
+    #[test]
+    fn test_devices() {
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:devices-l");
+
+            let devices = "emulator-5554          device product:sdk_google_phone_x86 \
+                           model:Android_SDK_built_for_x86 device:generic_x86 transport_id:9\n";
+            let response = format!("OKAY{:04x}{}", devices.len(), devices);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        let devices = client.devices().unwrap();
+        server.join().unwrap();
+
+        assert!(devices.starts_with("emulator-5554          device product:"));
+    }
+
+    #[test]
+    fn test_shell_keeps_binary_output() {
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "shell:cat /data/local/tmp/blob");
+            stream.write_all(b"OKAY").unwrap();
+            stream.write_all(&[0x00, 0xff, 0xfe, 0x0d, 0x0a]).unwrap();
+        });
+
+        let output = client
+            .shell(Some("emulator-5554"), "cat /data/local/tmp/blob")
+            .unwrap();
+        server.join().unwrap();
+
+        assert_that!(output, is(equal_to(vec![0x00, 0xff, 0xfe, 0x0d, 0x0a])));
+    }
+
+    #[test]
+    fn test_failed_request() {
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport:unknown-device");
+            let message = "device 'unknown-device' not found";
+            let response = format!("FAIL{:04x}{}", message.len(), message);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        let error = client.shell(Some("unknown-device"), "ls").unwrap_err();
+        server.join().unwrap();
+
+        assert_that!(
+            format!("{}", error),
+            is(equal_to(String::from(
+                "adb server rejected 'host:transport:unknown-device': \
+                 device 'unknown-device' not found"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_backup_streams_to_file() {
+        let local_file = "22d0a619c96de34ad16c305b380d17fa"; // md5 of 'test_backup_streams_to_file'
+
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport-any");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "backup: -noapk -all");
+            stream.write_all(b"OKAY").unwrap();
+            stream.write_all(b"ANDROID BACKUP\n5\n1\nnone\n\x78\x9c").unwrap();
+        });
+
+        client.backup(None, &["-noapk", "-all"], Path::new(local_file)).unwrap();
+        server.join().unwrap();
+
+        let mut output = Vec::new();
+        File::open(local_file).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(&output[..], &b"ANDROID BACKUP\n5\n1\nnone\n\x78\x9c"[..]);
+
+        assert!(remove_file(local_file).is_ok());
+    }
+
+    #[test]
+    fn test_failed_backup_keeps_existing_file() {
+        let local_file = "44cc10b7ce0dec594165e46505130b2e"; // md5 of 'test_failed_backup_keeps_existing_file'
+        File::create(local_file).unwrap().write_all(b"previous backup").unwrap();
+
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            let message = "device offline";
+            let response = format!("FAIL{:04x}{}", message.len(), message);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        assert!(client.backup(Some("emulator-5554"), &["-all"], Path::new(local_file)).is_err());
+        server.join().unwrap();
+
+        let mut content = String::new();
+        File::open(local_file).unwrap().read_to_string(&mut content).unwrap();
+        assert_that!(content, is(equal_to(String::from("previous backup"))));
+
+        assert!(remove_file(local_file).is_ok());
+    }
+
+    #[test]
+    fn test_restore_sends_backup_unchanged() {
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport-any");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "restore:");
+            stream.write_all(b"OKAY").unwrap();
+
+            let mut received = [0u8; 24];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(&received[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+
+            // nothing follows the backup itself
+            stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            assert!(stream.read(&mut received).is_err());
+        });
+
+        client.restore(None, &mut &b"ANDROID BACKUP\n5\n0\nnone\n"[..]).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_sync_pull_file() {
+        let local_file = "2fac380c18189bdb6fe4f700baed384b"; // md5 of 'test_sync_pull_file'
+
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "sync:");
+            stream.write_all(b"OKAY").unwrap();
+
+            expect_packet(&mut stream, b"STAT", b"/sdcard/notes.txt");
+            stream.write_all(&sync_packet(b"STAT", &[0o100644, 5, 0])).unwrap();
+
+            expect_packet(&mut stream, b"RECV", b"/sdcard/notes.txt");
+            let mut data = sync_packet(b"DATA", &[5]);
+            data.extend_from_slice(b"hello");
+            data.extend_from_slice(&sync_packet(b"DONE", &[0]));
+            stream.write_all(&data).unwrap();
+
+            expect_packet(&mut stream, b"QUIT", b"");
+        });
+
+        client
+            .pull(Some("emulator-5554"), "/sdcard/notes.txt", Path::new(local_file))
+            .unwrap();
+        server.join().unwrap();
+
+        let mut content = String::new();
+        File::open(local_file).unwrap().read_to_string(&mut content).unwrap();
+        assert_that!(content, is(equal_to(String::from("hello"))));
+
+        assert!(remove_file(local_file).is_ok());
+    }
+
+    #[test]
+    fn test_sync_push_into_directory() {
+        let local_file = "33bc8e9a4dc3b2fe277ecbfac2b17c55"; // md5 of 'test_sync_push_into_directory'
+        File::create(local_file).unwrap().write_all(b"pushed").unwrap();
+
+        let (client, server) = fake_server(move |mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "sync:");
+            stream.write_all(b"OKAY").unwrap();
+
+            expect_packet(&mut stream, b"STAT", b"/sdcard/la/");
+            stream.write_all(&sync_packet(b"STAT", &[0o040771, 4096, 0])).unwrap();
+
+            let send = format!("/sdcard/la/{},{}", local_file, 0o100644);
+            expect_packet(&mut stream, b"SEND", send.as_bytes());
+            expect_packet(&mut stream, b"DATA", b"pushed");
+
+            let mut done = [0u8; 8];
+            stream.read_exact(&mut done).unwrap();
+            assert_eq!(&done[..4], b"DONE");
+
+            stream.write_all(&sync_packet(b"OKAY", &[0])).unwrap();
+            expect_packet(&mut stream, b"QUIT", b"");
+        });
+
+        client
+            .push(Some("emulator-5554"), Path::new(local_file), "/sdcard/la/")
+            .unwrap();
+        server.join().unwrap();
+
+        assert!(remove_file(local_file).is_ok());
+    }
+
+    #[test]
+    fn test_server_runner_pulls_into_target() {
+        let local_file = "621cf247bb9ddb55d86ade01908a2e36"; // md5 of 'test_server_runner_pulls_into_target'
+
+        let (client, server) = fake_server(|mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            expect_request(&mut stream, "sync:");
+            stream.write_all(b"OKAY").unwrap();
+
+            expect_packet(&mut stream, b"STAT", b"/sdcard/notes.txt");
+            stream.write_all(&sync_packet(b"STAT", &[0o100644, 5, 0])).unwrap();
+
+            expect_packet(&mut stream, b"RECV", b"/sdcard/notes.txt");
+            let mut data = sync_packet(b"DATA", &[5]);
+            data.extend_from_slice(b"hello");
+            data.extend_from_slice(&sync_packet(b"DONE", &[0]));
+            stream.write_all(&data).unwrap();
+
+            expect_packet(&mut stream, b"QUIT", b"");
+        });
+
+        let output = ServerRunner::with_client(client)
+            .run(&["-s", "emulator-5554", "pull", "/sdcard/notes.txt", local_file, "-a"])
+            .unwrap();
+        server.join().unwrap();
+
+        assert_that!(output, is(equal_to(AdbOutput::stdout(""))));
+        assert!(!Path::new("notes.txt").exists());
+
+        let mut content = String::new();
+        File::open(local_file).unwrap().read_to_string(&mut content).unwrap();
+        assert_that!(content, is(equal_to(String::from("hello"))));
+
+        assert!(remove_file(local_file).is_ok());
+    }
+
+    #[test]
+    fn test_install_multiple() {
+        let directory = Path::new("969a15c5c972f79bd1ee140bb54bf6e7"); // md5 of 'test_install_multiple'
+        create_dir_all(directory).unwrap();
+        let base = directory.join("base.apk");
+        let split = directory.join("split_config.arm64_v8a.apk");
+        File::create(&base).unwrap().write_all(b"base").unwrap();
+        File::create(&split).unwrap().write_all(b"split").unwrap();
+
+        let (client, server) = fake_server_connections(4, |connection, mut stream| {
+            expect_request(&mut stream, "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+
+            let (request, data, output): (&str, &[u8], &str) = match connection {
+                0 => ("exec:cmd package install-create -r -S 9", b"", "Success: created install session [1234]\n"),
+                1 => ("exec:cmd package install-write -S 4 1234 0_base.apk -", b"base", "Success: streamed 4 bytes\n"),
+                2 => (
+                    "exec:cmd package install-write -S 5 1234 1_split_config.arm64_v8a.apk -",
+                    b"split",
+                    "Success: streamed 5 bytes\n",
+                ),
+                _ => ("exec:cmd package install-commit 1234", b"", "Success\n"),
+            };
+            expect_request(&mut stream, request);
+            stream.write_all(b"OKAY").unwrap();
+
+            let mut received = vec![0u8; data.len()];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(&received[..], data);
+            stream.write_all(output.as_bytes()).unwrap();
+        });
+
+        let output = ServerRunner::with_client(client)
+            .run(&[
+                "-s", "emulator-5554", "install-multiple", "-r",
+                &base.to_string_lossy(), &split.to_string_lossy(),
+            ])
+            .unwrap();
+        server.join().unwrap();
+
+        assert_that!(output, is(equal_to(AdbOutput::stdout("Success\n"))));
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_failed_install_multiple_is_abandoned() {
+        let directory = Path::new("8a8f109a8486ed6f513f982be0685ecb"); // md5 of 'test_failed_install_multiple_is_abandoned'
+        create_dir_all(directory).unwrap();
+        let base = directory.join("base.apk");
+        File::create(&base).unwrap().write_all(b"base").unwrap();
+
+        let (client, server) = fake_server_connections(4, |connection, mut stream| {
+            expect_request(&mut stream, "host:transport-any");
+            stream.write_all(b"OKAY").unwrap();
+
+            let (request, output) = match connection {
+                0 => ("exec:cmd package install-create -S 4", "Success: created install session [7]\n"),
+                1 => ("exec:cmd package install-write -S 4 7 0_base.apk -", "Success: streamed 4 bytes\n"),
+                2 => (
+                    "exec:cmd package install-commit 7",
+                    "Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: signatures do not match]\n",
+                ),
+                _ => ("exec:cmd package install-abandon 7", "Success\n"),
+            };
+            expect_request(&mut stream, request);
+            stream.write_all(b"OKAY").unwrap();
+            if connection == 1 {
+                stream.read_exact(&mut [0u8; 4]).unwrap();
+            }
+            stream.write_all(output.as_bytes()).unwrap();
+        });
+
+        let error = client.install_multiple(None, &[], &[&base]).unwrap_err();
+        server.join().unwrap();
+
+        assert_that!(
+            format!("{}", error),
+            is(equal_to(String::from(
+                "adb server rejected 'cmd package install-commit 7': \
+                 Failure [INSTALL_FAILED_UPDATE_INCOMPATIBLE: signatures do not match]"
+            )))
+        );
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_local_pull_target() {
+        assert_that!(local_pull_target("/sdcard/la/"), is(equal_to(PathBuf::from("la"))));
+        assert_that!(
+            local_pull_target("/sdcard/DCIM/photo.jpg"),
+            is(equal_to(PathBuf::from("photo.jpg")))
+        );
+    }
+}
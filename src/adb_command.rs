@@ -1,7 +1,7 @@
-use std::process::{Command, Stdio};
-
 use failure::{err_msg, Error};
 
+use adb_runner;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct AdbCommand<'a> {
     command: &'a str,
@@ -33,31 +33,37 @@ impl<'a> AdbCommand<'a> {
     }
 
     pub fn execute(self) -> Result<String, Error> {
-        let mut command = Command::new("adb");
+        let command = self.command;
+        let output = self.execute_raw()?;
 
-        if let Some(device_id) = self.device_id {
-            command.arg("-s").arg(device_id);
-        }
+        let output_message = String::from_utf8_lossy(&output);
+        trace!("output message from {}: {}", command, output_message);
+        Ok(output_message.to_string())
+    }
 
-        command.arg(&self.command);
-        if self.args.len() > 0 {
-            command.args(self.args);
+    pub fn execute_raw(self) -> Result<Vec<u8>, Error> {
+        let mut args = Vec::new();
+        if let Some(device_id) = self.device_id {
+            args.push("-s");
+            args.push(device_id);
         }
+        args.push(self.command);
+        args.extend(self.args.iter());
 
         trace!("Executing command: {}", self.command);
 
-        let output = command.stderr(Stdio::piped()).output()?;
+        let output = adb_runner::current_runner()
+            .run(&args)
+            .map_err(|e| err_msg(format!("Error executing {}.\n {}", self.command, e)))?;
 
-        if output.status.success() {
-            let output_message = String::from_utf8_lossy(&output.stdout);
-            trace!("output message from {}: {}", self.command, output_message);
-            Ok(output_message.to_string())
+        if output.is_success() {
+            Ok(output.stdout)
         } else {
             let error_message = String::from_utf8_lossy(&output.stderr);
-            return Err(err_msg(format!(
+            Err(err_msg(format!(
                 "Error executing {}.\n {}",
                 self.command, error_message
-            )));
+            )))
         }
     }
 }
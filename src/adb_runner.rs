@@ -0,0 +1,263 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+use failure::{err_msg, Error};
+
+use adb_client::{local_pull_target, AdbClient, AdbClientError};
+
+thread_local! {
+    static RUNNER: RefCell<Rc<dyn AdbRunner>> = RefCell::new(Rc::new(ServerRunner::new()));
+}
+
+/// Replaces the runner every `AdbCommand` of the current thread is executed with.
+pub fn set_runner(runner: Rc<dyn AdbRunner>) {
+    RUNNER.with(|current| *current.borrow_mut() = runner);
+}
+
+pub fn current_runner() -> Rc<dyn AdbRunner> {
+    RUNNER.with(|current| current.borrow().clone())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AdbOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: i32,
+}
+
+impl AdbOutput {
+    pub fn new(stdout: Vec<u8>, stderr: Vec<u8>, status: i32) -> Self {
+        AdbOutput {
+            stdout,
+            stderr,
+            status,
+        }
+    }
+
+    pub fn stdout(stdout: &str) -> Self {
+        AdbOutput::new(stdout.as_bytes().to_vec(), Vec::new(), 0)
+    }
+
+    pub fn error(status: i32, stderr: &str) -> Self {
+        AdbOutput::new(Vec::new(), stderr.as_bytes().to_vec(), status)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// Executes an adb invocation, given as the argument vector the `adb` binary would get
+/// (e.g. `["-s", "emulator-5554", "shell", "pm", "list", "packages"]`).
+pub trait AdbRunner {
+    fn run(&self, args: &[&str]) -> Result<AdbOutput, Error>;
+}
+
+/// Runs adb invocations against the adb server with the native protocol client.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ServerRunner {
+    client: AdbClient,
+}
+
+impl ServerRunner {
+    pub fn new() -> Self {
+        ServerRunner::with_client(AdbClient::new())
+    }
+
+    pub fn with_client(client: AdbClient) -> Self {
+        ServerRunner { client }
+    }
+
+    fn backup(&self, device_id: Option<&str>, args: &[&str]) -> Result<Vec<u8>, Error> {
+        // `-f <file>` is handled on this side of the connection, the device streams the
+        // backup back and never sees the file name
+        let mut backup_args = Vec::new();
+        let mut backup_file = "backup.ab";
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match *arg {
+                "-f" => backup_file = iter.next().ok_or_else(|| err_msg("-f requires a file"))?,
+                arg => backup_args.push(arg),
+            }
+        }
+
+        self.client.backup(device_id, &backup_args, Path::new(backup_file))?;
+
+        Ok(Vec::new())
+    }
+
+    fn restore(&self, device_id: Option<&str>, args: &[&str]) -> Result<Vec<u8>, Error> {
+        let backup_file = args.first().ok_or_else(|| err_msg("No backup file given"))?;
+
+        let mut input = File::open(backup_file)?;
+        self.client.restore(device_id, &mut input)?;
+
+        Ok(Vec::new())
+    }
+
+    fn pull(&self, device_id: Option<&str>, args: &[&str]) -> Result<Vec<u8>, Error> {
+        // `-a` (preserve timestamps) only concerns the adb binary, file contents are
+        // transferred unchanged either way
+        let mut paths = args.iter().filter(|arg| !arg.starts_with('-'));
+        let remote = paths.next().ok_or_else(|| err_msg("No source path given"))?;
+
+        // like adb, into the current directory without a target and into a target which is
+        // an existing directory
+        let local = match paths.next() {
+            Some(target) if Path::new(target).is_dir() => Path::new(target).join(local_pull_target(remote)),
+            Some(target) => PathBuf::from(target),
+            None => local_pull_target(remote),
+        };
+
+        self.client.pull(device_id, remote, &local)?;
+
+        Ok(Vec::new())
+    }
+
+    fn install_multiple(&self, device_id: Option<&str>, args: &[&str]) -> Result<Vec<u8>, Error> {
+        let (options, apks): (Vec<&str>, Vec<&str>) = args.iter().partition(|arg| arg.starts_with('-'));
+        if apks.is_empty() {
+            return Err(err_msg("No apks given"));
+        }
+
+        let apks = apks.iter().map(Path::new).collect::<Vec<&Path>>();
+        let output = self.client.install_multiple(device_id, &options, &apks)?;
+
+        Ok(output.into_bytes())
+    }
+
+    fn push(&self, device_id: Option<&str>, args: &[&str]) -> Result<Vec<u8>, Error> {
+        match (args.get(0), args.get(1)) {
+            (Some(src_path), Some(dst_path)) => {
+                self.client.push(device_id, Path::new(src_path), dst_path)?;
+                Ok(Vec::new())
+            }
+            _ => Err(err_msg("Source or target not specified")),
+        }
+    }
+}
+
+impl AdbRunner for ServerRunner {
+    fn run(&self, args: &[&str]) -> Result<AdbOutput, Error> {
+        let (device_id, args) = if args.len() > 1 && args[0] == "-s" {
+            (Some(args[1]), &args[2..])
+        } else {
+            (None, args)
+        };
+
+        let (command, rest) = args
+            .split_first()
+            .ok_or_else(|| err_msg("No adb command given"))?;
+
+        let stdout = match *command {
+            "devices" => self.client.devices().map(String::into_bytes)?,
+            "shell" => self.client.shell(device_id, &rest.join(" "))?,
+            "backup" => self.backup(device_id, rest)?,
+            "restore" => self.restore(device_id, rest)?,
+            "pull" => self.pull(device_id, rest)?,
+            "push" => self.push(device_id, rest)?,
+            "install-multiple" => self.install_multiple(device_id, rest)?,
+            command => {
+                return Err(Error::from(AdbClientError::UnsupportedCommand {
+                    command: String::from(command),
+                }))
+            }
+        };
+
+        Ok(AdbOutput::new(stdout, Vec::new(), 0))
+    }
+}
+
+/// Runs adb invocations by spawning the `adb` binary found on the path.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProcessRunner;
+
+impl AdbRunner for ProcessRunner {
+    fn run(&self, args: &[&str]) -> Result<AdbOutput, Error> {
+        let output = Command::new("adb")
+            .args(args)
+            .stderr(Stdio::piped())
+            .output()?;
+
+        Ok(AdbOutput::new(
+            output.stdout,
+            output.stderr,
+            output.status.code().unwrap_or(-1),
+        ))
+    }
+}
+
+/// Answers adb invocations with canned output, so code driving adb can be tested
+/// without a device. Every invocation is recorded and can be inspected with `calls`.
+#[derive(Debug, Clone)]
+pub struct ScriptedRunner {
+    responses: Vec<(Vec<String>, AdbOutput)>,
+    calls: Rc<RefCell<Vec<Vec<String>>>>,
+}
+
+impl ScriptedRunner {
+    pub fn new() -> Self {
+        ScriptedRunner {
+            responses: Vec::new(),
+            calls: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn expect(self, args: &[&str], output: AdbOutput) -> Self {
+        let mut responses = self.responses;
+        responses.push((args.iter().map(|arg| arg.to_string()).collect(), output));
+        ScriptedRunner { responses, ..self }
+    }
+
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.borrow().clone()
+    }
+}
+
+impl AdbRunner for ScriptedRunner {
+    fn run(&self, args: &[&str]) -> Result<AdbOutput, Error> {
+        self.calls
+            .borrow_mut()
+            .push(args.iter().map(|arg| arg.to_string()).collect());
+
+        self.responses
+            .iter()
+            .find(|&&(ref expected, _)| expected.iter().map(String::as_str).eq(args.iter().cloned()))
+            .map(|&(_, ref output)| output.clone())
+            .ok_or_else(|| err_msg(format!("unexpected adb invocation: adb {}", args.join(" "))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use adb_runner::{AdbOutput, AdbRunner, ScriptedRunner};
+
+    #[test]
+    fn test_scripted_runner_answers_expected_invocation() {
+        let runner = ScriptedRunner::new()
+            .expect(&["devices", "-l"], AdbOutput::stdout("List of devices attached\n\n"))
+            .expect(&["-s", "emulator-5554", "restore", "x.ab"], AdbOutput::error(1, "failed"));
+
+        assert_that!(
+            runner.run(&["devices", "-l"]).unwrap(),
+            is(equal_to(AdbOutput::stdout("List of devices attached\n\n")))
+        );
+        assert!(!runner.run(&["-s", "emulator-5554", "restore", "x.ab"]).unwrap().is_success());
+        assert_that!(runner.calls().len(), is(equal_to(2)));
+    }
+
+    #[test]
+    fn test_scripted_runner_rejects_unexpected_invocation() {
+        let runner = ScriptedRunner::new();
+
+        assert_that!(
+            format!("{}", runner.run(&["shell", "reboot"]).unwrap_err()),
+            is(equal_to(String::from("unexpected adb invocation: adb shell reboot")))
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
+
+use failure::Error;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::{Aes256, BlockCipher, NewBlockCipher};
+use android_backup::header::{read_header_line, HeaderError};
+use hex;
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+
+pub static PBKDF2_ROUNDS: u32 = 10000;
+
+static SALT_SIZE: usize = 64;
+static KEY_SIZE: usize = 32;
+static BLOCK_SIZE: usize = 16;
+
+#[derive(Debug, Fail)]
+pub enum EncryptionError {
+    #[fail(display = "backup is encrypted, the password entered on the device is required")]
+    PasswordRequired,
+
+    #[fail(display = "wrong password for encrypted backup")]
+    WrongPassword,
+
+    #[fail(display = "encrypted backup data is truncated or corrupt")]
+    CorruptData,
+}
+
+/// Key material android stores in the header of an `AES-256` encrypted backup.
+///
+/// The payload is encrypted with a random master key, which itself is stored encrypted
+/// with a key derived from the user's password (PBKDF2 with HMAC-SHA1) in
+/// `master_key_blob`, next to a checksum to tell a wrong password apart.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncryptionParameters {
+    pub user_salt: Vec<u8>,
+    pub checksum_salt: Vec<u8>,
+    pub rounds: u32,
+    pub user_iv: Vec<u8>,
+    pub master_key_blob: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct MasterKey {
+    key: Vec<u8>,
+    iv: Vec<u8>,
+}
+
+impl EncryptionParameters {
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<EncryptionParameters, Error> {
+        let user_salt = read_hex_line(reader, "user password salt")?;
+        let checksum_salt = read_hex_line(reader, "master key checksum salt")?;
+
+        let rounds = read_header_line(reader)?;
+        let rounds = rounds.parse::<u32>().map_err(|_| HeaderError::InvalidField {
+            expected: String::from("pbkdf2 rounds"),
+            found: rounds.clone(),
+        })?;
+
+        let user_iv = read_hex_line(reader, "user key iv")?;
+        let master_key_blob = read_hex_line(reader, "master key blob")?;
+
+        Ok(EncryptionParameters {
+            user_salt,
+            checksum_salt,
+            rounds,
+            user_iv,
+            master_key_blob,
+        })
+    }
+
+    pub fn to_header_lines(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            hex::encode_upper(&self.user_salt),
+            hex::encode_upper(&self.checksum_salt),
+            self.rounds,
+            hex::encode_upper(&self.user_iv),
+            hex::encode_upper(&self.master_key_blob)
+        )
+    }
+
+    /// Creates a new random master key and the parameters to unlock it with `password`.
+    pub fn generate(password: &str, version: u32) -> (EncryptionParameters, MasterKey) {
+        let user_salt = random_bytes(SALT_SIZE);
+        let checksum_salt = random_bytes(SALT_SIZE);
+        let user_iv = random_bytes(BLOCK_SIZE);
+
+        let master_key = MasterKey {
+            key: random_bytes(KEY_SIZE),
+            iv: random_bytes(BLOCK_SIZE),
+        };
+        let checksum = master_key_checksum(&master_key.key, &checksum_salt, PBKDF2_ROUNDS, version);
+
+        let mut blob = Vec::new();
+        blob.push(master_key.iv.len() as u8);
+        blob.extend_from_slice(&master_key.iv);
+        blob.push(master_key.key.len() as u8);
+        blob.extend_from_slice(&master_key.key);
+        blob.push(checksum.len() as u8);
+        blob.extend_from_slice(&checksum);
+
+        let user_key = derive_key(&password_bytes(password, version), &user_salt, PBKDF2_ROUNDS);
+        let mut writer = EncryptingWriter::new(Vec::new(), &user_key, &user_iv);
+        writer
+            .write_all(&blob)
+            .expect("writing into memory does not fail");
+        let master_key_blob = writer.finish().expect("writing into memory does not fail");
+
+        let parameters = EncryptionParameters {
+            user_salt,
+            checksum_salt,
+            rounds: PBKDF2_ROUNDS,
+            user_iv,
+            master_key_blob,
+        };
+
+        (parameters, master_key)
+    }
+
+    /// Decrypts the master key with `password`.
+    pub fn unlock(&self, password: &str, version: u32) -> Result<MasterKey, Error> {
+        let user_key = derive_key(&password_bytes(password, version), &self.user_salt, self.rounds);
+
+        let mut blob = Vec::new();
+        DecryptingReader::new(&self.master_key_blob[..], &user_key, &self.user_iv)
+            .read_to_end(&mut blob)
+            .map_err(|_| EncryptionError::WrongPassword)?;
+
+        let mut fields = Vec::new();
+        let mut rest = &blob[..];
+        while let Some((&length, tail)) = rest.split_first() {
+            let length = length as usize;
+            if tail.len() < length {
+                return Err(Error::from(EncryptionError::WrongPassword));
+            }
+            fields.push(&tail[..length]);
+            rest = &tail[length..];
+        }
+
+        match fields.as_slice() {
+            &[iv, key, checksum]
+                if master_key_checksum(key, &self.checksum_salt, self.rounds, version)
+                    == checksum =>
+            {
+                Ok(MasterKey {
+                    key: key.to_vec(),
+                    iv: iv.to_vec(),
+                })
+            }
+            _ => Err(Error::from(EncryptionError::WrongPassword)),
+        }
+    }
+}
+
+impl MasterKey {
+    pub fn decrypt<R: Read>(&self, reader: R) -> DecryptingReader<R> {
+        DecryptingReader::new(reader, &self.key, &self.iv)
+    }
+
+    pub fn encrypt<W: Write>(&self, writer: W) -> EncryptingWriter<W> {
+        EncryptingWriter::new(writer, &self.key, &self.iv)
+    }
+}
+
+/// Decrypts an AES-256-CBC stream with PKCS#7 padding while reading.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: Aes256,
+    previous: Vec<u8>,
+    ciphertext: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, key: &[u8], iv: &[u8]) -> Self {
+        DecryptingReader {
+            inner,
+            cipher: Aes256::new(GenericArray::from_slice(key)),
+            previous: iv.to_vec(),
+            ciphertext: Vec::new(),
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    fn fill_plaintext(&mut self) -> io::Result<()> {
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        while self.position >= self.plaintext.len() && !self.finished {
+            let read = self.inner.read(&mut buffer)?;
+            self.ciphertext.extend_from_slice(&buffer[..read]);
+
+            // the last block is held back until the end of the stream, it carries the padding
+            let available = match read {
+                0 => self.ciphertext.len(),
+                _ => self.ciphertext.len().saturating_sub(1) / BLOCK_SIZE * BLOCK_SIZE,
+            };
+
+            if read == 0 && (available == 0 || available % BLOCK_SIZE != 0) {
+                return Err(corrupt_data());
+            }
+
+            let mut blocks = self.ciphertext.drain(..available).collect::<Vec<u8>>();
+            for block in blocks.chunks_mut(BLOCK_SIZE) {
+                let ciphertext = block.to_vec();
+                self.cipher.decrypt_block(GenericArray::from_mut_slice(block));
+                xor_block(block, &self.previous);
+                self.previous = ciphertext;
+            }
+
+            if read == 0 {
+                let padding = *blocks.last().unwrap_or(&0) as usize;
+                if padding == 0
+                    || padding > BLOCK_SIZE
+                    || blocks[blocks.len() - padding..]
+                        .iter()
+                        .any(|byte| *byte as usize != padding)
+                {
+                    return Err(corrupt_data());
+                }
+
+                let length = blocks.len() - padding;
+                blocks.truncate(length);
+                self.finished = true;
+            }
+
+            self.plaintext = blocks;
+            self.position = 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_plaintext()?;
+
+        let length = cmp::min(buf.len(), self.plaintext.len() - self.position);
+        buf[..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+/// Encrypts into an AES-256-CBC stream while writing, `finish` adds the PKCS#7 padding.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: Aes256,
+    previous: Vec<u8>,
+    plaintext: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(inner: W, key: &[u8], iv: &[u8]) -> Self {
+        EncryptingWriter {
+            inner,
+            cipher: Aes256::new(GenericArray::from_slice(key)),
+            previous: iv.to_vec(),
+            plaintext: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let padding = BLOCK_SIZE - self.plaintext.len() % BLOCK_SIZE;
+        self.plaintext.extend(vec![padding as u8; padding]);
+        self.encrypt_blocks()?;
+
+        Ok(self.inner)
+    }
+
+    fn encrypt_blocks(&mut self) -> io::Result<()> {
+        let available = self.plaintext.len() / BLOCK_SIZE * BLOCK_SIZE;
+
+        let mut blocks = self.plaintext.drain(..available).collect::<Vec<u8>>();
+        for block in blocks.chunks_mut(BLOCK_SIZE) {
+            xor_block(block, &self.previous);
+            self.cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            self.previous = block.to_vec();
+        }
+
+        self.inner.write_all(&blocks)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.plaintext.extend_from_slice(buf);
+        self.encrypt_blocks()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn derive_key(password: &[u8], salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut key = vec![0u8; KEY_SIZE];
+    pbkdf2::<Hmac<Sha1>>(password, salt, rounds, &mut key);
+    key
+}
+
+/// Android derives the checksum from the master key bytes converted to java chars, where
+/// every byte >= 0x80 turns into a sign extended char (e.g. 0x80 -> U+FF80). Version 1
+/// backups hash the low byte of each char, which is the raw key; later versions hash the
+/// chars UTF-8 encoded.
+fn master_key_checksum(key: &[u8], salt: &[u8], rounds: u32, version: u32) -> Vec<u8> {
+    match version {
+        1 => derive_key(key, salt, rounds),
+        _ => {
+            let key_as_chars = key
+                .iter()
+                .map(|byte| match *byte {
+                    byte if byte < 0x80 => byte as char,
+                    byte => ::std::char::from_u32(0xff00 | u32::from(byte)).unwrap_or('\u{fffd}'),
+                })
+                .collect::<String>();
+
+            derive_key(key_as_chars.as_bytes(), salt, rounds)
+        }
+    }
+}
+
+/// The password as version 1 backups hash it, the low byte of each java char, and UTF-8
+/// encoded for later versions.
+fn password_bytes(password: &str, version: u32) -> Vec<u8> {
+    match version {
+        1 => password.encode_utf16().map(|char| char as u8).collect(),
+        _ => password.as_bytes().to_vec(),
+    }
+}
+
+fn read_hex_line<R: BufRead>(reader: &mut R, field: &str) -> Result<Vec<u8>, Error> {
+    let line = read_header_line(reader)?;
+
+    hex::decode(&line).map_err(|_| {
+        Error::from(HeaderError::InvalidField {
+            expected: String::from(field),
+            found: line.clone(),
+        })
+    })
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn xor_block(block: &mut [u8], other: &[u8]) {
+    block
+        .iter_mut()
+        .zip(other.iter())
+        .for_each(|(byte, other)| *byte ^= *other);
+}
+
+fn corrupt_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, EncryptionError::CorruptData.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::encryption::{
+        derive_key, master_key_checksum, password_bytes, DecryptingReader, EncryptingWriter,
+        EncryptionParameters,
+    };
+    use hex;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_pbkdf2_hmac_sha1() {
+        // test vector from RFC 6070
+        let key = derive_key(b"password", b"salt", 4096);
+
+        assert_that!(
+            hex::encode(&key[..20]),
+            is(equal_to(String::from("4b007901b765489abead49d926f721d065a429c1")))
+        );
+    }
+
+    #[test]
+    fn test_aes_256_cbc() {
+        // test vector from NIST SP 800-38A, F.2.5
+        let key = hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+            .unwrap();
+        let iv = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let plaintext = hex::decode("6bc1bee22e409f96e93d7e117393172a").unwrap();
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &key, &iv);
+        writer.write_all(&plaintext).unwrap();
+        let ciphertext = writer.finish().unwrap();
+
+        assert_that!(
+            hex::encode(&ciphertext[..16]),
+            is(equal_to(String::from("f58c4c04d6e5f1ba779eabfb5f7bfbd6")))
+        );
+        assert_that!(ciphertext.len(), is(equal_to(32)));
+
+        let mut decrypted = Vec::new();
+        DecryptingReader::new(&ciphertext[..], &key, &iv)
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_that!(decrypted, is(equal_to(plaintext)));
+    }
+
+    #[test]
+    fn test_truncated_ciphertext() {
+        let key = [7u8; 32];
+        let iv = [3u8; 16];
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &key, &iv);
+        writer.write_all(&[1u8; 100]).unwrap();
+        let ciphertext = writer.finish().unwrap();
+
+        let mut decrypted = Vec::new();
+        assert!(
+            DecryptingReader::new(&ciphertext[..ciphertext.len() - 5], &key, &iv)
+                .read_to_end(&mut decrypted)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_master_key_checksum_of_version_one() {
+        // version 1 hashes the raw bytes, later versions sign extend bytes >= 0x80 to
+        // U+FF80..U+FFFF and hash them as three UTF-8 bytes
+        let salt = b"salt";
+
+        assert_that!(
+            master_key_checksum(&[0x41, 0x80], salt, 1, 1),
+            is(equal_to(derive_key(&[0x41, 0x80], salt, 1)))
+        );
+        assert_that!(
+            master_key_checksum(&[0x41, 0x80], salt, 1, 5),
+            is(equal_to(derive_key(&[0x41, 0xef, 0xbe, 0x80], salt, 1)))
+        );
+    }
+
+    #[test]
+    fn test_password_bytes() {
+        assert_that!(password_bytes("pässword", 1), is(equal_to(b"p\xe4ssword".to_vec())));
+        assert_that!(password_bytes("pässword", 5), is(equal_to("pässword".as_bytes().to_vec())));
+    }
+
+    #[test]
+    fn test_unlock_master_key() {
+        let (parameters, master_key) = EncryptionParameters::generate("secret", 5);
+
+        let mut header = parameters.to_header_lines().into_bytes();
+        header.extend_from_slice(b"payload");
+        let mut header = &header[..];
+        let read_parameters = EncryptionParameters::read(&mut header).unwrap();
+        assert_that!(read_parameters.clone(), is(equal_to(parameters)));
+
+        let unlocked = read_parameters.unlock("secret", 5).unwrap();
+        assert_that!(unlocked.key, is(equal_to(master_key.key)));
+        assert_that!(unlocked.iv, is(equal_to(master_key.iv)));
+
+        assert_that!(
+            format!("{}", read_parameters.unlock("wrong", 5).err().unwrap()),
+            is(equal_to(String::from("wrong password for encrypted backup")))
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+
+/// Package android files the shared storage (`shared/...`) under.
+pub static SHARED_STORAGE_PACKAGE: &str = "com.android.sharedstoragebackup";
+
+/// Part of an app's data an archive entry belongs to, named after the directory token
+/// android uses in the backup tar (`apps/<package>/<token>/...`).
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+pub enum Domain {
+    Manifest,
+    Metadata,
+    Apk,
+    Obb,
+    Root,
+    Files,
+    Database,
+    SharedPreferences,
+    Cache,
+    NoBackup,
+    KeyValue,
+    ExternalFiles,
+    SharedStorage,
+    Other(String),
+}
+
+impl Domain {
+    pub fn from_token(token: &str) -> Domain {
+        match token {
+            "_manifest" => Domain::Manifest,
+            "_meta" => Domain::Metadata,
+            "a" => Domain::Apk,
+            "obb" => Domain::Obb,
+            "r" => Domain::Root,
+            "f" => Domain::Files,
+            "db" => Domain::Database,
+            "sp" => Domain::SharedPreferences,
+            "c" => Domain::Cache,
+            "nb" => Domain::NoBackup,
+            "k" => Domain::KeyValue,
+            "ef" => Domain::ExternalFiles,
+            "shared" => Domain::SharedStorage,
+            other => Domain::Other(String::from(other)),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        match *self {
+            Domain::Manifest => "_manifest",
+            Domain::Metadata => "_meta",
+            Domain::Apk => "a",
+            Domain::Obb => "obb",
+            Domain::Root => "r",
+            Domain::Files => "f",
+            Domain::Database => "db",
+            Domain::SharedPreferences => "sp",
+            Domain::Cache => "c",
+            Domain::NoBackup => "nb",
+            Domain::KeyValue => "k",
+            Domain::ExternalFiles => "ef",
+            Domain::SharedStorage => "shared",
+            Domain::Other(ref other) => other,
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BackupEntry {
+    pub package: String,
+    pub domain: Domain,
+    pub path: String,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+}
+
+impl BackupEntry {
+    /// Splits a path inside the backup tar into package, domain and the path
+    /// relative to the domain.
+    pub fn split_archive_path(archive_path: &str) -> (String, Domain, String) {
+        let mut components = archive_path.trim_matches('/').splitn(4, '/');
+
+        match (components.next(), components.next(), components.next(), components.next()) {
+            (Some("apps"), Some(package), Some(domain), path) => (
+                String::from(package),
+                Domain::from_token(domain),
+                String::from(path.unwrap_or("")),
+            ),
+            (Some("shared"), first, second, rest) => {
+                let path = vec![first, second, rest]
+                    .into_iter()
+                    .filter_map(|component| component)
+                    .collect::<Vec<&str>>()
+                    .join("/");
+
+                (String::from(SHARED_STORAGE_PACKAGE), Domain::SharedStorage, path)
+            }
+            _ => (
+                String::new(),
+                Domain::Other(String::new()),
+                String::from(archive_path),
+            ),
+        }
+    }
+
+    pub fn archive_path(&self) -> String {
+        match self.domain {
+            Domain::SharedStorage => format!("shared/{}", self.path),
+            _ if self.path.is_empty() => format!("apps/{}/{}", self.package, self.domain),
+            _ => format!("apps/{}/{}/{}", self.package, self.domain, self.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::entry::{BackupEntry, Domain, SHARED_STORAGE_PACKAGE};
+
+    #[test]
+    fn test_split_archive_path() {
+        assert_that!(
+            BackupEntry::split_archive_path("apps/org.cryptomator/_manifest"),
+            is(equal_to((
+                String::from("org.cryptomator"),
+                Domain::Manifest,
+                String::new()
+            )))
+        );
+        assert_that!(
+            BackupEntry::split_archive_path("apps/org.cryptomator/sp/settings.xml"),
+            is(equal_to((
+                String::from("org.cryptomator"),
+                Domain::SharedPreferences,
+                String::from("settings.xml")
+            )))
+        );
+        assert_that!(
+            BackupEntry::split_archive_path("apps/com.dropbox.android/ef/files/cache/x.bin"),
+            is(equal_to((
+                String::from("com.dropbox.android"),
+                Domain::ExternalFiles,
+                String::from("files/cache/x.bin")
+            )))
+        );
+        assert_that!(
+            BackupEntry::split_archive_path("shared/0/DCIM/Camera/IMG_0001.jpg"),
+            is(equal_to((
+                String::from(SHARED_STORAGE_PACKAGE),
+                Domain::SharedStorage,
+                String::from("0/DCIM/Camera/IMG_0001.jpg")
+            )))
+        );
+    }
+
+    #[test]
+    fn test_archive_path_round_trip() {
+        let archive_paths = vec![
+            "apps/org.cryptomator/_manifest",
+            "apps/org.cryptomator/db/vaults.db",
+            "shared/0/Download/notes.txt",
+        ];
+
+        archive_paths.into_iter().for_each(|archive_path| {
+            let (package, domain, path) = BackupEntry::split_archive_path(archive_path);
+            let entry = BackupEntry {
+                package,
+                domain,
+                path,
+                size: 0,
+                mode: 0o600,
+                mtime: 0,
+            };
+
+            assert_that!(entry.archive_path(), is(equal_to(String::from(archive_path))));
+        });
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::BufRead;
+
+use failure::Error;
+
+use android_backup::encryption::EncryptionParameters;
+
+pub static BACKUP_MAGIC: &str = "ANDROID BACKUP";
+pub static BACKUP_FILE_VERSION: u32 = 5;
+
+#[derive(Debug, Fail)]
+pub enum HeaderError {
+    #[fail(display = "not an android backup, header starts with '{}'", magic)]
+    InvalidMagic {
+        magic: String,
+    },
+
+    #[fail(display = "invalid backup header, expected {} but found '{}'", expected, found)]
+    InvalidField {
+        expected: String,
+        found: String,
+    },
+
+    #[fail(display = "unsupported backup format version: {}", version)]
+    UnsupportedVersion {
+        version: u32,
+    },
+
+    #[fail(display = "unsupported backup encryption: {}", encryption)]
+    UnsupportedEncryption {
+        encryption: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Encryption {
+    None,
+    Aes256(EncryptionParameters),
+}
+
+/// The plain text header in front of every `.ab` file, e.g.
+/// `ANDROID BACKUP\n5\n1\nnone\n`, followed by the key material if encrypted.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BackupHeader {
+    pub version: u32,
+    pub compressed: bool,
+    pub encryption: Encryption,
+}
+
+impl BackupHeader {
+    pub fn new(compressed: bool) -> Self {
+        BackupHeader {
+            version: BACKUP_FILE_VERSION,
+            compressed,
+            encryption: Encryption::None,
+        }
+    }
+
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<BackupHeader, Error> {
+        let magic = read_header_line(reader)?;
+        if magic != BACKUP_MAGIC {
+            return Err(Error::from(HeaderError::InvalidMagic { magic }));
+        }
+
+        let version = read_header_line(reader)?;
+        let version = version.parse::<u32>().map_err(|_| HeaderError::InvalidField {
+            expected: String::from("format version"),
+            found: version.clone(),
+        })?;
+        if version < 1 || version > BACKUP_FILE_VERSION {
+            return Err(Error::from(HeaderError::UnsupportedVersion { version }));
+        }
+
+        let compressed = match read_header_line(reader)?.as_str() {
+            "0" => false,
+            "1" => true,
+            found => {
+                return Err(Error::from(HeaderError::InvalidField {
+                    expected: String::from("compression flag"),
+                    found: String::from(found),
+                }))
+            }
+        };
+
+        let encryption = match read_header_line(reader)?.as_str() {
+            "none" => Encryption::None,
+            "AES-256" => Encryption::Aes256(EncryptionParameters::read(reader)?),
+            encryption => {
+                return Err(Error::from(HeaderError::UnsupportedEncryption {
+                    encryption: String::from(encryption),
+                }))
+            }
+        };
+
+        Ok(BackupHeader {
+            version,
+            compressed,
+            encryption,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let encryption = match self.encryption {
+            Encryption::None => String::from("none\n"),
+            Encryption::Aes256(ref parameters) => {
+                format!("AES-256\n{}", parameters.to_header_lines())
+            }
+        };
+
+        format!(
+            "{}\n{}\n{}\n{}",
+            BACKUP_MAGIC,
+            self.version,
+            if self.compressed { 1 } else { 0 },
+            encryption
+        ).into_bytes()
+    }
+}
+
+pub fn read_header_line<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+
+    if line.last() != Some(&b'\n') {
+        return Err(Error::from(HeaderError::InvalidField {
+            expected: String::from("header line"),
+            found: String::from("end of file"),
+        }));
+    }
+    line.pop();
+
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::header::{BackupHeader, Encryption};
+
+    #[test]
+    fn test_read_header() {
+        let mut mocked_header = &b"ANDROID BACKUP\n5\n1\nnone\nx\x9c"[..];
+
+        let header = BackupHeader::read(&mut mocked_header).unwrap();
+
+        assert_that!(header, is(equal_to(BackupHeader {
+            version: 5,
+            compressed: true,
+            encryption: Encryption::None,
+        })));
+        assert_eq!(mocked_header, &b"x\x9c"[..]);
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = BackupHeader::new(false);
+
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+        assert_that!(BackupHeader::read(&mut &bytes[..]).unwrap(), is(equal_to(header)));
+    }
+
+    #[test]
+    fn test_read_invalid_header() {
+        let mut not_a_backup = &b"PK\x03\x04\n"[..];
+        assert_that!(
+            format!("{}", BackupHeader::read(&mut not_a_backup).unwrap_err()),
+            is(equal_to(String::from("not an android backup, header starts with 'PK\u{3}\u{4}'")))
+        );
+
+        let mut truncated = &b"ANDROID BACKUP\n5\n"[..];
+        assert!(BackupHeader::read(&mut truncated).is_err());
+
+        let mut future_version = &b"ANDROID BACKUP\n6\n1\nnone\n"[..];
+        assert_that!(
+            format!("{}", BackupHeader::read(&mut future_version).unwrap_err()),
+            is(equal_to(String::from("unsupported backup format version: 6")))
+        );
+    }
+}
@@ -0,0 +1,6 @@
+pub mod encryption;
+pub mod entry;
+pub mod header;
+pub mod reader;
+pub mod summary;
+pub mod writer;
@@ -0,0 +1,273 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use failure::Error;
+
+use android_backup::encryption::EncryptionError;
+use android_backup::entry::BackupEntry;
+use flate2::read::ZlibDecoder;
+use android_backup::header::{BackupHeader, Encryption};
+use tar;
+
+/// Reads an android backup (`.ab`): the header, followed by an optionally encrypted and
+/// zlib compressed tar archive with the backed up files.
+pub struct BackupReader<'a> {
+    header: BackupHeader,
+    archive: tar::Archive<Box<dyn Read + 'a>>,
+}
+
+impl<'a> BackupReader<'a> {
+    pub fn new<R: Read + 'a>(reader: R) -> Result<BackupReader<'a>, Error> {
+        BackupReader::with_password(reader, None)
+    }
+
+    /// Reads a backup which may be encrypted with `password`, the password is ignored for
+    /// unencrypted backups.
+    pub fn with_password<R: Read + 'a>(
+        reader: R,
+        password: Option<&str>,
+    ) -> Result<BackupReader<'a>, Error> {
+        let mut reader = BufReader::new(reader);
+        let header = BackupHeader::read(&mut reader)?;
+
+        let decrypted: Box<dyn Read + 'a> = match (&header.encryption, password) {
+            (&Encryption::None, _) => Box::new(reader),
+            (&Encryption::Aes256(ref parameters), Some(password)) => {
+                let master_key = parameters.unlock(password, header.version)?;
+                Box::new(master_key.decrypt(reader))
+            }
+            (&Encryption::Aes256(_), None) => {
+                return Err(Error::from(EncryptionError::PasswordRequired))
+            }
+        };
+
+        let payload: Box<dyn Read + 'a> = match header.compressed {
+            true => Box::new(ZlibDecoder::new(decrypted)),
+            false => decrypted,
+        };
+
+        Ok(BackupReader {
+            header,
+            archive: tar::Archive::new(payload),
+        })
+    }
+
+    pub fn header(&self) -> &BackupHeader {
+        &self.header
+    }
+
+    pub fn entries<'s>(&'s mut self) -> Result<BackupEntries<'s, 'a>, Error> {
+        Ok(BackupEntries {
+            entries: self.archive.entries()?,
+        })
+    }
+
+    /// The tar archive inside the backup, for callers that need the file contents and
+    /// not only the entry metadata.
+    pub fn archive(&mut self) -> &mut tar::Archive<Box<dyn Read + 'a>> {
+        &mut self.archive
+    }
+
+    /// Extracts all entries below `directory`, keeping the layout of the backup
+    /// (`apps/<package>/<domain>/...`). Returns the number of extracted files.
+    pub fn unpack(&mut self, directory: &Path) -> Result<usize, Error> {
+        fs::create_dir_all(directory)?;
+
+        let mut extracted = 0;
+        for entry in self.archive.entries()? {
+            let mut entry = entry?;
+
+            // unpack_in refuses paths escaping the directory, e.g. with `..`
+            if entry.unpack_in(directory)? && entry.header().entry_type().is_file() {
+                extracted += 1;
+            }
+        }
+
+        Ok(extracted)
+    }
+
+    /// The decrypted and decompressed payload, a plain tar stream.
+    pub fn into_tar(self) -> Box<dyn Read + 'a> {
+        self.archive.into_inner()
+    }
+}
+
+impl BackupReader<'static> {
+    pub fn open(path: &Path, password: Option<&str>) -> Result<BackupReader<'static>, Error> {
+        BackupReader::with_password(File::open(path)?, password)
+    }
+}
+
+pub struct BackupEntries<'a, 'b: 'a> {
+    entries: tar::Entries<'a, Box<dyn Read + 'b>>,
+}
+
+impl<'a, 'b> Iterator for BackupEntries<'a, 'b> {
+    type Item = Result<BackupEntry, Error>;
+
+    fn next(&mut self) -> Option<Result<BackupEntry, Error>> {
+        self.entries
+            .next()
+            .map(|entry| entry.map_err(Error::from).and_then(|entry| to_backup_entry(&entry)))
+    }
+}
+
+pub fn to_backup_entry<R: Read>(entry: &tar::Entry<R>) -> Result<BackupEntry, Error> {
+    let archive_path = entry.path()?.to_string_lossy().to_string();
+    let (package, domain, path) = BackupEntry::split_archive_path(&archive_path);
+
+    let header = entry.header();
+
+    Ok(BackupEntry {
+        package,
+        domain,
+        path,
+        size: header.size()?,
+        mode: header.mode()?,
+        mtime: header.mtime()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::entry::Domain;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use android_backup::reader::BackupReader;
+    use tar;
+    use std::fs::{remove_dir_all, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    fn mocked_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let files: Vec<(&str, &[u8])> = vec![
+            ("apps/org.cryptomator/_manifest", b"1\norg.cryptomator\n"),
+            ("apps/org.cryptomator/sp/settings.xml", b"<map />"),
+            ("shared/0/Download/notes.txt", b"notes"),
+        ];
+
+        files.into_iter().for_each(|(path, data)| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_mtime(1_530_000_000);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        });
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_read_uncompressed_backup() {
+        let mut backup = b"ANDROID BACKUP\n5\n0\nnone\n".to_vec();
+        backup.extend(mocked_tar());
+
+        let mut reader = BackupReader::new(&backup[..]).unwrap();
+        assert!(!reader.header().compressed);
+
+        let entries = reader
+            .entries()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_that!(entries.len(), is(equal_to(3)));
+        assert_that!(entries[1].package.clone(), is(equal_to(String::from("org.cryptomator"))));
+        assert_that!(entries[1].domain.clone(), is(equal_to(Domain::SharedPreferences)));
+        assert_that!(entries[1].path.clone(), is(equal_to(String::from("settings.xml"))));
+        assert_that!(entries[1].size, is(equal_to(7)));
+        assert_that!(entries[1].mode, is(equal_to(0o600)));
+        assert_that!(entries[1].mtime, is(equal_to(1_530_000_000)));
+        assert_that!(entries[2].domain.clone(), is(equal_to(Domain::SharedStorage)));
+    }
+
+    #[test]
+    fn test_read_compressed_backup() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&mocked_tar()).unwrap();
+
+        let mut backup = b"ANDROID BACKUP\n5\n1\nnone\n".to_vec();
+        backup.extend(encoder.finish().unwrap());
+
+        let mut reader = BackupReader::new(&backup[..]).unwrap();
+        assert!(reader.header().compressed);
+
+        let packages = reader
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().package)
+            .collect::<Vec<String>>();
+
+        assert_that!(
+            packages,
+            is(equal_to(vec![
+                String::from("org.cryptomator"),
+                String::from("org.cryptomator"),
+                String::from("com.android.sharedstoragebackup"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_unpack_and_convert_to_tar() {
+        let temp_dir = "74d598b02b4b39e2352fb037dedd5d34"; // md5 of 'test_unpack_and_convert_to_tar'
+
+        let mut backup = b"ANDROID BACKUP\n5\n0\nnone\n".to_vec();
+        backup.extend(mocked_tar());
+
+        let mut reader = BackupReader::new(&backup[..]).unwrap();
+        assert_that!(reader.unpack(Path::new(temp_dir)).unwrap(), is(equal_to(3)));
+
+        let mut settings = String::new();
+        File::open(Path::new(temp_dir).join("apps/org.cryptomator/sp/settings.xml"))
+            .unwrap()
+            .read_to_string(&mut settings)
+            .unwrap();
+        assert_that!(settings, is(equal_to(String::from("<map />"))));
+        assert!(remove_dir_all(temp_dir).is_ok());
+
+        let mut tar = Vec::new();
+        BackupReader::new(&backup[..])
+            .unwrap()
+            .into_tar()
+            .read_to_end(&mut tar)
+            .unwrap();
+        assert_that!(tar, is(equal_to(mocked_tar())));
+    }
+
+    #[test]
+    fn test_read_encrypted_backup() {
+        let fixture = Path::new("tests/test_backups/encrypted_v5.ab");
+
+        assert_that!(
+            format!("{}", BackupReader::open(fixture, None).err().unwrap()),
+            is(equal_to(String::from(
+                "backup is encrypted, the password entered on the device is required"
+            )))
+        );
+        assert_that!(
+            format!("{}", BackupReader::open(fixture, Some("wrong")).err().unwrap()),
+            is(equal_to(String::from("wrong password for encrypted backup")))
+        );
+
+        let mut reader = BackupReader::open(fixture, Some("adbackup")).unwrap();
+        let archive_paths = reader
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().archive_path())
+            .collect::<Vec<String>>();
+
+        assert_that!(
+            archive_paths,
+            is(equal_to(vec![
+                String::from("apps/org.cryptomator/_manifest"),
+                String::from("apps/org.cryptomator/sp/settings.xml"),
+            ]))
+        );
+    }
+}
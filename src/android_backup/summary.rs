@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use failure::Error;
+
+use android_backup::entry::Domain;
+use android_backup::reader::{to_backup_entry, BackupReader};
+
+/// Files and bytes a backup holds for one package.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PackageSummary {
+    pub package: String,
+    pub files: usize,
+    pub size: u64,
+    pub domains: Vec<Domain>,
+}
+
+/// Overview of the contents of a backup, one summary per package sorted by name.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct BackupSummary {
+    pub packages: Vec<PackageSummary>,
+}
+
+impl BackupSummary {
+    /// Reads all remaining entries of `reader`, only regular files are counted.
+    pub fn read(reader: &mut BackupReader) -> Result<BackupSummary, Error> {
+        let mut packages: BTreeMap<String, PackageSummary> = BTreeMap::new();
+
+        for entry in reader.archive().entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let entry = to_backup_entry(&entry)?;
+            let summary = packages
+                .entry(entry.package.clone())
+                .or_insert_with(|| PackageSummary {
+                    package: entry.package.clone(),
+                    files: 0,
+                    size: 0,
+                    domains: Vec::new(),
+                });
+
+            summary.files += 1;
+            summary.size += entry.size;
+            if !summary.domains.contains(&entry.domain) {
+                summary.domains.push(entry.domain);
+                summary.domains.sort();
+            }
+        }
+
+        Ok(BackupSummary {
+            packages: packages.into_iter().map(|(_, summary)| summary).collect(),
+        })
+    }
+
+    pub fn files(&self) -> usize {
+        self.packages.iter().map(|package| package.files).sum()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.packages.iter().map(|package| package.size).sum()
+    }
+
+    /// Whether the backup was made with `-apk`, i.e. contains the apk of any package.
+    pub fn includes_apks(&self) -> bool {
+        self.includes(&Domain::Apk)
+    }
+
+    /// Whether the backup was made with `-shared`, i.e. contains the shared storage.
+    pub fn includes_shared_storage(&self) -> bool {
+        self.includes(&Domain::SharedStorage)
+    }
+
+    fn includes(&self, domain: &Domain) -> bool {
+        self.packages
+            .iter()
+            .any(|package| package.domains.contains(domain))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::entry::Domain;
+    use android_backup::reader::BackupReader;
+    use android_backup::summary::BackupSummary;
+    use tar;
+    use std::path::Path;
+
+    #[test]
+    fn test_summarize_backup() {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let files: Vec<(&str, &[u8])> = vec![
+            ("apps/org.cryptomator/_manifest", b"1\norg.cryptomator\n"),
+            ("apps/org.cryptomator/a/base.apk", b"PK"),
+            ("apps/org.cryptomator/sp/settings.xml", b"<map />"),
+            ("apps/com.dropbox.android/_manifest", b"1\ncom.dropbox.android\n"),
+            ("shared/0/Download/notes.txt", b"notes"),
+        ];
+
+        files.into_iter().for_each(|(path, data)| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        });
+
+        let mut backup = b"ANDROID BACKUP\n5\n0\nnone\n".to_vec();
+        backup.extend(builder.into_inner().unwrap());
+
+        let summary = BackupSummary::read(&mut BackupReader::new(&backup[..]).unwrap()).unwrap();
+
+        let packages = summary
+            .packages
+            .iter()
+            .map(|package| (package.package.as_str(), package.files))
+            .collect::<Vec<(&str, usize)>>();
+        assert_that!(
+            packages,
+            is(equal_to(vec![
+                ("com.android.sharedstoragebackup", 1),
+                ("com.dropbox.android", 1),
+                ("org.cryptomator", 3),
+            ]))
+        );
+        assert_that!(
+            summary.packages[2].domains.clone(),
+            is(equal_to(vec![Domain::Manifest, Domain::Apk, Domain::SharedPreferences]))
+        );
+        assert_that!(summary.files(), is(equal_to(5)));
+        assert_that!(summary.size(), is(equal_to(54)));
+        assert!(summary.includes_apks());
+        assert!(summary.includes_shared_storage());
+    }
+
+    #[test]
+    fn test_summarize_encrypted_backup() {
+        let fixture = Path::new("tests/test_backups/encrypted_v5.ab");
+        let mut reader = BackupReader::open(fixture, Some("adbackup")).unwrap();
+
+        let summary = BackupSummary::read(&mut reader).unwrap();
+
+        assert_that!(summary.packages.len(), is(equal_to(1)));
+        assert_that!(summary.files(), is(equal_to(2)));
+        assert!(!summary.includes_apks());
+        assert!(!summary.includes_shared_storage());
+    }
+}
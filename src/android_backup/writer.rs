@@ -0,0 +1,571 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+
+use android_backup::encryption::{EncryptingWriter, EncryptionError, EncryptionParameters, MasterKey};
+use android_backup::entry::{BackupEntry, Domain, SHARED_STORAGE_PACKAGE};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use android_backup::header::{BackupHeader, Encryption};
+use tar;
+
+#[derive(Debug, Fail)]
+pub enum WriterError {
+    #[fail(display = "package {} has no _manifest, android would not restore its data", package)]
+    MissingManifest {
+        package: String,
+    },
+
+    #[fail(display = "{} is not part of an android backup layout (apps/<package>/... or shared/...)", path)]
+    InvalidLayout {
+        path: String,
+    },
+
+    #[fail(display = "{} is not preceded by the _manifest of package {}, android would not restore it", path, package)]
+    ManifestNotFirst {
+        path: String,
+        package: String,
+    },
+}
+
+enum Sink<W: Write> {
+    Plain(W),
+    Encrypted(EncryptingWriter<W>),
+}
+
+impl<W: Write> Sink<W> {
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Sink::Plain(writer) => Ok(writer),
+            Sink::Encrypted(encrypter) => encrypter.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Sink::Plain(ref mut writer) => writer.write(buf),
+            Sink::Encrypted(ref mut encrypter) => encrypter.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Sink::Plain(ref mut writer) => writer.flush(),
+            Sink::Encrypted(ref mut encrypter) => encrypter.flush(),
+        }
+    }
+}
+
+enum Payload<W: Write> {
+    Plain(Sink<W>),
+    Compressed(ZlibEncoder<Sink<W>>),
+}
+
+impl<W: Write> Payload<W> {
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Payload::Plain(sink) => sink.finish(),
+            Payload::Compressed(encoder) => encoder.finish()?.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Payload<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Payload::Plain(ref mut sink) => sink.write(buf),
+            Payload::Compressed(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Payload::Plain(ref mut sink) => sink.flush(),
+            Payload::Compressed(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+struct PendingEntry {
+    entry: BackupEntry,
+    header: tar::Header,
+    file: PathBuf,
+}
+
+/// Writes an android backup (`.ab`) which can be restored with `adb restore`.
+///
+/// Files of directories are collected first and written ordered the way android expects
+/// them: per package the `_manifest` first, then the apk and then the data domains. Tar
+/// streams are written through as they are read, so they have to be in that order already.
+pub struct BackupWriter<W: Write> {
+    builder: tar::Builder<Payload<W>>,
+    pending: Vec<PendingEntry>,
+    // packages whose _manifest is already written
+    manifests: BTreeSet<String>,
+}
+
+impl<W: Write> BackupWriter<W> {
+    /// Starts an unencrypted backup, use `with_password` for an encrypted one.
+    pub fn new(writer: W, header: &BackupHeader) -> Result<BackupWriter<W>, Error> {
+        match header.encryption {
+            Encryption::None => BackupWriter::with_sink(writer, header, None),
+            Encryption::Aes256(_) => Err(Error::from(EncryptionError::PasswordRequired)),
+        }
+    }
+
+    /// Starts a backup encrypted with `password` like android does it, with a new random
+    /// master key.
+    pub fn with_password(
+        writer: W,
+        compressed: bool,
+        password: &str,
+    ) -> Result<BackupWriter<W>, Error> {
+        let mut header = BackupHeader::new(compressed);
+        let (parameters, master_key) = EncryptionParameters::generate(password, header.version);
+        header.encryption = Encryption::Aes256(parameters);
+
+        BackupWriter::with_sink(writer, &header, Some(master_key))
+    }
+
+    fn with_sink(
+        mut writer: W,
+        header: &BackupHeader,
+        master_key: Option<MasterKey>,
+    ) -> Result<BackupWriter<W>, Error> {
+        writer.write_all(&header.to_bytes())?;
+
+        let sink = match master_key {
+            Some(master_key) => Sink::Encrypted(master_key.encrypt(writer)),
+            None => Sink::Plain(writer),
+        };
+
+        let payload = match header.compressed {
+            true => Payload::Compressed(ZlibEncoder::new(sink, Compression::default())),
+            false => Payload::Plain(sink),
+        };
+
+        let mut builder = tar::Builder::new(payload);
+        builder.mode(tar::HeaderMode::Deterministic);
+
+        Ok(BackupWriter {
+            builder,
+            pending: Vec::new(),
+            manifests: BTreeSet::new(),
+        })
+    }
+
+    /// Adds every file below `directory`, which has to follow the layout of an extracted
+    /// backup (`apps/<package>/_manifest`, `apps/<package>/f/...`, `shared/0/...`).
+    pub fn append_directory(&mut self, directory: &Path) -> Result<(), Error> {
+        let mut files = Vec::new();
+        collect_files(directory, &mut files)?;
+
+        for file in files {
+            let archive_path = file
+                .strip_prefix(directory)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join("/");
+
+            let mut header = tar::Header::new_ustar();
+            header.set_metadata(&fs::metadata(&file)?);
+
+            let entry = to_entry(&archive_path, &header)?;
+            self.pending.push(PendingEntry { entry, header, file });
+        }
+
+        Ok(())
+    }
+
+    /// Writes the regular files of a tar stream as they are read, in the order android
+    /// wrote them: the `_manifest` of a package before its other entries. Files of
+    /// directories appended before are written along with the package they belong to.
+    pub fn append_tar<R: Read>(&mut self, tar: R) -> Result<(), Error> {
+        let mut archive = tar::Archive::new(tar);
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.header().entry_type() != tar::EntryType::Regular {
+                continue;
+            }
+
+            let archive_path = entry.path()?.to_string_lossy().to_string();
+            let mut header = entry.header().clone();
+            let backup_entry = to_entry(&archive_path, &header)?;
+
+            if backup_entry.domain == Domain::Manifest {
+                self.write_entry(&backup_entry, &mut header, &mut entry)?;
+                self.manifests.insert(backup_entry.package.clone());
+                self.write_pending(&backup_entry.package)?;
+                continue;
+            }
+
+            if !self.manifests.contains(&backup_entry.package) {
+                self.write_pending(&backup_entry.package)?;
+            }
+            if !self.manifests.contains(&backup_entry.package) && needs_manifest(&backup_entry) {
+                return Err(Error::from(WriterError::ManifestNotFirst {
+                    path: archive_path,
+                    package: backup_entry.package,
+                }));
+            }
+
+            self.write_entry(&backup_entry, &mut header, &mut entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the collected files which were not written along with a tar stream and
+    /// returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut pending = self.pending.split_off(0);
+        pending.sort_by(compare_entries);
+
+        check_manifests(&pending, &self.manifests)?;
+
+        for PendingEntry { entry, mut header, file } in pending {
+            self.write_entry(&entry, &mut header, File::open(file)?)?;
+        }
+
+        Ok(self.builder.into_inner()?.finish()?)
+    }
+
+    // writes the collected files of `package`, its _manifest first unless one is written
+    fn write_pending(&mut self, package: &str) -> Result<(), Error> {
+        let (mut entries, pending): (Vec<PendingEntry>, Vec<PendingEntry>) = self
+            .pending
+            .split_off(0)
+            .into_iter()
+            .partition(|pending| pending.entry.package == package);
+        self.pending = pending;
+        entries.sort_by(compare_entries);
+
+        for PendingEntry { entry, mut header, file } in entries {
+            if entry.domain == Domain::Manifest && !self.manifests.insert(entry.package.clone()) {
+                continue;
+            }
+
+            self.write_entry(&entry, &mut header, File::open(file)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_entry<R: Read>(
+        &mut self,
+        entry: &BackupEntry,
+        header: &mut tar::Header,
+        data: R,
+    ) -> Result<(), Error> {
+        trace!("Adding {} to backup", entry.archive_path());
+        self.builder.append_data(header, entry.archive_path(), data)?;
+
+        Ok(())
+    }
+}
+
+fn to_entry(archive_path: &str, header: &tar::Header) -> Result<BackupEntry, Error> {
+    let (package, domain, path) = BackupEntry::split_archive_path(archive_path);
+    if package.is_empty() {
+        return Err(Error::from(WriterError::InvalidLayout {
+            path: String::from(archive_path),
+        }));
+    }
+
+    Ok(BackupEntry {
+        package,
+        domain,
+        path,
+        size: header.size()?,
+        mode: header.mode()?,
+        mtime: header.mtime()?,
+    })
+}
+
+fn compare_entries(a: &PendingEntry, b: &PendingEntry) -> Ordering {
+    (&a.entry.package, &a.entry.domain, &a.entry.path).cmp(&(&b.entry.package, &b.entry.domain, &b.entry.path))
+}
+
+// shared storage entries are written by android without a manifest of their own, and
+// apks may be added for apps without data in the backup, android skips a package
+// without manifest
+fn needs_manifest(entry: &BackupEntry) -> bool {
+    entry.package != SHARED_STORAGE_PACKAGE && entry.domain != Domain::Apk
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn check_manifests(pending: &[PendingEntry], written: &BTreeSet<String>) -> Result<(), Error> {
+    let manifests = pending
+        .iter()
+        .filter(|pending| pending.entry.domain == Domain::Manifest)
+        .map(|pending| pending.entry.package.as_str())
+        .chain(written.iter().map(String::as_str))
+        .collect::<BTreeSet<&str>>();
+
+    match pending
+        .iter()
+        .find(|pending| needs_manifest(&pending.entry) && !manifests.contains(pending.entry.package.as_str()))
+    {
+        Some(pending) => Err(Error::from(WriterError::MissingManifest {
+            package: pending.entry.package.clone(),
+        })),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use android_backup::entry::Domain;
+    use android_backup::header::BackupHeader;
+    use android_backup::reader::BackupReader;
+    use tar;
+    use android_backup::writer::BackupWriter;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+    use std::io::Write;
+    use std::path::Path;
+
+    fn read_archive_paths(backup: &[u8]) -> Vec<String> {
+        let mut reader = BackupReader::new(backup).unwrap();
+
+        reader
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().archive_path())
+            .collect()
+    }
+
+    #[test]
+    fn test_write_from_directory() {
+        let temp_dir = "02fa194fe61e3d028f3f185804c200e2"; // md5 of 'test_write_from_directory'
+        let files = vec![
+            "apps/org.cryptomator/sp/settings.xml",
+            "apps/org.cryptomator/f/vaults.json",
+            "apps/org.cryptomator/_manifest",
+            "apps/com.dropbox.android/db/dropbox.db",
+            "apps/com.dropbox.android/a/base.apk",
+            "apps/com.dropbox.android/_manifest",
+        ];
+
+        files.iter().for_each(|file| {
+            let path = Path::new(temp_dir).join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(file.as_bytes()).unwrap();
+        });
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(true)).unwrap();
+        writer.append_directory(Path::new(temp_dir)).unwrap();
+        let backup = writer.finish().unwrap();
+
+        assert_that!(
+            read_archive_paths(&backup),
+            is(equal_to(vec![
+                String::from("apps/com.dropbox.android/_manifest"),
+                String::from("apps/com.dropbox.android/a/base.apk"),
+                String::from("apps/com.dropbox.android/db/dropbox.db"),
+                String::from("apps/org.cryptomator/_manifest"),
+                String::from("apps/org.cryptomator/f/vaults.json"),
+                String::from("apps/org.cryptomator/sp/settings.xml"),
+            ]))
+        );
+
+        assert!(remove_dir_all(temp_dir).is_ok());
+    }
+
+    #[test]
+    fn test_write_from_tar_stream() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let files: Vec<(&str, &[u8])> = vec![
+            ("apps/com.android.sharedstoragebackup/_manifest", b"1\n"),
+            ("shared/0/Download/notes.txt", b"notes"),
+            ("apps/org.cryptomator/_manifest", b"1\norg.cryptomator\n"),
+            ("apps/org.cryptomator/sp/settings.xml", b"<map />"),
+        ];
+        files.into_iter().for_each(|(path, data)| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        });
+        let tar = builder.into_inner().unwrap();
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(false)).unwrap();
+        writer.append_tar(&tar[..]).unwrap();
+        let backup = writer.finish().unwrap();
+
+        assert!(backup.starts_with(b"ANDROID BACKUP\n5\n0\nnone\n"));
+        assert_that!(
+            read_archive_paths(&backup),
+            is(equal_to(vec![
+                String::from("apps/com.android.sharedstoragebackup/_manifest"),
+                String::from("shared/0/Download/notes.txt"),
+                String::from("apps/org.cryptomator/_manifest"),
+                String::from("apps/org.cryptomator/sp/settings.xml"),
+            ]))
+        );
+
+        let mut reader = BackupReader::new(&backup[..]).unwrap();
+        let domains = reader
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().domain)
+            .collect::<Vec<Domain>>();
+        assert_that!(domains[1].clone(), is(equal_to(Domain::SharedStorage)));
+    }
+
+    #[test]
+    fn test_write_tar_stream_with_directory() {
+        let temp_dir = "2a7ad0e412827f00f4b779456c8f1498"; // md5 of 'test_write_tar_stream_with_directory'
+        let files = vec!["apps/org.cryptomator/a/base.apk", "apps/com.dropbox.android/a/base.apk"];
+        files.iter().for_each(|file| {
+            let path = Path::new(temp_dir).join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(file.as_bytes()).unwrap();
+        });
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let files: Vec<(&str, &[u8])> = vec![
+            ("apps/org.cryptomator/_manifest", b"1\norg.cryptomator\n"),
+            ("apps/org.cryptomator/sp/settings.xml", b"<map />"),
+        ];
+        files.into_iter().for_each(|(path, data)| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        });
+        let tar = builder.into_inner().unwrap();
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(true)).unwrap();
+        writer.append_directory(Path::new(temp_dir)).unwrap();
+        writer.append_tar(&tar[..]).unwrap();
+        let backup = writer.finish().unwrap();
+
+        // the apk goes between the manifest and the data of its package
+        assert_that!(
+            read_archive_paths(&backup),
+            is(equal_to(vec![
+                String::from("apps/org.cryptomator/_manifest"),
+                String::from("apps/org.cryptomator/a/base.apk"),
+                String::from("apps/org.cryptomator/sp/settings.xml"),
+                String::from("apps/com.dropbox.android/a/base.apk"),
+            ]))
+        );
+
+        assert!(remove_dir_all(temp_dir).is_ok());
+    }
+
+    #[test]
+    fn test_write_encrypted_backup() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(2);
+        header.set_mode(0o600);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "apps/org.cryptomator/_manifest", &b"1\n"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let mut writer = BackupWriter::with_password(Vec::new(), true, "secret").unwrap();
+        writer.append_tar(&tar[..]).unwrap();
+        let backup = writer.finish().unwrap();
+
+        assert!(backup.starts_with(b"ANDROID BACKUP\n5\n1\nAES-256\n"));
+        assert!(BackupReader::new(&backup[..]).is_err());
+
+        let mut reader = BackupReader::with_password(&backup[..], Some("secret")).unwrap();
+        let archive_paths = reader
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().archive_path())
+            .collect::<Vec<String>>();
+        assert_that!(
+            archive_paths,
+            is(equal_to(vec![String::from("apps/org.cryptomator/_manifest")]))
+        );
+    }
+
+    #[test]
+    fn test_write_without_manifest() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(7);
+        header.set_mode(0o600);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "apps/org.cryptomator/sp/settings.xml", &b"<map />"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(true)).unwrap();
+        assert_that!(
+            format!("{}", writer.append_tar(&tar[..]).err().unwrap()),
+            is(equal_to(String::from(
+                "apps/org.cryptomator/sp/settings.xml is not preceded by the _manifest of package \
+                 org.cryptomator, android would not restore it"
+            )))
+        );
+
+        let temp_dir = "8a296d0344a6942a23efb34922923328"; // md5 of 'test_write_without_manifest'
+        let settings = Path::new(temp_dir).join("apps/org.cryptomator/sp/settings.xml");
+        create_dir_all(settings.parent().unwrap()).unwrap();
+        File::create(&settings).unwrap().write_all(b"<map />").unwrap();
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(true)).unwrap();
+        writer.append_directory(Path::new(temp_dir)).unwrap();
+        assert_that!(
+            format!("{}", writer.finish().err().unwrap()),
+            is(equal_to(String::from(
+                "package org.cryptomator has no _manifest, android would not restore its data"
+            )))
+        );
+
+        assert!(remove_dir_all(temp_dir).is_ok());
+    }
+
+    #[test]
+    fn test_write_apks_without_manifest() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(4);
+        header.set_mode(0o600);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "apps/org.cryptomator/a/base.apk", &b"PK\x03\x04"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let mut writer = BackupWriter::new(Vec::new(), &BackupHeader::new(true)).unwrap();
+        writer.append_tar(&tar[..]).unwrap();
+        let backup = writer.finish().unwrap();
+
+        assert_that!(
+            read_archive_paths(&backup),
+            is(equal_to(vec![String::from("apps/org.cryptomator/a/base.apk")]))
+        );
+    }
+}
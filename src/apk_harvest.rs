@@ -0,0 +1,267 @@
+use failure::Error;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use adb_command::AdbCommand;
+use android_backup::entry::Domain;
+use android_backup::header::{BackupHeader, Encryption};
+use android_backup::reader::{to_backup_entry, BackupReader};
+use android_backup::writer::BackupWriter;
+use apps::App;
+use file_transfer::FileTransfer;
+
+thread_local! {
+    static ENABLED: Cell<bool> = Cell::new(false);
+}
+
+#[derive(Debug, Fail)]
+pub enum HarvestError {
+    #[fail(display = "apk {} of package {} in the backup does not match its hash", name, package)]
+    Corrupt {
+        package: String,
+        name: String,
+    },
+
+    #[fail(display = "apk path {} is not valid UTF-8 and can not be given to adb", path)]
+    InvalidPath {
+        path: String,
+    },
+}
+
+/// An app whose apks were pulled into a backup, so it can be installed again before its
+/// data is restored.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct HarvestedApp {
+    pub package: String,
+    pub apks: Vec<HarvestedApk>,
+    /// hashes of the certificates the app is signed with, android only updates an app by
+    /// one signed alike
+    pub signatures: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct HarvestedApk {
+    /// file name in the apk domain of the package, `base.apk` or the one of a split apk,
+    /// e.g. `split_config.arm64_v8a.apk`
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Sets whether the apks of the backed up apps are pulled into backups made on the current
+/// thread, `adb backup -apk` does not include them on newer android versions.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|harvest| harvest.set(enabled));
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ApkHarvest {}
+
+impl ApkHarvest {
+    /// Pulls the base and split apks of `packages` as told by `pm path` and adds them to
+    /// the backup at `backup_file`, as `apps/<package>/a/<name>` like `adb backup -apk`
+    /// does. Packages without apks on the device are left out.
+    ///
+    /// The backup is written anew next to it. An encrypted backup needs its password and is
+    /// encrypted with it again, without it no apks are pulled.
+    pub fn harvest(
+        device_id: &str,
+        packages: &[String],
+        backup_file: &Path,
+        password: Option<&str>,
+    ) -> Result<Vec<HarvestedApp>, Error> {
+        let header = BackupHeader::read(&mut BufReader::new(File::open(backup_file)?))?;
+        if header.encryption != Encryption::None && password.is_none() {
+            warn!("No apks are added to the encrypted backup, its password is required");
+            return Ok(Vec::new());
+        }
+
+        let directory = side_path(backup_file, "apks");
+
+        let harvested = ApkHarvest::pull_apks(device_id, packages, &directory).and_then(|harvested| {
+            if !harvested.is_empty() {
+                ApkHarvest::add_to_backup(backup_file, &directory, password)?;
+            }
+
+            Ok(harvested)
+        });
+
+        if directory.exists() {
+            fs::remove_dir_all(&directory)?;
+        }
+
+        harvested
+    }
+
+    /// Installs `apps` with `adb install-multiple` from the apks in the backup at
+    /// `backup_file`, so their data can be restored. Returns the installed packages, an app
+    /// android refuses, e.g. as a newer version of it is installed, is left as it is.
+    pub fn install(
+        device_id: &str,
+        apps: &[HarvestedApp],
+        backup_file: &Path,
+        password: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let directory = side_path(backup_file, "apks");
+
+        let installed = ApkHarvest::extract_apks(backup_file, password, &directory).and_then(|_| {
+            let mut installed = Vec::new();
+
+            for app in apps {
+                let apks = ApkHarvest::verified_apks(&directory, app)?;
+                let apks = apks
+                    .iter()
+                    .map(|apk| {
+                        apk.to_str().ok_or_else(|| HarvestError::InvalidPath { path: apk.to_string_lossy().into_owned() })
+                    })
+                    .collect::<Result<Vec<&str>, HarvestError>>()?;
+
+                // -r replaces an installed version of the app, keeping its data
+                let mut args = vec!["-r"];
+                args.extend(apks);
+                let result = AdbCommand::command("install-multiple")
+                    .with_args(args)
+                    .with_device_id(Some(device_id))
+                    .execute();
+
+                match result {
+                    Ok(_) => installed.push(app.package.clone()),
+                    Err(error) => warn!("Could not install {}: {}", app.package, error),
+                }
+            }
+
+            Ok(installed)
+        });
+
+        if directory.exists() {
+            fs::remove_dir_all(&directory)?;
+        }
+
+        installed
+    }
+
+    fn pull_apks(device_id: &str, packages: &[String], directory: &Path) -> Result<Vec<HarvestedApp>, Error> {
+        let mut harvested = Vec::new();
+
+        for package in packages {
+            let paths = App::apk_paths(Some(device_id), package)?;
+            if paths.is_empty() {
+                continue;
+            }
+
+            let apk_directory = apk_directory(directory, package);
+            fs::create_dir_all(&apk_directory)?;
+
+            let mut apks = Vec::new();
+            for path in paths {
+                let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+                let apk = apk_directory.join(&name);
+
+                FileTransfer::pull_to(Some(device_id), &path, &apk.to_string_lossy())?;
+                let (sha256, size) = hash_file(&apk)?;
+
+                apks.push(HarvestedApk { name, sha256, size });
+            }
+
+            harvested.push(HarvestedApp {
+                package: package.clone(),
+                apks,
+                signatures: App::signatures(Some(device_id), package)?,
+            });
+        }
+
+        Ok(harvested)
+    }
+
+    fn add_to_backup(backup_file: &Path, directory: &Path, password: Option<&str>) -> Result<(), Error> {
+        let reader = BackupReader::open(backup_file, password)?;
+        let header = reader.header().clone();
+
+        let part = side_path(backup_file, "part");
+        let written = File::create(&part).map_err(Error::from).and_then(|file| {
+            let mut writer = match (&header.encryption, password) {
+                (&Encryption::Aes256(_), Some(password)) => BackupWriter::with_password(file, header.compressed, password)?,
+                _ => BackupWriter::new(file, &header)?,
+            };
+
+            // the apks are written right after the _manifest of their package while the
+            // backup streams through
+            writer.append_directory(directory)?;
+            writer.append_tar(reader.into_tar())?;
+            writer.finish()?;
+
+            Ok(())
+        });
+
+        match written {
+            Ok(_) => Ok(fs::rename(&part, backup_file)?),
+            Err(error) => {
+                if part.exists() {
+                    fs::remove_file(&part)?;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    fn extract_apks(backup_file: &Path, password: Option<&str>, directory: &Path) -> Result<(), Error> {
+        let mut reader = BackupReader::open(backup_file, password)?;
+        fs::create_dir_all(directory)?;
+
+        for entry in reader.archive().entries()? {
+            let mut entry = entry?;
+
+            if to_backup_entry(&entry)?.domain == Domain::Apk {
+                entry.unpack_in(directory)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the apks of the app in the order they were pulled, the base apk first, checked
+    // against their hashes so no altered apk gets installed
+    fn verified_apks(directory: &Path, app: &HarvestedApp) -> Result<Vec<PathBuf>, Error> {
+        app.apks
+            .iter()
+            .map(|harvested| {
+                let apk = apk_directory(directory, &app.package).join(&harvested.name);
+
+                match hash_file(&apk) {
+                    Ok((ref sha256, _)) if *sha256 == harvested.sha256 => Ok(apk),
+                    _ => Err(Error::from(HarvestError::Corrupt {
+                        package: app.package.clone(),
+                        name: harvested.name.clone(),
+                    })),
+                }
+            })
+            .collect()
+    }
+}
+
+fn apk_directory(directory: &Path, package: &str) -> PathBuf {
+    directory.join("apps").join(package).join(Domain::Apk.token())
+}
+
+fn hash_file(file: &Path) -> Result<(String, u64), Error> {
+    let mut sha256 = Sha256::new();
+    let size = io::copy(&mut File::open(file)?, &mut sha256)?;
+
+    Ok((format!("{:x}", sha256.finalize()), size))
+}
+
+// next to the backup, e.g. `<device>.ab.apks`
+fn side_path(backup_file: &Path, extension: &str) -> PathBuf {
+    let mut side = backup_file.as_os_str().to_os_string();
+    side.push(".");
+    side.push(extension);
+
+    PathBuf::from(side)
+}
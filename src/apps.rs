@@ -0,0 +1,541 @@
+use failure::Error;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use adb_command::AdbCommand;
+
+/// A package installed on the device.
+#[derive(Debug, PartialEq, Clone)]
+pub struct App {
+    pub package: String,
+    /// path of the base apk on the device
+    pub apk_path: String,
+    /// package of the app which installed it, none for preinstalled and sideloaded apps
+    pub installer: Option<String>,
+    pub uid: Option<u32>,
+    pub system: bool,
+    pub enabled: bool,
+    pub version_name: Option<String>,
+    pub version_code: Option<u64>,
+    /// none if the device did not tell the flags of the package
+    pub eligibility: Option<Eligibility>,
+}
+
+/// Whether `adb backup` backs up an app, as told by the flags of its package.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Eligibility {
+    Eligible,
+    /// the app sets `android:allowBackup="false"`
+    OptedOut,
+    /// the app has a key/value backup agent and is not `fullBackupOnly`, `adb backup` leaves
+    /// such apps out
+    KeyValueOnly { agent: String },
+}
+
+impl Eligibility {
+    pub fn is_eligible(&self) -> bool {
+        *self == Eligibility::Eligible
+    }
+}
+
+impl fmt::Display for Eligibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Eligibility::Eligible => write!(f, "eligible"),
+            Eligibility::OptedOut => write!(f, "excluded, the app does not allow backups"),
+            Eligibility::KeyValueOnly { ref agent } => write!(
+                f,
+                "excluded, the app only supports key/value backups by its agent {}",
+                agent
+            ),
+        }
+    }
+}
+
+/// Which apps to list, all of them by default. Third party and system apps are both listed
+/// if both are asked for.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct AppFilter {
+    third_party: bool,
+    system: bool,
+    disabled: bool,
+    installer: Option<String>,
+    grep: Option<String>,
+    user: Option<u32>,
+}
+
+impl AppFilter {
+    pub fn with_third_party(self) -> Self {
+        AppFilter { third_party: true, ..self }
+    }
+
+    pub fn with_system(self) -> Self {
+        AppFilter { system: true, ..self }
+    }
+
+    pub fn with_disabled(self) -> Self {
+        AppFilter { disabled: true, ..self }
+    }
+
+    pub fn with_installer(self, installer: &str) -> Self {
+        AppFilter { installer: Some(installer.to_string()), ..self }
+    }
+
+    /// Only packages whose name contains `pattern`, ignoring case.
+    pub fn with_grep(self, pattern: &str) -> Self {
+        AppFilter { grep: Some(pattern.to_lowercase()), ..self }
+    }
+
+    /// The apps of another user of the device than the current one.
+    pub fn with_user(self, user: u32) -> Self {
+        AppFilter { user: Some(user), ..self }
+    }
+
+    pub fn matches(&self, app: &App) -> bool {
+        let kind = match (self.third_party, self.system) {
+            (false, false) | (true, true) => true,
+            (third_party, _) => app.system != third_party,
+        };
+
+        kind
+            && (!self.disabled || !app.enabled)
+            && self.installer.as_ref().map_or(true, |installer| app.installer.as_ref() == Some(installer))
+            && self.grep.as_ref().map_or(true, |pattern| app.package.to_lowercase().contains(pattern))
+    }
+}
+
+impl App {
+    /// The apps installed on the device which pass `filter`, sorted by package.
+    pub fn list(device_id: Option<&str>, filter: &AppFilter) -> Result<Vec<App>, Error> {
+        let user = filter.user.map(|user| user.to_string());
+        let list_packages = |options: &[&'static str]| {
+            let mut args = vec!["pm", "list", "packages"];
+            args.extend(options);
+            if let Some(ref user) = user {
+                args.extend(vec!["--user", user.as_str()]);
+            }
+
+            AdbCommand::command("shell")
+                .with_args(args)
+                .with_device_id(device_id)
+                .execute()
+        };
+
+        let packages = list_packages(&["-f", "-i", "-U"])?;
+        let third_party = App::parse_package_names(&list_packages(&["-3"])?);
+        let disabled = App::parse_package_names(&list_packages(&["-d"])?);
+
+        // versions and eligibility are left out if the device does not dump its packages
+        let dump = AdbCommand::command("shell")
+            .with_args(vec!["dumpsys", "package", "packages"])
+            .with_device_id(device_id)
+            .execute()
+            .unwrap_or_default();
+        let versions = App::parse_versions(&dump);
+        let eligibility = App::parse_eligibility(&dump);
+
+        let mut apps = App::parse_packages(&packages, &third_party, &disabled, &versions)
+            .into_iter()
+            .filter(|app| filter.matches(app))
+            .map(|app| App { eligibility: eligibility.get(&app.package).cloned(), ..app })
+            .collect::<Vec<App>>();
+        apps.sort_by(|a, b| a.package.cmp(&b.package));
+
+        Ok(apps)
+    }
+
+    // `package:<apk path>=<package>  installer=<installer> uid:<uid>` as listed by
+    // `pm list packages -f -i -U`, the apk path may contain `=` itself
+    fn parse_packages(
+        output: &str,
+        third_party: &HashSet<String>,
+        disabled: &HashSet<String>,
+        versions: &HashMap<String, (Option<String>, Option<u64>)>,
+    ) -> Vec<App> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.trim().split_whitespace();
+                let path_and_package = fields.next()?;
+                if !path_and_package.starts_with("package:") {
+                    return None;
+                }
+
+                let mut path_and_package = path_and_package["package:".len()..].rsplitn(2, '=');
+                let package = path_and_package.next()?.to_string();
+                let apk_path = path_and_package.next()?.to_string();
+
+                let mut installer = None;
+                let mut uid = None;
+                for field in fields {
+                    if field.starts_with("installer=") {
+                        installer = Some(field["installer=".len()..].to_string()).filter(|installer| installer != "null");
+                    } else if field.starts_with("uid:") {
+                        uid = field["uid:".len()..].parse().ok();
+                    }
+                }
+
+                let (version_name, version_code) = versions.get(&package).cloned().unwrap_or((None, None));
+
+                Some(App {
+                    system: !third_party.contains(&package),
+                    enabled: !disabled.contains(&package),
+                    package,
+                    apk_path,
+                    installer,
+                    uid,
+                    version_name,
+                    version_code,
+                    eligibility: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether `adb backup` backs up `package`, as told by `dumpsys package <package>`. None
+    /// if the package is not installed.
+    pub fn eligibility(device_id: Option<&str>, package: &str) -> Result<Option<Eligibility>, Error> {
+        let dump = AdbCommand::command("shell")
+            .with_args(vec!["dumpsys", "package", package])
+            .with_device_id(device_id)
+            .execute()?;
+
+        Ok(App::parse_eligibility(&dump).remove(package))
+    }
+
+    /// Paths of the base and split apks of `package` on the device, as told by `pm path`.
+    pub fn apk_paths(device_id: Option<&str>, package: &str) -> Result<Vec<String>, Error> {
+        let output = AdbCommand::command("shell")
+            .with_args(vec!["pm", "path", package])
+            .with_device_id(device_id)
+            .execute()?;
+
+        Ok(App::parse_apk_paths(&output))
+    }
+
+    /// The hashes of the certificates `package` is signed with, as told by `dumpsys package`.
+    pub fn signatures(device_id: Option<&str>, package: &str) -> Result<Vec<String>, Error> {
+        let dump = AdbCommand::command("shell")
+            .with_args(vec!["dumpsys", "package", package])
+            .with_device_id(device_id)
+            .execute()?;
+
+        Ok(App::parse_signatures(&dump))
+    }
+
+    // `package:<path>` per apk
+    fn parse_apk_paths(output: &str) -> Vec<String> {
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("package:"))
+            .map(|line| line["package:".len()..].to_string())
+            .collect()
+    }
+
+    // `signatures=PackageSignatures{9fe2bd4 version:2, signatures:[5a5d2c36], past signatures:[]}`,
+    // before android 9 `signatures=PackageSignatures{419a7e60 [41bebaec]}`
+    fn parse_signatures(output: &str) -> Vec<String> {
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("signatures=PackageSignatures{"))
+            .filter_map(|line| {
+                let start = line
+                    .find("signatures:[")
+                    .map(|start| start + "signatures:".len())
+                    .or_else(|| line.find('['))?;
+                let signatures = &line[start + 1..];
+                let end = signatures.find(']')?;
+
+                Some(
+                    signatures[..end]
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|signature| !signature.is_empty())
+                        .map(String::from)
+                        .collect(),
+                )
+            })
+            .next()
+            .unwrap_or_default()
+    }
+
+    // the flags of a package are dumped as `flags=[ HAS_CODE ALLOW_BACKUP ... ]`, on older
+    // versions as `pkgFlags=[ ... ]`, a backup agent as `backupAgentName=<class>` and
+    // `fullBackupOnly=true` or the `FULL_BACKUP_ONLY` flag
+    fn parse_eligibility(output: &str) -> HashMap<String, Eligibility> {
+        let mut packages = HashMap::new();
+        let mut package: Option<String> = None;
+
+        for line in output.lines().map(str::trim) {
+            if line.starts_with("Package [") {
+                package = line["Package [".len()..].split(']').next().map(String::from);
+                continue;
+            }
+
+            let flags = match package {
+                Some(ref package) => packages.entry(package.clone()).or_insert((None, None, false)),
+                None => continue,
+            };
+
+            if line.starts_with("flags=[") || line.starts_with("pkgFlags=[") {
+                let allow_backup = line.split_whitespace().any(|flag| flag == "ALLOW_BACKUP");
+                flags.0 = Some(flags.0.unwrap_or(false) || allow_backup);
+                flags.2 = flags.2 || line.split_whitespace().any(|flag| flag == "FULL_BACKUP_ONLY");
+            }
+
+            for field in line.split_whitespace() {
+                if field.starts_with("backupAgentName=") && flags.1.is_none() {
+                    flags.1 = Some(field["backupAgentName=".len()..].to_string()).filter(|agent| agent != "null");
+                } else if field == "fullBackupOnly=true" {
+                    flags.2 = true;
+                }
+            }
+        }
+
+        packages
+            .into_iter()
+            .filter_map(|(package, (allow_backup, agent, full_backup_only))| {
+                let eligibility = match (allow_backup?, agent) {
+                    (false, _) => Eligibility::OptedOut,
+                    (true, Some(agent)) if !full_backup_only => Eligibility::KeyValueOnly { agent },
+                    (true, _) => Eligibility::Eligible,
+                };
+
+                Some((package, eligibility))
+            })
+            .collect()
+    }
+
+    // `package:<package>` per line
+    fn parse_package_names(output: &str) -> HashSet<String> {
+        output
+            .lines()
+            .filter(|line| line.starts_with("package:"))
+            .map(|line| line["package:".len()..].trim().to_string())
+            .collect()
+    }
+
+    // `Package [<package>] (<id>):` starts the dump of a package, followed by indented
+    // lines like `versionCode=<code> minSdk=<sdk> targetSdk=<sdk>` and `versionName=<name>`
+    fn parse_versions(output: &str) -> HashMap<String, (Option<String>, Option<u64>)> {
+        let mut versions = HashMap::new();
+        let mut package: Option<String> = None;
+
+        for line in output.lines().map(str::trim) {
+            if line.starts_with("Package [") {
+                package = line["Package [".len()..].split(']').next().map(String::from);
+                continue;
+            }
+
+            let version = match package {
+                Some(ref package) => versions.entry(package.clone()).or_insert((None, None)),
+                None => continue,
+            };
+
+            for field in line.split_whitespace() {
+                if field.starts_with("versionCode=") && version.1.is_none() {
+                    version.1 = field["versionCode=".len()..].parse().ok();
+                } else if field.starts_with("versionName=") && version.0.is_none() {
+                    version.0 = Some(field["versionName=".len()..].to_string());
+                }
+            }
+        }
+
+        versions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use apps::{App, AppFilter, Eligibility};
+    use std::collections::{HashMap, HashSet};
+
+    fn app(package: &str, system: bool, enabled: bool, installer: Option<&str>) -> App {
+        App {
+            package: package.to_string(),
+            apk_path: format!("/data/app/{}-1/base.apk", package),
+            installer: installer.map(String::from),
+            uid: Some(10085),
+            system,
+            enabled,
+            version_name: None,
+            version_code: None,
+            eligibility: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_mocked_packages() {
+        let mocked_output = "package:/data/app/~~x5Vb8K3JzQ==/org.cryptomator-Jd2mXz7Q==/base.apk=org.cryptomator  \
+            installer=com.android.vending uid:10085\n\
+            package:/system/priv-app/GoogleMaps/GoogleMaps.apk=com.google.android.apps.maps  installer=null uid:10064\n\
+            package:/system/app/Bloat/Bloat.apk=com.vendor.bloat  installer=null uid:10070\n";
+
+        let third_party = vec!["org.cryptomator".to_string()].into_iter().collect::<HashSet<String>>();
+        let disabled = vec!["com.vendor.bloat".to_string()].into_iter().collect::<HashSet<String>>();
+        let mut versions = HashMap::new();
+        versions.insert("org.cryptomator".to_string(), (Some("1.5.0".to_string()), Some(1500)));
+
+        assert_that!(
+            App::parse_packages(mocked_output, &third_party, &disabled, &versions),
+            is(equal_to(vec![
+                App {
+                    package: "org.cryptomator".to_string(),
+                    apk_path: "/data/app/~~x5Vb8K3JzQ==/org.cryptomator-Jd2mXz7Q==/base.apk".to_string(),
+                    installer: Some("com.android.vending".to_string()),
+                    uid: Some(10085),
+                    system: false,
+                    enabled: true,
+                    version_name: Some("1.5.0".to_string()),
+                    version_code: Some(1500),
+                    eligibility: None,
+                },
+                App {
+                    package: "com.google.android.apps.maps".to_string(),
+                    apk_path: "/system/priv-app/GoogleMaps/GoogleMaps.apk".to_string(),
+                    installer: None,
+                    uid: Some(10064),
+                    system: true,
+                    enabled: true,
+                    version_name: None,
+                    version_code: None,
+                    eligibility: None,
+                },
+                App {
+                    package: "com.vendor.bloat".to_string(),
+                    apk_path: "/system/app/Bloat/Bloat.apk".to_string(),
+                    installer: None,
+                    uid: Some(10070),
+                    system: true,
+                    enabled: false,
+                    version_name: None,
+                    version_code: None,
+                    eligibility: None,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_mocked_versions() {
+        let mocked_output = "Packages:\n  \
+            Package [org.cryptomator] (5d7f0e3):\n    \
+            userId=10085\n    \
+            pkg=Package{a1b2c3 org.cryptomator}\n    \
+            versionCode=1500 minSdk=21 targetSdk=28\n    \
+            versionName=1.5.0\n  \
+            Package [com.android.smoketest] (8e1c2a0):\n    \
+            userId=10031\n    \
+            versionCode=28 minSdk=28 targetSdk=28\n";
+
+        let versions = App::parse_versions(mocked_output);
+
+        assert_that!(versions.len(), is(equal_to(2)));
+        assert_that!(
+            versions["org.cryptomator"].clone(),
+            is(equal_to((Some("1.5.0".to_string()), Some(1500))))
+        );
+        assert_that!(versions["com.android.smoketest"].clone(), is(equal_to((None, Some(28)))));
+    }
+
+    #[test]
+    fn test_app_filter() {
+        let apps = vec![
+            app("org.cryptomator", false, true, Some("com.android.vending")),
+            app("org.fdroid.fdroid", false, true, None),
+            app("com.google.android.apps.maps", true, true, Some("com.android.vending")),
+            app("com.vendor.bloat", true, false, None),
+        ];
+        let packages = |filter: AppFilter| {
+            apps.iter()
+                .filter(|app| filter.matches(app))
+                .map(|app| app.package.as_str())
+                .collect::<Vec<&str>>()
+        };
+
+        assert_that!(packages(AppFilter::default()).len(), is(equal_to(4)));
+        assert_that!(
+            packages(AppFilter::default().with_third_party()),
+            is(equal_to(vec!["org.cryptomator", "org.fdroid.fdroid"]))
+        );
+        assert_that!(
+            packages(AppFilter::default().with_system()),
+            is(equal_to(vec!["com.google.android.apps.maps", "com.vendor.bloat"]))
+        );
+        assert_that!(packages(AppFilter::default().with_third_party().with_system()).len(), is(equal_to(4)));
+        assert_that!(packages(AppFilter::default().with_disabled()), is(equal_to(vec!["com.vendor.bloat"])));
+        assert_that!(
+            packages(AppFilter::default().with_installer("com.android.vending")),
+            is(equal_to(vec!["org.cryptomator", "com.google.android.apps.maps"]))
+        );
+        assert_that!(
+            packages(AppFilter::default().with_system().with_grep("MAPS")),
+            is(equal_to(vec!["com.google.android.apps.maps"]))
+        );
+    }
+
+    #[test]
+    fn test_parse_mocked_eligibility() {
+        let mocked_output = "Packages:\n  \
+            Package [org.cryptomator] (5d7f0e3):\n    \
+            versionCode=1500 minSdk=21 targetSdk=28\n    \
+            flags=[ HAS_CODE ALLOW_CLEAR_USER_DATA ALLOW_BACKUP ]\n  \
+            Package [com.example.banking] (8e1c2a0):\n    \
+            flags=[ HAS_CODE ALLOW_CLEAR_USER_DATA ]\n  \
+            Package [com.example.notes] (1f2e3d4):\n    \
+            backupAgentName=com.example.notes.NotesBackupAgent\n    \
+            pkgFlags=[ HAS_CODE ALLOW_BACKUP ]\n  \
+            Package [com.example.photos] (4d3c2b1):\n    \
+            backupAgentName=com.example.photos.PhotosBackupAgent fullBackupOnly=true\n    \
+            flags=[ HAS_CODE ALLOW_BACKUP ]\n  \
+            Package [com.example.unknown] (9a8b7c6):\n    \
+            versionCode=1 minSdk=21 targetSdk=28\n";
+
+        let eligibility = App::parse_eligibility(mocked_output);
+
+        assert_that!(eligibility.len(), is(equal_to(4)));
+        assert_that!(eligibility["org.cryptomator"].clone(), is(equal_to(Eligibility::Eligible)));
+        assert_that!(eligibility["com.example.banking"].clone(), is(equal_to(Eligibility::OptedOut)));
+        assert_that!(
+            eligibility["com.example.notes"].clone(),
+            is(equal_to(Eligibility::KeyValueOnly { agent: "com.example.notes.NotesBackupAgent".to_string() }))
+        );
+        assert_that!(eligibility["com.example.photos"].clone(), is(equal_to(Eligibility::Eligible)));
+        assert!(!eligibility.contains_key("com.example.unknown"));
+    }
+
+    #[test]
+    fn test_parse_mocked_apk_paths() {
+        let mocked_output = "package:/data/app/org.cryptomator-1/base.apk\n\
+            package:/data/app/org.cryptomator-1/split_config.arm64_v8a.apk\n\
+            package:/data/app/org.cryptomator-1/split_config.xxhdpi.apk\n";
+
+        assert_that!(
+            App::parse_apk_paths(mocked_output),
+            is(equal_to(vec![
+                "/data/app/org.cryptomator-1/base.apk".to_string(),
+                "/data/app/org.cryptomator-1/split_config.arm64_v8a.apk".to_string(),
+                "/data/app/org.cryptomator-1/split_config.xxhdpi.apk".to_string(),
+            ]))
+        );
+        assert!(App::parse_apk_paths("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_mocked_signatures() {
+        let mocked_output = "Packages:\n  \
+            Package [org.cryptomator] (5d7f0e3):\n    \
+            versionCode=1500 minSdk=21 targetSdk=28\n    \
+            signatures=PackageSignatures{9fe2bd4 version:2, signatures:[5a5d2c36, 1b2c3d4e], past signatures:[]}\n";
+        assert_that!(
+            App::parse_signatures(mocked_output),
+            is(equal_to(vec!["5a5d2c36".to_string(), "1b2c3d4e".to_string()]))
+        );
+
+        let mocked_output = "    signatures=PackageSignatures{419a7e60 [41bebaec]}\n";
+        assert_that!(App::parse_signatures(mocked_output), is(equal_to(vec!["41bebaec".to_string()])));
+    }
+}
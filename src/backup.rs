@@ -74,71 +74,4 @@ impl Backup {
         
         Ok(())
     }
-
-    pub fn list_apps(device_id: Option<&str>) -> Result<Vec<String>, Error> {
-        let args: Vec<&str> = vec!["pm", "list", "packages"];
-
-        let output = AdbCommand::command("shell")
-            .with_args(args)
-            .with_device_id(device_id)
-            .execute()?;
-
-        return Ok(Backup::parse_list_apps(output));
-    }
-
-    fn parse_list_apps(command_response: String) -> Vec<String> {
-        let mut result: Vec<String> = Vec::new();
-
-        command_response
-            .split('\n')
-            .collect::<Vec<&str>>()
-            .into_iter()
-            .filter(|x| {
-                !x.contains("package:com.android.") && !x.contains("package:com.google.android.")
-            })
-            .for_each(|package| {
-                let split_package = package.split(':').collect::<Vec<&str>>();
-
-                if let Some(package_name) = split_package.get(1) {
-                    result.push(package_name.to_string());
-                }
-            });
-
-        result
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use hamcrest::prelude::*;
-    use backup::Backup;
-
-    #[test]
-    fn test_parse_list_apps() {
-        let mocked_output = "package:com.android.smoketest\n\
-            package:com.android.cts.priv.ctsshim\npackage:org.cryptomator\n
-            package:com.google.android.youtube\npackage:com.google.android.ext.services\n
-            package:com.example.android.livecubes\npackage:com.android.providers.telephony\n
-            package:com.google.android.googlequicksearchbox\npackage:com.android.provider.calendar\n
-            package:com.android.providers.media\npackage:com.google.android.onetimeinitializer\n
-            package:com.google.android.ext.shared\npackage:com.android.protips\n
-            package:com.estrongs.android.pop\npackage:org.cryptomator.test\n
-            package:com.dropbox.android\npackage:com.android.sdksetup\n
-            package:com.ustwo.lwp\npackage:com.breel.geswallpapers";
-
-        let mocked_apps = vec![
-            "org.cryptomator".to_string(),
-            "com.example.android.livecubes".to_string(),
-            "com.estrongs.android.pop".to_string(),
-            "org.cryptomator.test".to_string(),
-            "com.dropbox.android".to_string(),
-            "com.ustwo.lwp".to_string(),
-            "com.breel.geswallpapers".to_string(),
-        ];
-
-        assert_that!(
-            Backup::parse_list_apps(mocked_output.to_string()),
-            is(equal_to(mocked_apps))
-        )
-    }
 }
@@ -11,9 +11,14 @@ use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 extern crate adbackup;
 
+use adbackup::{AppFilter, Codec, RetentionPolicy, StoreKind};
+
 extern crate failure;
 
 use failure::{Error, err_msg};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 
 fn main() {
     let matches = make_clap().get_matches();
@@ -27,6 +32,23 @@ fn main() {
 
     adbackup::setup_logging(verbosity);
 
+    if let Some(repo) = param_from_match("repo", &matches, subm) {
+        adbackup::set_repository(Path::new(repo));
+    }
+
+    if let Some(store) = param_from_match("store", &matches, subm) {
+        match store.parse::<StoreKind>() {
+            Ok(kind) => adbackup::set_store(kind),
+            Err(error) => return error!("adbackup finished with error: {}", error.to_string()),
+        }
+    }
+
+    match read_password_file(param_from_match("passphrase_file", &matches, subm)) {
+        Ok(Some(passphrase)) => adbackup::set_passphrase(&passphrase),
+        Ok(None) => {}
+        Err(error) => return error!("adbackup finished with error: {}", error.to_string()),
+    }
+
     let result = match sub_name {
         "backup" => backup(&matches, subm),
         "restore" => restore(&matches, subm),
@@ -34,6 +56,16 @@ fn main() {
         "push" => push(&matches, subm),
         "pull" => pull(&matches, subm),
         "apps" => apps(&matches, subm),
+        "extract" => extract(&matches, subm),
+        "backups" => backups(&matches, subm),
+        "verify" => verify(&matches, subm),
+        "prune" => prune(&matches, subm),
+        "init" => init(subm),
+        "change-passphrase" => change_passphrase(&matches, subm),
+        "export" => export(&matches, subm),
+        "import" => import(&matches, subm),
+        "check" => check(subm),
+        "info" => info(&matches, subm),
         _ => unimplemented!(),
     };
 
@@ -52,6 +84,22 @@ fn make_clap<'a, 'b>() -> clap::App<'a, 'b> {
             .value_name("ID")
     };
 
+    fn password_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+        Arg::with_name("password_file")
+            .help("File containing the password of an encrypted backup, as entered on the device")
+            .long("password-file")
+            .takes_value(true)
+            .value_name("FILE")
+    };
+
+    fn keep_arg<'a, 'b>(name: &'a str, long: &'a str, help: &'a str) -> Arg<'a, 'b> {
+        Arg::with_name(name)
+            .help(help)
+            .long(long)
+            .takes_value(true)
+            .value_name("N")
+    };
+
     App::new("adbackup")
         .about("A backup tool for android using adb")
         .author(crate_authors!())
@@ -64,6 +112,30 @@ fn make_clap<'a, 'b>() -> clap::App<'a, 'b> {
                 .global(true)
                 .help("Increases logging verbosity each use for up to 3 times"),
         )
+        .arg(
+            Arg::with_name("repo")
+                .long("repo")
+                .takes_value(true)
+                .global(true)
+                .value_name("DIR")
+                .help("Repository of the backups, defaults to $ADBACKUP_REPO or the current directory"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .takes_value(true)
+                .global(true)
+                .value_name("KIND")
+                .help("How the repository keeps the backups: sqlite (default), directory, tar or s3"),
+        )
+        .arg(
+            Arg::with_name("passphrase_file")
+                .long("passphrase-file")
+                .takes_value(true)
+                .global(true)
+                .value_name("FILE")
+                .help("Passphrase or key file of an encrypted repository"),
+        )
         .subcommand(
             SubCommand::with_name("backup")
                 .display_order(1)
@@ -95,13 +167,42 @@ fn make_clap<'a, 'b>() -> clap::App<'a, 'b> {
                         .takes_value(true)
                         .multiple(true)
                         .value_name("APP"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("compression")
+                        .help("Compression of the stored backup: zstd (default), gzip, xz or none, not for encrypted repositories")
+                        .long("compression")
+                        .takes_value(true)
+                        .value_name("CODEC"),
+                )
+                .arg(
+                    Arg::with_name("harvest_apks")
+                        .help("Pull the base and split apks of the apps into the backup, restoring installs them")
+                        .long("harvest-apks"),
+                )
+                .arg(password_file_arg()),
         )
         .subcommand(
             SubCommand::with_name("restore")
                 .display_order(2)
                 .about("Restore android backup")
-                .arg(device_arg()),
+                .arg(device_arg())
+                .arg(
+                    Arg::with_name("backup_version")
+                        .help("Version of the backup to restore, the latest if omitted")
+                        .long("version")
+                        .takes_value(true)
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::with_name("before")
+                        .help("Restore the latest backup created before this date (UTC)")
+                        .long("before")
+                        .takes_value(true)
+                        .conflicts_with("backup_version")
+                        .value_name("YYYY-MM-DD[ HH:MM:SS]"),
+                )
+                .arg(password_file_arg()),
         )
         .subcommand(
             SubCommand::with_name("devices")
@@ -141,6 +242,190 @@ fn make_clap<'a, 'b>() -> clap::App<'a, 'b> {
             SubCommand::with_name("apps")
                 .display_order(6)
                 .about("List all installed apps on devices")
+                .arg(device_arg())
+                .arg(
+                    Arg::with_name("third_party")
+                        .help("List apps installed by the user")
+                        .long("third-party"),
+                )
+                .arg(
+                    Arg::with_name("system")
+                        .help("List apps which are part of the system")
+                        .long("system"),
+                )
+                .arg(
+                    Arg::with_name("disabled")
+                        .help("List only disabled apps")
+                        .long("disabled"),
+                )
+                .arg(
+                    Arg::with_name("installer")
+                        .help("List only apps installed by this package, e.g. com.android.vending")
+                        .long("installer")
+                        .takes_value(true)
+                        .value_name("PACKAGE"),
+                )
+                .arg(
+                    Arg::with_name("grep")
+                        .help("List only apps whose package contains TEXT, ignoring case")
+                        .long("grep")
+                        .takes_value(true)
+                        .value_name("TEXT"),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .help("List the apps of another user of the device")
+                        .long("user")
+                        .takes_value(true)
+                        .value_name("ID"),
+                )
+                .arg(
+                    Arg::with_name("backup_eligibility")
+                        .help("Tell for each app whether adb backup backs it up")
+                        .long("backup-eligibility"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .display_order(7)
+                .about("Extract a stored backup into a folder or tar file")
+                .arg(device_arg())
+                .arg(
+                    Arg::with_name("backup_version")
+                        .help("Version of the backup to extract, the latest if omitted")
+                        .long("version")
+                        .takes_value(true)
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .help("Folder to extract into, or a file ending with .tar")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("DIR|FILE.tar"),
+                )
+                .arg(password_file_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("backups")
+                .display_order(8)
+                .about("List and inspect stored backups")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List all stored backups of a device")
+                        .arg(device_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show the packages and files of a stored backup")
+                        .arg(device_arg())
+                        .arg(
+                            Arg::with_name("backup_version")
+                                .help("Version of the backup to show")
+                                .required(true)
+                                .value_name("VERSION"),
+                        )
+                        .arg(password_file_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("stats")
+                        .about("Show how many backups the repository holds and their size"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .display_order(9)
+                .about("Check stored backups for corruption")
+                .arg(device_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("prune")
+                .display_order(10)
+                .about("Remove old backups according to a retention policy")
+                .arg(device_arg())
+                .arg(keep_arg("keep_last", "keep-last", "Keep the last N backups"))
+                .arg(keep_arg("keep_daily", "keep-daily", "Keep the latest backup of each of the last N days"))
+                .arg(keep_arg("keep_weekly", "keep-weekly", "Keep the latest backup of each of the last N weeks"))
+                .arg(keep_arg("keep_monthly", "keep-monthly", "Keep the latest backup of each of the last N months"))
+                .arg(
+                    Arg::with_name("dry_run")
+                        .help("Only list the backups which would be removed")
+                        .long("dry-run"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .display_order(11)
+                .about("Create the repository, optionally encrypted with the passphrase")
+                .arg(
+                    Arg::with_name("encrypt")
+                        .help("Encrypt all backups put into the repository")
+                        .long("encrypt"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("change-passphrase")
+                .display_order(12)
+                .about("Change the passphrase of an encrypted repository")
+                .arg(
+                    Arg::with_name("new_passphrase_file")
+                        .help("File containing the new passphrase")
+                        .long("new-passphrase-file")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .display_order(13)
+                .about("Export backups of a device into a bundle file")
+                .arg(device_arg())
+                .arg(
+                    Arg::with_name("versions")
+                        .help("Versions to export, all if not given")
+                        .long("versions")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .value_name("VERSION"),
+                )
+                .arg(
+                    Arg::with_name("bundle")
+                        .help("Bundle file to create")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .display_order(14)
+                .about("Import the backups of a bundle file into the repository")
+                .arg(
+                    Arg::with_name("bundle")
+                        .help("Bundle file written by export")
+                        .required(true)
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .display_order(15)
+                .about("Check the repository for damage, e.g. by interrupted backups")
+                .arg(
+                    Arg::with_name("repair")
+                        .help("Move broken backups into the quarantine and remove what is left over")
+                        .long("repair"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .display_order(16)
+                .about("Show android version, storage and battery of a device")
                 .arg(device_arg()),
         )
 }
@@ -158,13 +443,28 @@ fn backup(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error>
     let shared = param_from_match("shared", matches, subm);
     let system = param_from_match("system", matches, subm);
     let only_specified = param_from_match("only_specified", matches, subm);
+    let password = read_password_file(param_from_match("password_file", matches, subm))?;
+
+    if let Some(compression) = param_from_match("compression", matches, subm) {
+        adbackup::set_compression(compression.parse::<Codec>()?);
+    }
+    if subm.map(|subm| subm.is_present("harvest_apks")).unwrap_or(false) {
+        adbackup::set_apk_harvest(true);
+    }
 
     let device_id = match device_id {
         Some(id) => String::from(id),
         None => adbackup::get_device_id()?
     };
 
-    let backup = adbackup::backup(&device_id, apk, shared, system, only_specified)?;
+    let backup = adbackup::backup(
+        &device_id,
+        apk,
+        shared,
+        system,
+        only_specified,
+        password.as_ref().map(String::as_str),
+    )?;
     info!("{}", backup);
 
     Ok(())
@@ -172,13 +472,26 @@ fn backup(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error>
 
 fn restore(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
     let device_id = param_from_match("device", matches, subm);
+    let version = param_from_match("backup_version", matches, subm);
+    let before = param_from_match("before", matches, subm);
+    let password = read_password_file(param_from_match("password_file", matches, subm))?;
 
     let device_id = match device_id {
         Some(id) => String::from(id),
         None => adbackup::get_device_id()?
     };
 
-    let restore = adbackup::restore(&device_id)?;
+    let version = match version {
+        Some(version) => Some(parse_backup_version(version)?),
+        None => None,
+    };
+
+    let restore = adbackup::restore(
+        &device_id,
+        version,
+        before,
+        password.as_ref().map(String::as_str),
+    )?;
     info!("{}", restore);
 
     Ok(())
@@ -186,13 +499,227 @@ fn restore(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error>
 
 fn apps(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
     let device_id = param_from_match("device", matches, subm);
+    let is_present = |name: &str| subm.map(|subm| subm.is_present(name)).unwrap_or(false);
+
+    let mut filter = AppFilter::default();
+    if is_present("third_party") {
+        filter = filter.with_third_party();
+    }
+    if is_present("system") {
+        filter = filter.with_system();
+    }
+    if is_present("disabled") {
+        filter = filter.with_disabled();
+    }
+    if let Some(installer) = param_from_match("installer", matches, subm) {
+        filter = filter.with_installer(installer);
+    }
+    if let Some(pattern) = param_from_match("grep", matches, subm) {
+        filter = filter.with_grep(pattern);
+    }
+    if let Some(user) = param_from_match("user", matches, subm) {
+        let user = user
+            .parse::<u32>()
+            .map_err(|_| err_msg(format!("Invalid user: {}", user)))?;
+        filter = filter.with_user(user);
+    }
 
-    let apps = adbackup::get_printable_app_list(device_id)?;
+    let apps = match is_present("backup_eligibility") {
+        true => adbackup::get_printable_backup_eligibility(device_id, &filter)?,
+        false => adbackup::get_printable_app_list(device_id, &filter)?,
+    };
     info!("{}", apps);
 
     Ok(())
 }
 
+fn info(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let device_id = param_from_match("device", matches, subm);
+
+    let device_info = adbackup::get_printable_device_info(device_id)?;
+    info!("{}", device_info);
+
+    Ok(())
+}
+
+fn extract(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let device_id = param_from_match("device", matches, subm);
+    let version = param_from_match("backup_version", matches, subm);
+    let target = param_from_match("target", matches, subm);
+    let password = read_password_file(param_from_match("password_file", matches, subm))?;
+
+    let device_id = match device_id {
+        Some(id) => String::from(id),
+        None => adbackup::get_device_id()?
+    };
+
+    let version = match version {
+        Some(version) => Some(parse_backup_version(version)?),
+        None => None,
+    };
+
+    if let Some(target) = target {
+        let result = adbackup::extract(
+            &device_id,
+            version,
+            target,
+            password.as_ref().map(String::as_str),
+        )?;
+        info!("{}", result);
+
+        return Ok(());
+    }
+
+    Err(err_msg("Target not specified")) // is not possible from cmd because it is required
+}
+
+fn backups(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let (backups_name, backups_subm) = match subm {
+        Some(subm) => subm.subcommand(),
+        None => return Err(err_msg("No backups command given")),
+    };
+
+    if backups_name == "stats" {
+        let stats = adbackup::get_printable_store_stats()?;
+        info!("{}", stats);
+
+        return Ok(());
+    }
+
+    let device_id = param_from_match("device", matches, backups_subm);
+    let version = param_from_match("backup_version", matches, backups_subm);
+    let password = read_password_file(param_from_match("password_file", matches, backups_subm))?;
+
+    let device_id = match device_id {
+        Some(id) => String::from(id),
+        None => adbackup::get_device_id()?
+    };
+
+    let result = match (backups_name, version) {
+        ("list", _) => adbackup::get_printable_backup_list(&device_id)?,
+        ("show", Some(version)) => adbackup::get_printable_backup(
+            &device_id,
+            parse_backup_version(version)?,
+            password.as_ref().map(String::as_str),
+        )?,
+        ("show", None) => return Err(err_msg("Backup version not specified")),
+        (name, _) => return Err(err_msg(format!("Unknown backups command: {}", name))),
+    };
+    info!("{}", result);
+
+    Ok(())
+}
+
+fn verify(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let device_id = param_from_match("device", matches, subm);
+
+    let device_id = match device_id {
+        Some(id) => String::from(id),
+        None => adbackup::get_device_id()?
+    };
+
+    let verify = adbackup::verify(&device_id)?;
+    info!("{}", verify);
+
+    Ok(())
+}
+
+fn prune(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let device_id = param_from_match("device", matches, subm);
+    let dry_run = subm.map(|subm| subm.is_present("dry_run")).unwrap_or(false);
+
+    let device_id = match device_id {
+        Some(id) => String::from(id),
+        None => adbackup::get_device_id()?
+    };
+
+    let keep = |name: &str| match param_from_match(name, matches, subm) {
+        Some(count) => count
+            .parse::<u32>()
+            .map_err(|_| err_msg(format!("Invalid number of backups to keep: {}", count))),
+        None => Ok(0),
+    };
+
+    let policy = RetentionPolicy::default()
+        .with_keep_last(keep("keep_last")?)
+        .with_keep_daily(keep("keep_daily")?)
+        .with_keep_weekly(keep("keep_weekly")?)
+        .with_keep_monthly(keep("keep_monthly")?);
+
+    let prune = adbackup::prune(&device_id, &policy, dry_run)?;
+    info!("{}", prune);
+
+    Ok(())
+}
+
+fn init(subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let encrypt = subm.map(|subm| subm.is_present("encrypt")).unwrap_or(false);
+
+    let init = adbackup::init(encrypt)?;
+    info!("{}", init);
+
+    Ok(())
+}
+
+fn change_passphrase(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let new_passphrase = read_password_file(param_from_match("new_passphrase_file", matches, subm))?;
+
+    match new_passphrase {
+        Some(new_passphrase) => {
+            let changed = adbackup::change_passphrase(&new_passphrase)?;
+            info!("{}", changed);
+
+            Ok(())
+        }
+        None => Err(err_msg("New passphrase not specified")), // is not possible from cmd because it is required
+    }
+}
+
+fn export(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let device_id = param_from_match("device", matches, subm);
+    let bundle = param_from_match("bundle", matches, subm);
+
+    let device_id = match device_id {
+        Some(id) => String::from(id),
+        None => adbackup::get_device_id()?
+    };
+
+    let versions = match subm.and_then(|subm| subm.values_of("versions")) {
+        Some(versions) => versions.map(parse_backup_version).collect::<Result<Vec<u32>, Error>>()?,
+        None => Vec::new(),
+    };
+
+    match bundle {
+        Some(bundle) => {
+            let export = adbackup::export(&device_id, &versions, bundle)?;
+            info!("{}", export);
+
+            Ok(())
+        }
+        None => Err(err_msg("Bundle not specified")), // is not possible from cmd because it is required
+    }
+}
+
+fn import(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
+    match param_from_match("bundle", matches, subm) {
+        Some(bundle) => {
+            let import = adbackup::import(bundle)?;
+            info!("{}", import);
+
+            Ok(())
+        }
+        None => Err(err_msg("Bundle not specified")), // is not possible from cmd because it is required
+    }
+}
+
+fn check(subm: Option<&ArgMatches>) -> Result<(), Error> {
+    let repair = subm.map(|subm| subm.is_present("repair")).unwrap_or(false);
+
+    let check = adbackup::check(repair)?;
+    info!("{}", check);
+
+    Ok(())
+}
 
 fn pull(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
     let device_id = param_from_match("device", matches, subm);
@@ -226,6 +753,24 @@ fn push(matches: &ArgMatches, subm: Option<&ArgMatches>) -> Result<(), Error> {
     Err(err_msg("Source or target not specified")) // is not possible from cmd because it is required
 }
 
+fn parse_backup_version(version: &str) -> Result<u32, Error> {
+    version
+        .parse::<u32>()
+        .map_err(|_| err_msg(format!("Invalid backup version: {}", version)))
+}
+
+fn read_password_file(password_file: Option<&str>) -> Result<Option<String>, Error> {
+    match password_file {
+        Some(password_file) => {
+            let mut password = String::new();
+            File::open(password_file)?.read_to_string(&mut password)?;
+
+            Ok(Some(String::from(password.trim_end_matches(|c| c == '\r' || c == '\n'))))
+        }
+        None => Ok(None),
+    }
+}
+
 fn param_from_match<'a>(
     param: &'a str,
     matches: &'a ArgMatches,
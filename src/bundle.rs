@@ -0,0 +1,342 @@
+use failure::Error;
+use std::fs::remove_file;
+use std::path::{Path, PathBuf};
+
+use database::management::{DatabaseError, DatabaseManager, Integrity, StoredBackup};
+use store::BackupStore;
+
+#[derive(Debug, Fail)]
+pub enum BundleError {
+    #[fail(display = "{} already exists", path)]
+    AlreadyExists {
+        path: String,
+    },
+
+    #[fail(display = "{} does not exist", path)]
+    NotFound {
+        path: String,
+    },
+
+    #[fail(display = "backup version {} of device {} in the bundle does not match its hash", version, device)]
+    Corrupt {
+        device: String,
+        version: u32,
+    },
+}
+
+/// What was imported from a bundle for one device.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImportedDevice {
+    pub device: String,
+    pub imported: u32,
+    /// versions which are in the repository already
+    pub skipped: u32,
+}
+
+/// Writes `versions` of the backups of `device` in `store`, all of them if empty, into a
+/// new bundle at `path`. Returns the number of exported versions.
+///
+/// A bundle is a database as the sqlite store keeps it, holding only the exported versions
+/// together with their metadata. So it describes itself, and bundles written by an older
+/// adbackup are migrated like any database when they are imported. The backups in a
+/// bundle are not encrypted, even if they are in the repository.
+pub fn export(store: &dyn BackupStore, device: &str, versions: &[u32], path: &Path) -> Result<u32, Error> {
+    if path.exists() {
+        return Err(Error::from(BundleError::AlreadyExists { path: path.to_string_lossy().into_owned() }));
+    }
+
+    let mut backups = store.list(device)?;
+    if !versions.is_empty() {
+        if let Some(&version) = versions.iter().find(|version| !backups.iter().any(|backup| backup.version == **version)) {
+            return Err(Error::from(DatabaseError::BackupVersionNotFound { version }));
+        }
+        backups.retain(|backup| versions.contains(&backup.version));
+    }
+
+    let exported = DatabaseManager::open_file(path, device).and_then(|bundle| {
+        let backup_file = part_path(path);
+
+        for backup in &backups {
+            let copied = store
+                .get(device, backup.version, &backup_file)
+                .and_then(|_| bundle.insert_data(&backup_file.to_string_lossy(), backup.metadata.as_ref()));
+            if backup_file.exists() {
+                remove_file(&backup_file)?;
+            }
+            copied?;
+        }
+
+        Ok(backups.len() as u32)
+    });
+
+    // an incomplete bundle is not left behind
+    if exported.is_err() && path.exists() {
+        remove_file(path)?;
+    }
+
+    exported
+}
+
+/// Merges the backups of all devices in the bundle at `path` into `store`. Versions already
+/// in the repository, i.e. of which a backup with the same hash is stored, are skipped. Where
+/// the store does not know the hash, a backup with the same metadata counts as the same.
+/// Nothing is imported unless all backups in the bundle match their hashes.
+pub fn import(store: &dyn BackupStore, path: &Path) -> Result<Vec<ImportedDevice>, Error> {
+    if !path.is_file() {
+        return Err(Error::from(BundleError::NotFound { path: path.to_string_lossy().into_owned() }));
+    }
+
+    let devices = DatabaseManager::list_devices(path)?;
+    for device in &devices {
+        let bundle = DatabaseManager::open_file(path, device)?;
+
+        if let Some(corrupt) = bundle.verify()?.into_iter().find(|backup| backup.integrity != Integrity::Intact) {
+            return Err(Error::from(BundleError::Corrupt { device: device.clone(), version: corrupt.version }));
+        }
+    }
+
+    devices.into_iter().map(|device| import_device(store, path, device)).collect()
+}
+
+fn import_device(store: &dyn BackupStore, path: &Path, device: String) -> Result<ImportedDevice, Error> {
+    let bundle = DatabaseManager::open_file(path, &device)?;
+    let stored = store.list(&device)?;
+
+    let backup_file = part_path(path);
+    let mut imported = ImportedDevice { device, imported: 0, skipped: 0 };
+
+    for backup in bundle.list_backups()? {
+        if stored.iter().any(|stored| is_same_backup(stored, &backup)) {
+            imported.skipped += 1;
+            continue;
+        }
+
+        let copied = bundle
+            .get_backup(backup.version, &backup_file.to_string_lossy())
+            .and_then(|_| store.put(&imported.device, &backup_file, backup.metadata.as_ref()));
+        if backup_file.exists() {
+            remove_file(&backup_file)?;
+        }
+        copied?;
+
+        imported.imported += 1;
+    }
+
+    Ok(imported)
+}
+
+fn is_same_backup(stored: &StoredBackup, backup: &StoredBackup) -> bool {
+    match (&stored.data_hash, &backup.data_hash) {
+        (Some(stored_hash), Some(hash)) => stored_hash == hash,
+        _ => stored.metadata.is_some() && stored.metadata == backup.metadata,
+    }
+}
+
+// the backup copied between the store and the bundle, next to the bundle
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_os_string();
+    part.push(".part");
+
+    PathBuf::from(part)
+}
+
+#[cfg(test)]
+mod tests {
+    use bundle::{export, import, BundleError, ImportedDevice};
+    use database::management::StoredBackup;
+    use database::metadata::BackupMetadata;
+    use hamcrest::prelude::*;
+    use rusqlite::Connection;
+    use std::fs::{copy, create_dir_all, remove_dir_all, remove_file, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use store::directory::DirectoryStore;
+    use store::sqlite::SqliteStore;
+    use store::BackupStore;
+
+    fn metadata(created_at: &str) -> BackupMetadata {
+        BackupMetadata {
+            created_at: String::from(created_at),
+            device_serial: String::from("emulator-5554"),
+            device_model: Some(String::from("Pixel")),
+            android_version: Some(String::from("8.1.0")),
+            applications: false,
+            shared_storage: false,
+            system_apps: false,
+            packages: Vec::new(),
+            adbackup_version: String::from("0.5.2"),
+            duration: 1000,
+            size: 3,
+            device_info: None,
+            excluded_packages: Vec::new(),
+            harvested_apps: Vec::new(),
+            summary: None,
+        }
+    }
+
+    fn content(store: &dyn BackupStore, device: &str, version: u32, file: &Path) -> Vec<u8> {
+        store.get(device, version, file).unwrap();
+
+        let mut content = Vec::new();
+        File::open(file).unwrap().read_to_end(&mut content).unwrap();
+        remove_file(file).unwrap();
+
+        content
+    }
+
+    fn created(backups: &[StoredBackup]) -> Vec<String> {
+        backups.iter().map(|backup| backup.date_created.clone()).collect()
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let directory = Path::new("24fbafd3288ecad16eb56042f7f0f138"); // md5 of 'test_export_and_import'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        let source = SqliteStore::open(&directory.join("source")).unwrap();
+        let target = DirectoryStore::open(&directory.join("target")).unwrap();
+        let bundle = directory.join("bundle.adbk");
+        let file = directory.join("backup.ab");
+
+        for (data, created_at) in vec![
+            (vec![00, 01, 02], "2018-06-01 08:00:00"),
+            (vec![03, 04, 05], "2018-06-02 08:00:00"),
+            (vec![06, 07, 08], "2018-06-03 08:00:00"),
+        ] {
+            File::create(&file).unwrap().write_all(&data).unwrap();
+            source.put("0123456789ABCDEF", &file, Some(&metadata(created_at))).unwrap();
+        }
+
+        assert_that!(
+            export(&source, "0123456789ABCDEF", &[4], &bundle).unwrap_err().to_string(),
+            is(equal_to(String::from("no backup with version 4 found")))
+        );
+        assert!(!bundle.exists());
+
+        assert_that!(export(&source, "0123456789ABCDEF", &[1, 3], &bundle).unwrap(), is(equal_to(2)));
+        assert!(export(&source, "0123456789ABCDEF", &[], &bundle).is_err());
+
+        assert_that!(
+            import(&target, &bundle).unwrap(),
+            is(equal_to(vec![ImportedDevice { device: String::from("0123456789ABCDEF"), imported: 2, skipped: 0 }]))
+        );
+        let imported = target.list("0123456789ABCDEF").unwrap();
+        assert_that!(
+            created(&imported),
+            is(equal_to(vec![String::from("2018-06-01 08:00:00"), String::from("2018-06-03 08:00:00")]))
+        );
+        assert_that!(imported[1].metadata.clone(), is(equal_to(Some(metadata("2018-06-03 08:00:00")))));
+        assert_that!(content(&target, "0123456789ABCDEF", 2, &file), is(equal_to(vec![06, 07, 08])));
+
+        // importing the bundle again does not duplicate the backups
+        assert_that!(
+            import(&target, &bundle).unwrap(),
+            is(equal_to(vec![ImportedDevice { device: String::from("0123456789ABCDEF"), imported: 0, skipped: 2 }]))
+        );
+        assert_that!(target.list("0123456789ABCDEF").unwrap().len(), is(equal_to(2)));
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_import_bundle_without_metadata() {
+        let directory = Path::new("2301df8219b4fa96631b49d0ac8b682d"); // md5 of 'test_import_bundle_without_metadata'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        let source = SqliteStore::open(&directory.join("source")).unwrap();
+        let bundle = directory.join("bundle.adbk");
+        let file = directory.join("backup.ab");
+
+        for data in vec![vec![00, 01, 02], vec![03, 04, 05]] {
+            File::create(&file).unwrap().write_all(&data).unwrap();
+            source.put("0123456789ABCDEF", &file, None).unwrap();
+        }
+        export(&source, "0123456789ABCDEF", &[], &bundle).unwrap();
+
+        // backups without metadata are known by their hash
+        let targets: Vec<Box<dyn BackupStore>> = vec![
+            Box::new(SqliteStore::open(&directory.join("sqlite")).unwrap()),
+            Box::new(DirectoryStore::open(&directory.join("directory")).unwrap()),
+        ];
+        for target in &targets {
+            assert_that!(
+                import(&**target, &bundle).unwrap(),
+                is(equal_to(vec![ImportedDevice { device: String::from("0123456789ABCDEF"), imported: 2, skipped: 0 }]))
+            );
+            assert_that!(
+                import(&**target, &bundle).unwrap(),
+                is(equal_to(vec![ImportedDevice { device: String::from("0123456789ABCDEF"), imported: 0, skipped: 2 }]))
+            );
+            assert_that!(target.list("0123456789ABCDEF").unwrap().len(), is(equal_to(2)));
+            assert_that!(content(&**target, "0123456789ABCDEF", 2, &file), is(equal_to(vec![03, 04, 05])));
+        }
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_import_corrupt_bundle() {
+        let directory = Path::new("1ccbd4cb9b938de6af3840a7aefd9acf"); // md5 of 'test_import_corrupt_bundle'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        let source = SqliteStore::open(&directory.join("source")).unwrap();
+        let target = SqliteStore::open(&directory.join("target")).unwrap();
+        let bundle = directory.join("bundle.adbk");
+        let file = directory.join("backup.ab");
+
+        File::create(&file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+        source.put("0123456789ABCDEF", &file, Some(&metadata("2018-06-01 08:00:00"))).unwrap();
+        export(&source, "0123456789ABCDEF", &[], &bundle).unwrap();
+
+        Connection::open(&bundle).unwrap()
+            .execute("UPDATE chunks SET data = X'000103', codec = 'none'", &[])
+            .unwrap();
+
+        let error = import(&target, &bundle).unwrap_err();
+        assert!(error.downcast_ref::<BundleError>().is_some());
+        assert_that!(
+            error.to_string(),
+            is(equal_to(String::from(
+                "backup version 1 of device 0123456789ABCDEF in the bundle does not match its hash"
+            )))
+        );
+        assert!(target.list("0123456789ABCDEF").unwrap().is_empty());
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_import_bundle_of_older_version() {
+        let directory = Path::new("aae62921d3059327a659f8954b95c109"); // md5 of 'test_import_bundle_of_older_version'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        // a bundle written before chunks were compressed
+        let bundle = directory.join("bundle.adbk");
+        copy("tests/test_databases/dummy_db_v4.db", &bundle).unwrap();
+        Connection::open(&bundle).unwrap().execute_batch(
+            "INSERT INTO devices (id, serial) VALUES (1, '0123456789ABCDEF');
+            INSERT INTO chunks VALUES
+                ('ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc', X'000102');
+            INSERT INTO device_data (device, data_hash, version, date_created) VALUES
+                (1, 'ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc', 1, '2018-06-01 08:00:00');
+            INSERT INTO backup_chunks VALUES
+                (1, 1, 0, 'ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc');"
+        ).unwrap();
+
+        let target = SqliteStore::open(&directory.join("target")).unwrap();
+        assert_that!(
+            import(&target, &bundle).unwrap(),
+            is(equal_to(vec![ImportedDevice { device: String::from("0123456789ABCDEF"), imported: 1, skipped: 0 }]))
+        );
+        assert_that!(
+            content(&target, "0123456789ABCDEF", 1, &directory.join("backup.ab")),
+            is(equal_to(vec![00, 01, 02]))
+        );
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use database::migration::CURRENT_VERSION;
+use rusqlite::{Connection, Error as SqliteError};
+use failure::Error;
+use std::fmt;
+
+/// Something wrong with a repository, as found by checking it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Problem {
+    /// reported by sqlite's integrity check, can not be repaired by adbackup
+    Damaged { message: String },
+    /// the database was written by another adbackup, one of a newer version can not be
+    /// repaired
+    WrongVersion { version: u32 },
+    /// rows of a version whose backup was never stored completely
+    OrphanedVersion { device: String, version: u32 },
+    /// chunks no version refers to
+    OrphanedChunks { count: u32 },
+    /// the stored data does not match its hash
+    Corrupt { device: String, version: u32 },
+    /// data or chunks of the version are missing
+    Truncated { device: String, version: u32 },
+    /// the stored data is no android backup
+    InvalidHeader { device: String, version: u32, error: String },
+}
+
+impl Problem {
+    /// The device and version of the backup which is broken, broken backups are moved into
+    /// the quarantine when repairing.
+    pub fn broken_backup(&self) -> Option<(&str, u32)> {
+        match *self {
+            Problem::Corrupt { ref device, version }
+            | Problem::Truncated { ref device, version }
+            | Problem::InvalidHeader { ref device, version, .. } => Some((device, version)),
+            _ => None,
+        }
+    }
+
+    pub fn is_repairable(&self) -> bool {
+        match *self {
+            Problem::Damaged { .. } => false,
+            Problem::WrongVersion { version } => version < CURRENT_VERSION,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::Damaged { ref message } => write!(f, "database is damaged: {}", message),
+            Problem::WrongVersion { version } => write!(
+                f,
+                "database version {} differs from the current version {}",
+                version, CURRENT_VERSION
+            ),
+            Problem::OrphanedVersion { ref device, version } => write!(
+                f,
+                "Version: {} of device {}, left over by a backup which was not finished",
+                version, device
+            ),
+            Problem::OrphanedChunks { count } => write!(f, "{} chunk(s) belong to no backup", count),
+            Problem::Corrupt { ref device, version } => write!(
+                f,
+                "Version: {} of device {}, data does not match its hash, the backup is corrupted",
+                version, device
+            ),
+            Problem::Truncated { ref device, version } => write!(
+                f,
+                "Version: {} of device {}, data is missing, the backup is truncated",
+                version, device
+            ),
+            Problem::InvalidHeader { ref device, version, ref error } => write!(
+                f,
+                "Version: {} of device {}, invalid header: {}",
+                version, device, error
+            ),
+        }
+    }
+}
+
+/// What sqlite's integrity check reports, nothing if the database is fine.
+pub fn integrity_problems(connection: &Connection) -> Result<Vec<Problem>, Error> {
+    let mut statement = connection.prepare("PRAGMA integrity_check")?;
+
+    let messages = statement
+        .query_map(&[], |row| row.get(0))?
+        .collect::<Result<Vec<String>, SqliteError>>()?;
+
+    Ok(messages
+        .into_iter()
+        .filter(|message| message != "ok")
+        .map(|message| Problem::Damaged { message })
+        .collect())
+}
+
+/// The versions having chunks or metadata but no data, as left by an interrupted backup.
+pub fn orphaned_versions(connection: &Connection) -> Result<Vec<Problem>, Error> {
+    let mut statement = connection.prepare(
+        "SELECT IFNULL(devices.serial, CAST(parts.device AS TEXT)), parts.version FROM (
+            SELECT device, version FROM backup_chunks
+            UNION SELECT device, version FROM backup_metadata
+        ) parts
+        LEFT JOIN devices ON devices.id = parts.device
+        WHERE NOT EXISTS (SELECT 1 FROM device_data
+            WHERE device_data.device = parts.device AND device_data.version = parts.version)
+        ORDER BY parts.device, parts.version",
+    )?;
+
+    let orphaned = statement
+        .query_map(&[], |row| Problem::OrphanedVersion { device: row.get(0), version: row.get(1) })?
+        .collect::<Result<Vec<Problem>, SqliteError>>()?;
+
+    Ok(orphaned)
+}
+
+pub fn orphaned_chunks(connection: &Connection) -> Result<u32, Error> {
+    Ok(connection.query_row(
+        "SELECT COUNT(*) FROM chunks WHERE hash NOT IN (SELECT chunk_hash FROM backup_chunks)",
+        &[],
+        |row| row.get(0),
+    )?)
+}
+
+/// Removes the rows of orphaned versions and the chunks no version refers to.
+pub fn delete_orphans(connection: &Connection) -> Result<(), Error> {
+    connection.execute_batch(
+        "DELETE FROM backup_chunks WHERE NOT EXISTS (SELECT 1 FROM device_data
+            WHERE device_data.device = backup_chunks.device AND device_data.version = backup_chunks.version);
+        DELETE FROM backup_metadata WHERE NOT EXISTS (SELECT 1 FROM device_data
+            WHERE device_data.device = backup_metadata.device AND device_data.version = backup_metadata.version);
+        DELETE FROM chunks WHERE hash NOT IN (SELECT chunk_hash FROM backup_chunks);",
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::io::{self, Read};
+
+pub static MIN_CHUNK_SIZE: usize = 256 * 1024;
+pub static AVERAGE_CHUNK_SIZE: usize = 1024 * 1024;
+pub static MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// seed of the gear table, changing it moves every chunk boundary and so breaks the
+// deduplication against chunks stored before
+static GEAR_SEED: u64 = 0x6164_6261_636b_7570;
+
+/// Splits a stream into content defined chunks: a boundary is set where a rolling (gear)
+/// hash over the last bytes matches a mask, so inserting or removing data only changes
+/// the chunks around it and the following chunks are found again.
+pub struct Chunker<R> {
+    reader: R,
+    gear: [u64; 256],
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+    buffer: Vec<u8>,
+    position: usize,
+    filled: usize,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Chunker::with_sizes(reader, MIN_CHUNK_SIZE, AVERAGE_CHUNK_SIZE, MAX_CHUNK_SIZE)
+    }
+
+    /// `average_size` is rounded up to a power of two.
+    pub fn with_sizes(reader: R, min_size: usize, average_size: usize, max_size: usize) -> Self {
+        Chunker {
+            reader,
+            gear: gear_table(),
+            min_size,
+            max_size,
+            mask: (average_size as u64).next_power_of_two() - 1,
+            buffer: vec![0; 64 * 1024],
+            position: 0,
+            filled: 0,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let mut chunk = Vec::new();
+        let mut hash: u64 = 0;
+
+        loop {
+            if self.position == self.filled {
+                match self.reader.read(&mut self.buffer) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        self.position = 0;
+                        self.filled = read;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            let byte = self.buffer[self.position];
+            self.position += 1;
+            chunk.push(byte);
+
+            hash = (hash << 1).wrapping_add(self.gear[byte as usize]);
+            if (chunk.len() >= self.min_size && hash & self.mask == 0)
+                || chunk.len() >= self.max_size
+            {
+                break;
+            }
+        }
+
+        match chunk.is_empty() {
+            true => None,
+            false => Some(Ok(chunk)),
+        }
+    }
+}
+
+// random values (splitmix64) for each byte the rolling hash is built from
+fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = GEAR_SEED;
+
+    for value in table.iter_mut() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *value = z ^ (z >> 31);
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use database::chunking::Chunker;
+
+    fn pseudo_random_data(length: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::with_sizes(data, 64, 256, 1024)
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_chunks_make_up_the_data() {
+        let data = pseudo_random_data(100_000, 1);
+        let chunks = chunks(&data);
+
+        assert!(chunks.len() > 100_000 / 1024);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 1024));
+        assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.len() >= 64));
+        assert_that!(chunks.concat(), is(equal_to(data)));
+    }
+
+    #[test]
+    fn test_chunks_are_found_again_after_insertion() {
+        let data = pseudo_random_data(100_000, 2);
+        let mut changed = data[..5_000].to_vec();
+        changed.extend(pseudo_random_data(100, 3));
+        changed.extend(&data[5_000..]);
+
+        let original_chunks = chunks(&data);
+        let changed_chunks = chunks(&changed);
+
+        let reused = changed_chunks
+            .iter()
+            .filter(|chunk| original_chunks.contains(chunk))
+            .count();
+
+        // only the chunks around the inserted bytes differ
+        assert!(reused + 3 >= changed_chunks.len());
+    }
+
+    #[test]
+    fn test_small_data_is_one_chunk() {
+        assert_that!(chunks(b"adbackup"), is(equal_to(vec![b"adbackup".to_vec()])));
+        assert!(chunks(b"").is_empty());
+    }
+}
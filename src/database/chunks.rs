@@ -0,0 +1,124 @@
+use database::chunking::Chunker;
+use database::compression::Codec;
+use database::management::{sha256, DatabaseError};
+use rusqlite::{Connection, Error as SqliteError};
+use sha2::{Digest, Sha256};
+use failure::Error;
+use std::io::{self, Cursor, Read};
+use std::vec;
+
+/// Stores the content of `reader` as the chunks of `version` of `device`, compressed with
+/// `codec`. Chunks already stored for any version are only referenced. Returns the sha256
+/// of the whole content.
+pub fn store_chunks<R: Read>(connection: &Connection, device: u32, version: u32, reader: R, codec: Codec) -> Result<String, Error> {
+    let mut content_hash = Sha256::new();
+
+    for (position, chunk) in Chunker::new(reader).enumerate() {
+        let chunk = chunk?;
+        content_hash.update(&chunk);
+
+        let chunk_hash = sha256(&chunk);
+        if !chunk_exists(connection, &chunk_hash)? {
+            insert_chunk(connection, &chunk_hash, &chunk, codec)?;
+        }
+        connection.execute("INSERT INTO backup_chunks (device, version, position, chunk_hash)
+            VALUES (?1, ?2, ?3, ?4)",
+            &[&device, &version, &(position as u32), &chunk_hash])?;
+    }
+
+    Ok(format!("{:x}", content_hash.finalize()))
+}
+
+/// The hashes of the chunks making up `version`, in order. Empty for versions stored as
+/// a single blob.
+pub fn chunk_hashes(connection: &Connection, device: u32, version: u32) -> Result<Vec<String>, Error> {
+    let mut statement = connection.prepare(
+        "SELECT chunk_hash FROM backup_chunks WHERE device = ?1 AND version = ?2 ORDER BY position",
+    )?;
+
+    let hashes = statement
+        .query_map(&[&device, &version], |row| row.get(0))?
+        .collect::<Result<Vec<String>, SqliteError>>()?;
+
+    Ok(hashes)
+}
+
+fn chunk_exists(connection: &Connection, hash: &str) -> Result<bool, Error> {
+    Ok(connection.query_row("SELECT EXISTS (SELECT 1 FROM chunks WHERE hash = ?1)", &[&hash], |row| {
+        row.get(0)
+    })?)
+}
+
+// chunks which do not get smaller, e.g. parts of already compressed files, are stored as
+// they are
+fn insert_chunk(connection: &Connection, hash: &str, chunk: &[u8], codec: Codec) -> Result<(), Error> {
+    let compressed = codec.compress(chunk)?;
+    let (codec, data) = if compressed.len() < chunk.len() {
+        (codec, compressed.as_slice())
+    } else {
+        (Codec::None, chunk)
+    };
+
+    connection.execute("INSERT INTO chunks (hash, data, codec, size) VALUES (?1, ?2, ?3, ?4)",
+        &[&hash, &data, &codec.name(), &(chunk.len() as i64)])?;
+
+    Ok(())
+}
+
+/// The content of the chunk with the sha256 `hash`, decompressed with the codec it was
+/// stored with.
+pub fn load_chunk(connection: &Connection, hash: &str) -> Result<Option<Vec<u8>>, Error> {
+    let chunk = connection.query_row("SELECT data, codec FROM chunks WHERE hash = ?1", &[&hash], |row| {
+        (row.get_checked::<_, Vec<u8>>(0), row.get_checked::<_, String>(1))
+    });
+
+    match chunk {
+        Ok((data, codec)) => Ok(Some(codec?.parse::<Codec>()?.decompress(&data?)?)),
+        Err(SqliteError::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// Reads the content of a version, loading one chunk at a time.
+pub struct ChunkReader<'a> {
+    connection: &'a Connection,
+    hashes: vec::IntoIter<String>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub fn new(connection: &'a Connection, hashes: Vec<String>) -> Self {
+        ChunkReader {
+            connection,
+            hashes: hashes.into_iter(),
+            chunk: Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl<'a> Read for ChunkReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+
+            let hash = match self.hashes.next() {
+                Some(hash) => hash,
+                None => return Ok(0),
+            };
+
+            let chunk = load_chunk(self.connection, &hash)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        DatabaseError::ChunkNotFound { hash: hash.clone() }.to_string(),
+                    )
+                })?;
+
+            self.chunk = Cursor::new(chunk);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use flate2::read::{GzDecoder, GzEncoder};
+use flate2::Compression;
+use failure::{err_msg, Error};
+use std::io::Read;
+use std::str::FromStr;
+
+/// How a chunk is compressed in the database, recorded along with each chunk.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Codec {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+#[derive(Debug, Fail)]
+pub enum CompressionError {
+    #[fail(display = "adbackup was built without {} support", codec)]
+    Unsupported {
+        codec: &'static str,
+    },
+}
+
+impl Codec {
+    /// The name the codec is recorded and selected by.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Codec::None => "none",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut compressed = Vec::new();
+
+        match *self {
+            Codec::None => compressed.extend_from_slice(data),
+            Codec::Gzip => {
+                GzEncoder::new(data, Compression::default()).read_to_end(&mut compressed)?;
+            }
+            Codec::Zstd => compressed = zstd_compress(data)?,
+            Codec::Xz => compressed = xz_compress(data)?,
+        }
+
+        Ok(compressed)
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut decompressed = Vec::new();
+
+        match *self {
+            Codec::None => decompressed.extend_from_slice(data),
+            Codec::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            Codec::Zstd => decompressed = zstd_decompress(data)?,
+            Codec::Xz => decompressed = xz_decompress(data)?,
+        }
+
+        Ok(decompressed)
+    }
+}
+
+// zstd if adbackup is built with it, as it compresses about as well as gzip but a lot faster
+impl Default for Codec {
+    #[cfg(feature = "zstd")]
+    fn default() -> Self {
+        Codec::Zstd
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn default() -> Self {
+        Codec::Gzip
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(codec: &str) -> Result<Self, Error> {
+        match codec {
+            "none" => Ok(Codec::None),
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            "xz" => Ok(Codec::Xz),
+            _ => Err(err_msg(format!(
+                "Unknown compression: {}, expected none, gzip, zstd or xz",
+                codec
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(::zstd::stream::encode_all(data, 3)?)
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(::zstd::stream::decode_all(data)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::from(CompressionError::Unsupported { codec: "zstd" }))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::from(CompressionError::Unsupported { codec: "zstd" }))
+}
+
+#[cfg(feature = "xz2")]
+fn xz_compress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut compressed = Vec::new();
+    ::xz2::read::XzEncoder::new(data, 6).read_to_end(&mut compressed)?;
+
+    Ok(compressed)
+}
+
+#[cfg(feature = "xz2")]
+fn xz_decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decompressed = Vec::new();
+    ::xz2::read::XzDecoder::new(data).read_to_end(&mut decompressed)?;
+
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "xz2"))]
+fn xz_compress(_: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::from(CompressionError::Unsupported { codec: "xz" }))
+}
+
+#[cfg(not(feature = "xz2"))]
+fn xz_decompress(_: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(Error::from(CompressionError::Unsupported { codec: "xz" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use database::compression::Codec;
+    use hamcrest::prelude::*;
+
+    #[test]
+    fn test_codec_names() {
+        for codec in &[Codec::None, Codec::Gzip, Codec::Zstd, Codec::Xz] {
+            assert_that!(codec.name().parse::<Codec>().unwrap(), is(equal_to(*codec)));
+        }
+
+        assert_that!(
+            "lz4".parse::<Codec>().unwrap_err().to_string(),
+            is(equal_to(String::from("Unknown compression: lz4, expected none, gzip, zstd or xz")))
+        );
+    }
+
+    #[test]
+    fn test_compress() {
+        let data = b"adbackup adbackup adbackup adbackup adbackup adbackup".repeat(100);
+
+        for codec in &[Codec::None, Codec::Gzip, Codec::Zstd, Codec::Xz] {
+            let compressed = match codec.compress(&data) {
+                Ok(compressed) => compressed,
+                // built without the codec
+                Err(_) => continue,
+            };
+
+            if *codec != Codec::None {
+                assert!(compressed.len() < data.len());
+            }
+            assert_that!(codec.decompress(&compressed).unwrap(), is(equal_to(data.clone())));
+        }
+    }
+}
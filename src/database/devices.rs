@@ -0,0 +1,61 @@
+use rusqlite::{Connection, Error as SqliteError};
+use failure::Error;
+
+/// The id of the device with the hardware serial `serial`, which is added if it is not
+/// known yet.
+pub fn get_or_insert_device(connection: &Connection, serial: &str) -> Result<u32, Error> {
+    connection.execute("INSERT OR IGNORE INTO devices (serial) VALUES (?1)", &[&serial])?;
+
+    connection.query_row("SELECT id FROM devices WHERE serial = ?1", &[&serial], |row| {
+        row.get_checked(0)
+    })?.map_err(|e| {
+        Error::from(e)
+    })
+}
+
+/// The id of the device with the hardware serial `serial`, none if it is not known.
+pub fn get_device(connection: &Connection, serial: &str) -> Result<Option<u32>, Error> {
+    match connection.query_row("SELECT id FROM devices WHERE serial = ?1", &[&serial], |row| row.get_checked(0)) {
+        Ok(device) => Ok(Some(device?)),
+        Err(SqliteError::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// The hardware serial of the device known by its hardware serial or the adb serial it
+/// was reached with last.
+pub fn find_device(connection: &Connection, device_id: &str) -> Result<Option<String>, Error> {
+    match connection.query_row(
+        "SELECT serial FROM devices WHERE serial = ?1 OR adb_serial = ?1
+            ORDER BY serial = ?1 DESC LIMIT 1",
+        &[&device_id],
+        |row| row.get_checked(0),
+    ) {
+        Ok(serial) => Ok(Some(serial?)),
+        Err(SqliteError::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// The hardware serials of the devices having backups.
+pub fn list_devices(connection: &Connection) -> Result<Vec<String>, Error> {
+    let mut statement = connection.prepare(
+        "SELECT serial FROM devices WHERE EXISTS (SELECT 1 FROM device_data WHERE device = devices.id)
+            ORDER BY serial",
+    )?;
+
+    let serials = statement
+        .query_map(&[], |row| row.get(0))?
+        .collect::<Result<Vec<String>, SqliteError>>()?;
+
+    Ok(serials)
+}
+
+pub fn update_device(connection: &Connection, device: u32, adb_serial: &str, model: Option<&str>) -> Result<(), Error> {
+    connection.execute(
+        "UPDATE devices SET adb_serial = ?2, model = IFNULL(?3, model) WHERE id = ?1",
+        &[&device, &adb_serial, &model],
+    )?;
+
+    Ok(())
+}
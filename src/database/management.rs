@@ -1,24 +1,215 @@
-use database::migration::DatabaseMigrator;
-use database::rusqlite::Connection;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use android_backup::header::{BackupHeader, Encryption};
+use database::check::{delete_orphans, integrity_problems, orphaned_chunks, orphaned_versions, Problem};
+use database::chunks::{chunk_hashes, load_chunk, store_chunks, ChunkReader};
+use database::compression::Codec;
+use database::devices::{find_device, get_device, get_or_insert_device, list_devices, update_device};
+use database::metadata::{get_metadata, insert_metadata, BackupMetadata};
+use database::migration::{DatabaseMigrator, CURRENT_VERSION};
+use rusqlite::blob::Blob;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, DatabaseName, Error as SqliteError, OpenFlags};
+use sha2::{Digest, Sha256};
+use chrono::NaiveDateTime;
 use failure::{Error, err_msg};
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
 use std::fs::File;
 use std::path::Path;
 
+// hash of the versions stored before their content was hashed
+static UNHASHED: &str = "const_hash";
+
+// versions stored as a single blob with the same content as an earlier one store no
+// data of their own, only its hash, so their data is looked up by the hash
+static BACKUP_DATA: &str = "COALESCE(data, (SELECT blob.data FROM device_data blob
+    WHERE blob.data_hash = device_data.data_hash AND blob.data IS NOT NULL LIMIT 1))";
+
+#[derive(Debug, Fail)]
+pub enum DatabaseError {
+    #[fail(display = "no backup with version {} found", version)]
+    BackupVersionNotFound {
+        version: u32,
+    },
+
+    #[fail(display = "no backup created before {} found", date)]
+    NoBackupBefore {
+        date: String,
+    },
+
+    #[fail(display = "data of backup version {} is missing", version)]
+    BackupDataMissing {
+        version: u32,
+    },
+
+    #[fail(display = "chunk {} of a backup is missing", hash)]
+    ChunkNotFound {
+        hash: String,
+    },
+
+    #[fail(display = "unknown device: {}", serial)]
+    UnknownDevice {
+        serial: String,
+    },
+}
+
+/// A backup version stored in the database, without its data.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StoredBackup {
+    pub version: u32,
+    pub date_created: String,
+    /// size of the stored content, which for compressed backups is stored inflated
+    pub size: u64,
+    /// size the content takes in the store, including chunks shared with other versions
+    pub stored_size: u64,
+    /// sha256 of the backup as it was stored, if the store knows it
+    pub data_hash: Option<String>,
+    pub metadata: Option<BackupMetadata>,
+}
+
+impl StoredBackup {
+    /// When the backup was stored (UTC), unknown for versions stored before the date was
+    /// recorded along with the time.
+    pub fn created(&self) -> Option<NaiveDateTime> {
+        let created = self.metadata
+            .as_ref()
+            .map(|metadata| metadata.created_at.as_str())
+            .unwrap_or(&self.date_created);
+
+        NaiveDateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S").ok()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Integrity {
+    Intact,
+    /// the stored data does not match its hash anymore
+    Corrupt,
+    /// the version refers to data which is not stored
+    Missing,
+    /// stored before hashes were recorded, there is nothing to compare the data with
+    Unhashed,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VerifiedBackup {
+    pub version: u32,
+    pub integrity: Integrity,
+}
+
 pub struct DatabaseManager {
     _version: u32,
     connection: Connection,
     name: String,
+    device: u32,
+    serial: String,
+    codec: Codec,
 }
 
 impl DatabaseManager {
-    pub fn open_connection(name: &str) -> Result<DatabaseManager, Error> {
+    /// Opens the database holding the backups of all devices, for the backups of the device
+    /// with the hardware serial `device_serial`. A device not known yet is added.
+    pub fn open_connection(name: &str, device_serial: &str) -> Result<DatabaseManager, Error> {
+        let (conn, version, database_name) = Self::open_database(name)?;
+        let device = get_or_insert_device(&conn, device_serial)?;
+
+        Ok(DatabaseManager {connection: conn, _version: version, name: database_name, device, serial: String::from(device_serial), codec: Codec::default()} )
+    }
+
+    /// Opens the database holding the backups of all devices, for the backups of the known
+    /// device with the hardware serial `device_serial`. An unknown device is not added but
+    /// an error.
+    pub fn open_known_device(name: &str, device_serial: &str) -> Result<DatabaseManager, Error> {
+        let (conn, version, database_name) = Self::open_database(name)?;
+        let device = get_device(&conn, device_serial)?.ok_or_else(|| {
+            Error::from(DatabaseError::UnknownDevice { serial: String::from(device_serial) })
+        })?;
+
+        Ok(DatabaseManager {connection: conn, _version: version, name: database_name, device, serial: String::from(device_serial), codec: Codec::default()} )
+    }
+
+    // for checking the backups of a known device, the database is left as it is
+    fn open_read_only(name: &str, device_serial: &str, version: u32) -> Result<DatabaseManager, Error> {
+        let conn = Connection::open_with_flags(name, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let device = get_device(&conn, device_serial)?.ok_or_else(|| {
+            Error::from(DatabaseError::UnknownDevice { serial: String::from(device_serial) })
+        })?;
+
+        Ok(DatabaseManager {
+            connection: conn,
+            _version: version,
+            name: String::from(name),
+            device,
+            serial: String::from(device_serial),
+            codec: Codec::default(),
+        })
+    }
+
+    /// Opens the database in the file `path` as it is named, e.g. an exported bundle, for
+    /// the backups of the device with the hardware serial `device_serial`.
+    pub fn open_file(path: &Path, device_serial: &str) -> Result<DatabaseManager, Error> {
+        let (conn, version) = Self::connect(&path.to_string_lossy())?;
+        let device = get_or_insert_device(&conn, device_serial)?;
+
+        Ok(DatabaseManager {
+            connection: conn,
+            _version: version,
+            name: path.to_string_lossy().into_owned(),
+            device,
+            serial: String::from(device_serial),
+            codec: Codec::default(),
+        })
+    }
+
+    /// The hardware serials of the devices having backups in the database in the file
+    /// `path`.
+    pub fn list_devices(path: &Path) -> Result<Vec<String>, Error> {
+        let (conn, _) = Self::connect(&path.to_string_lossy())?;
+
+        list_devices(&conn)
+    }
+
+    /// Compresses the chunks of the backups inserted from now on with `codec`.
+    pub fn with_codec(self, codec: Codec) -> Self {
+        DatabaseManager { codec, ..self }
+    }
+
+    /// The hardware serial of the known device with the hardware serial or the adb serial
+    /// `device_id`, devices are only known once a backup of them was stored.
+    pub fn find_device(name: &str, device_id: &str) -> Result<Option<String>, Error> {
+        let (conn, _, _) = Self::open_database(name)?;
+
+        find_device(&conn, device_id)
+    }
+
+    /// The number of devices having backups and of all backups in the database.
+    pub fn count_backups(name: &str) -> Result<(u32, u32), Error> {
+        let (conn, _, _) = Self::open_database(name)?;
+
+        let counts = conn.query_row(
+            "SELECT COUNT(DISTINCT device), COUNT(*) FROM device_data",
+            &[],
+            |row| (row.get(0), row.get(1)),
+        )?;
+
+        Ok(counts)
+    }
+
+    fn open_database(name: &str) -> Result<(Connection, u32, String), Error> {
         let database_name = match name.ends_with(".db") {
             true => String::from(name),
             false => format!("{}.db", name)
         };
         
-        let conn = Connection::open(&database_name)?;
+        let (conn, version) = Self::connect(&database_name)?;
+
+        Ok((conn, version, database_name))
+    }
+
+    // databases of an older version are migrated to the current one
+    fn connect(database_name: &str) -> Result<(Connection, u32), Error> {
+        let conn = Connection::open(database_name)?;
         let version = match DatabaseMigrator::get_database_version(&conn) {
             Ok(v) => v,
             Err(_) => 0 // FIXME v1 find a way to only do this if e contains a SqliteFailure with string "table not found"
@@ -26,64 +217,629 @@ impl DatabaseManager {
     
         DatabaseMigrator::migrate(&conn, version)?;
 
-        Ok(DatabaseManager {connection: conn, _version: version, name: database_name} )
+        Ok((conn, version))
+    }
+
+    /// Records how the device was reached last.
+    pub fn update_device(&self, adb_serial: &str, model: Option<&str>) -> Result<(), Error> {
+        update_device(&self.connection, self.device, adb_serial, model)
+    }
+
+    /// Moves the backups of a database kept for a single device, as stored before all
+    /// devices shared one database, to this device. Returns the number of moved backups.
+    pub fn import_device_database(&self, device_database: &str) -> Result<u32, Error> {
+        // the old database is brought to the current schema, where its backups belong to
+        // this device
+        {
+            let conn = Connection::open(device_database)?;
+            let version = DatabaseMigrator::get_database_version(&conn).unwrap_or(0);
+            DatabaseMigrator::migrate_device_database(&conn, version, Some(&self.serial))?;
+        }
+
+        let latest = self.latest_version()?;
+        self.connection.execute("ATTACH DATABASE ?1 AS device_database", &[&device_database])?;
+
+        self.connection.execute_batch("BEGIN")?;
+        let imported = self.connection.execute_batch(&format!(
+            "INSERT OR IGNORE INTO chunks SELECT hash, data, codec, size FROM device_database.chunks;
+            INSERT INTO device_data (device, data_hash, version, data, date_created, payload_inflated)
+                SELECT {device}, data_hash, version + {latest}, data, date_created, payload_inflated
+                FROM device_database.device_data;
+            INSERT INTO backup_chunks
+                SELECT {device}, version + {latest}, position, chunk_hash
+                FROM device_database.backup_chunks;
+            INSERT INTO backup_metadata
+                SELECT {device}, version + {latest}, created_at, device_serial, device_model,
+                    android_version, applications, shared_storage, system_apps, packages,
+                    adbackup_version, duration, size, device_info, excluded_packages, harvested_apps,
+                    summary
+                FROM device_database.backup_metadata;",
+            device = self.device,
+            latest = latest
+        ));
+
+        let committed = match imported {
+            Ok(_) => self.connection.execute_batch("COMMIT"),
+            Err(e) => self.connection.execute_batch("ROLLBACK").and(Err(e)),
+        };
+        self.connection.execute("DETACH DATABASE device_database", &[])?;
+        committed?;
+
+        Ok(self.latest_version()? - latest)
     }
 
-    pub fn insert_data(&self, input_file: &str) -> Result<(), Error> {
+    fn latest_version(&self) -> Result<u32, Error> {
+        self.connection.query_row(
+            "SELECT IFNULL(MAX(version), 0) FROM device_data WHERE device = ?1",
+            &[&self.device],
+            |row| {
+                row.get_checked(0)
+            })?.map_err(|e| {
+                Error::from(e)
+            })
+    }
+
+    /// Stores a backup, together with what it was made from if known. Returns the version
+    /// of the stored backup.
+    pub fn insert_data(&self, input_file: &str, metadata: Option<&BackupMetadata>) -> Result<u32, Error> {
         if !Path::new(&self.name).exists() {
             return Err(err_msg("Could not open database"));
         }
 
-        // check how many files there are in the sqlite database (versioning)
-        let count: u32 = self.connection.query_row(
-            "SELECT COUNT(version) FROM device_data",
-            &[],
+        // the version follows the latest one, counting the versions would reuse the
+        // version of the latest backup once older ones were pruned (versioning)
+        let version = self.latest_version()? + 1;
+
+        // a version is either stored completely or not at all
+        self.connection.execute_batch("BEGIN")?;
+        let created = metadata
+            .map(|metadata| metadata.created_at.as_str())
+            .filter(|created| !created.is_empty());
+        let inserted = self.insert_chunked(input_file, version, created).and_then(|_| match metadata {
+            Some(metadata) => insert_metadata(&self.connection, self.device, version, metadata),
+            None => Ok(()),
+        });
+
+        match inserted {
+            Ok(_) => self.connection.execute_batch("COMMIT")?,
+            Err(e) => {
+                self.connection.execute_batch("ROLLBACK")?;
+                return Err(e);
+            }
+        }
+
+        Ok(version)
+    }
+
+    // the content is split into chunks, which are only stored if no version stored them
+    // before. The payload of compressed, unencrypted android backups is stored inflated,
+    // as the compressed stream of an almost unchanged backup differs from the start.
+    fn insert_chunked(&self, input_file: &str, version: u32, created: Option<&str>) -> Result<(), Error> {
+        let (data_hash, inflated) = match self.insert_inflated(input_file, version)? {
+            Some(data_hash) => (data_hash, true),
+            // not a compressed android backup, or its payload can not be inflated, e.g.
+            // because the backup was cancelled on the device
+            None => (store_chunks(&self.connection, self.device, version, File::open(input_file)?, self.codec)?, false),
+        };
+
+        // date_created defaults to CURRENT_TIME, which lacks the date, so the UTC date and
+        // time are set explicitly
+        self.connection.execute("INSERT INTO device_data (device, data_hash, version, date_created, payload_inflated)
+            VALUES (?1, ?2, ?3, IFNULL(?5, strftime('%Y-%m-%d %H:%M:%S', 'now')), ?4)",
+            &[&self.device, &data_hash, &version, &inflated, &created])?;
+
+        Ok(())
+    }
+
+    // the header is stored as uncompressed, followed by the inflated payload. The returned
+    // hash is the one of the backup deflated again, which is a valid backup of the same data
+    // but not necessarily the compressed bytes the device wrote.
+    fn insert_inflated(&self, input_file: &str, version: u32) -> Result<Option<String>, Error> {
+        let mut backup_file = BufReader::new(File::open(input_file)?);
+
+        let header = match BackupHeader::read(&mut backup_file) {
+            Ok(ref header) if header.compressed && header.encryption == Encryption::None => {
+                BackupHeader { compressed: false, ..header.clone() }
+            }
+            _ => return Ok(None),
+        };
+
+        self.connection.execute_batch("SAVEPOINT inflate")?;
+
+        let stored = self.store_inflated(backup_file, &header, version);
+        match stored {
+            Ok(Some(_)) => self.connection.execute_batch("RELEASE inflate")?,
+            _ => self.connection.execute_batch("ROLLBACK TO inflate; RELEASE inflate")?,
+        }
+
+        stored
+    }
+
+    fn store_inflated<R: BufRead>(&self, backup_file: R, header: &BackupHeader, version: u32) -> Result<Option<String>, Error> {
+        let mut payload = Inflater::new(backup_file);
+
+        let content = Cursor::new(header.to_bytes()).chain(&mut payload);
+        match store_chunks(&self.connection, self.device, version, content, self.codec) {
+            Ok(_) if !payload.at_end()? => return Ok(None),
+            Ok(_) => {}
+            Err(_) if payload.failed => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let backup_hash = write_backup(self.content(version)?, true, Sha256::new())?;
+        Ok(Some(format!("{:x}", backup_hash.finalize())))
+    }
+
+    pub fn get_latest_version(&self) -> Result<u32, Error> {
+        if !Path::new(&self.name).exists() {
+            return Err(err_msg("Could not open database"));
+        }
+
+        self.connection.query_row(
+            "SELECT version FROM device_data WHERE device = ?1 ORDER BY version DESC LIMIT 1",
+            &[&self.device],
             |row| {
                 row.get_checked(0)
             })?.map_err(|e| {
                 Error::from(e)
-            })?;
+            })
+    }
 
-        // insert file as blob into table (we can use a constant for the data_hash as incremental backups (for which we need to identify single files by their hash) are not yet implementable)
-        let mut backup_file = File::open(input_file)?;
-        let mut file_bytes = Vec::new();
-        backup_file.read_to_end(&mut file_bytes)?;
+    pub fn get_backup(&self, version: u32, output_file: &str) -> Result<(), Error> {
+        if !Path::new(&self.name).exists() {
+            return Err(err_msg("Could not open database"));
+        }
+
+        let inflated: bool = self.connection.query_row(
+            "SELECT payload_inflated FROM device_data WHERE device = ?1 AND version = ?2",
+            &[&self.device, &version],
+            |row| {
+                row.get_checked(0)
+            }).map_err(|e| match e {
+                SqliteError::QueryReturnedNoRows => {
+                    Error::from(DatabaseError::BackupVersionNotFound { version })
+                }
+                e => Error::from(e),
+            })?.map_err(|e| {
+                Error::from(e)
+            })?;
 
-        self.connection.execute("INSERT INTO device_data (data_hash, version, data)
-            VALUES (?1, ?2, ?3)",
-            &[&"const_hash", &(count + 1), &file_bytes])?;
+        let content = self.content(version)?;
+        write_backup(content, inflated, File::create(output_file)?)?;
 
         Ok(())
     }
 
-    pub fn get_latest_backup(&self, output_file: &str) -> Result<(), Error> {
+    // the stored content of a version, read one chunk at a time. Versions stored before the
+    // chunk store keep their data in device_data, which is read incrementally as it can be
+    // larger than the available memory.
+    fn content<'a>(&'a self, version: u32) -> Result<Box<dyn Read + 'a>, Error> {
+        let chunks = chunk_hashes(&self.connection, self.device, version)?;
+        if chunks.is_empty() {
+            return Ok(Box::new(self.open_blob(self.blob_row_id(version)?)?));
+        }
+
+        Ok(Box::new(ChunkReader::new(&self.connection, chunks)))
+    }
+
+    // the row storing the data of a version, which is another version's row if the data
+    // was stored already
+    fn blob_row_id(&self, version: u32) -> Result<i64, Error> {
+        self.connection.query_row(
+            "SELECT rowid FROM device_data WHERE data IS NOT NULL AND ((device = ?1 AND version = ?2)
+                OR data_hash = (SELECT data_hash FROM device_data WHERE device = ?1 AND version = ?2))
+                ORDER BY (device = ?1 AND version = ?2) DESC LIMIT 1",
+            &[&self.device, &version],
+            |row| {
+                row.get_checked(0)
+            }).map_err(|e| match e {
+                SqliteError::QueryReturnedNoRows => {
+                    Error::from(DatabaseError::BackupDataMissing { version })
+                }
+                e => Error::from(e),
+            })?.map_err(|e| {
+                Error::from(e)
+            })
+    }
+
+    fn open_blob<'a>(&'a self, row_id: i64) -> Result<Blob<'a>, Error> {
+        Ok(self.connection.blob_open(DatabaseName::Main, "device_data", "data", row_id, true)?)
+    }
+
+    /// The version of the latest backup created before `date` (UTC). Backups stored
+    /// without a creation date are never considered.
+    pub fn get_version_before(&self, date: &NaiveDateTime) -> Result<u32, Error> {
         if !Path::new(&self.name).exists() {
             return Err(err_msg("Could not open database"));
         }
 
-        // get blob from database and save as file
-        let data: Vec<u8> = self.connection.query_row(
-            "SELECT data FROM device_data ORDER BY version DESC LIMIT 1",
-            &[],
+        let date = date.format("%Y-%m-%d %H:%M:%S").to_string();
+
+        self.connection.query_row(
+            "SELECT version FROM device_data
+                WHERE device = ?1 AND date_created LIKE '____-__-__ __:__:__' AND date_created < ?2
+                ORDER BY version DESC LIMIT 1",
+            &[&self.device, &date],
             |row| {
                 row.get_checked(0)
+            }).map_err(|e| match e {
+                SqliteError::QueryReturnedNoRows => {
+                    Error::from(DatabaseError::NoBackupBefore { date: date.clone() })
+                }
+                e => Error::from(e),
             })?.map_err(|e| {
                 Error::from(e)
-            })?;
+            })
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<StoredBackup>, Error> {
+        if !Path::new(&self.name).exists() {
+            return Err(err_msg("Could not open database"));
+        }
+
+        // versions stored as a single blob are stored uncompressed
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT version, CAST(date_created AS TEXT), IFNULL(length({data}), (
+                SELECT IFNULL(SUM(chunks.size), 0) FROM backup_chunks
+                    JOIN chunks ON chunks.hash = backup_chunks.chunk_hash
+                    WHERE backup_chunks.device = device_data.device
+                        AND backup_chunks.version = device_data.version
+                )), IFNULL(length({data}), (
+                SELECT IFNULL(SUM(length(chunks.data)), 0) FROM backup_chunks
+                    JOIN chunks ON chunks.hash = backup_chunks.chunk_hash
+                    WHERE backup_chunks.device = device_data.device
+                        AND backup_chunks.version = device_data.version
+                )), data_hash FROM device_data WHERE device = ?1 ORDER BY version",
+            data = BACKUP_DATA
+        ))?;
+
+        let backups = statement
+            .query_map(&[&self.device], |row| StoredBackup {
+                version: row.get(0),
+                date_created: row.get(1),
+                size: row.get::<_, i64>(2) as u64,
+                stored_size: row.get::<_, i64>(3) as u64,
+                data_hash: Some(row.get::<_, String>(4)).filter(|data_hash| data_hash != UNHASHED),
+                metadata: None,
+            })?
+            .collect::<Result<Vec<StoredBackup>, SqliteError>>()?;
+
+        backups
+            .into_iter()
+            .map(|backup| {
+                Ok(StoredBackup {
+                    metadata: get_metadata(&self.connection, self.device, backup.version)?,
+                    ..backup
+                })
+            })
+            .collect()
+    }
+
+    /// Hashes the stored data again and compares it with the hash recorded when it was
+    /// inserted.
+    pub fn verify(&self) -> Result<Vec<VerifiedBackup>, Error> {
+        if !Path::new(&self.name).exists() {
+            return Err(err_msg("Could not open database"));
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT version, data_hash, CASE WHEN data IS NULL THEN NULL ELSE rowid END, payload_inflated
+                FROM device_data WHERE device = ?1 ORDER BY version",
+        )?;
+        let rows = statement.query_map(&[&self.device], |row| -> (u32, String, Option<i64>, bool) {
+            (row.get(0), row.get(1), row.get(2), row.get(3))
+        })?;
+
+        let mut stored_hashes: HashMap<String, Integrity> = HashMap::new();
+        let mut verified = Vec::new();
+
+        for row in rows {
+            let (version, data_hash, blob_row_id, inflated) = row?;
+            let chunks = chunk_hashes(&self.connection, self.device, version)?;
+
+            // versions without data of their own usually come after the one storing it,
+            // which may also be a version of another device
+            let blob_row_id = match blob_row_id {
+                None if chunks.is_empty() && !stored_hashes.contains_key(&data_hash) => match self.blob_row_id(version) {
+                    Ok(row_id) => Some(row_id),
+                    Err(ref e) if e.downcast_ref::<DatabaseError>().is_some() => None,
+                    Err(e) => return Err(e),
+                },
+                blob_row_id => blob_row_id,
+            };
+
+            let integrity = match blob_row_id {
+                _ if data_hash == UNHASHED => Integrity::Unhashed,
+                _ if !chunks.is_empty() => self.verify_chunks(chunks, &data_hash, inflated)?,
+                Some(row_id) => {
+                    let mut content_hash = Sha256::new();
+                    io::copy(&mut self.open_blob(row_id)?, &mut content_hash)?;
+
+                    let integrity = match format!("{:x}", content_hash.finalize()) == data_hash {
+                        true => Integrity::Intact,
+                        false => Integrity::Corrupt,
+                    };
+                    stored_hashes.insert(data_hash, integrity.clone());
+                    integrity
+                }
+                None => stored_hashes
+                    .get(&data_hash)
+                    .cloned()
+                    .unwrap_or(Integrity::Missing),
+            };
+
+            verified.push(VerifiedBackup { version, integrity });
+        }
+
+        Ok(verified)
+    }
+
+    /// Removes the given versions together with the chunks no other version refers to.
+    /// The file only shrinks after a `vacuum`.
+    pub fn delete_backups(&self, versions: &[u32]) -> Result<(), Error> {
+        if !Path::new(&self.name).exists() {
+            return Err(err_msg("Could not open database"));
+        }
 
-        let mut file = File::create(output_file)?;
-        file.write_all(&data)?;
+        self.connection.execute_batch("BEGIN")?;
+        let deleted = versions
+            .iter()
+            .map(|&version| self.delete_backup(version))
+            .collect::<Result<Vec<()>, Error>>()
+            .and_then(|_| {
+                self.connection.execute(
+                    "DELETE FROM chunks WHERE hash NOT IN (SELECT chunk_hash FROM backup_chunks)",
+                    &[],
+                )?;
+                Ok(())
+            });
+
+        match deleted {
+            Ok(_) => self.connection.execute_batch("COMMIT")?,
+            Err(e) => {
+                self.connection.execute_batch("ROLLBACK")?;
+                return Err(e);
+            }
+        }
 
         Ok(())
     }
+
+    fn delete_backup(&self, version: u32) -> Result<(), Error> {
+        let backup = [&self.device as &dyn ToSql, &version];
+        self.connection.execute("DELETE FROM backup_metadata WHERE device = ?1 AND version = ?2", &backup)?;
+        self.connection.execute("DELETE FROM backup_chunks WHERE device = ?1 AND version = ?2", &backup)?;
+
+        // a blob other versions refer to by its hash is handed on to the next of them
+        self.connection.execute(
+            "UPDATE device_data SET data = (SELECT data FROM device_data WHERE device = ?1 AND version = ?2)
+                WHERE rowid = (SELECT referring.rowid FROM device_data referring
+                    JOIN device_data deleted ON deleted.device = ?1 AND deleted.version = ?2
+                    WHERE referring.data_hash = deleted.data_hash AND referring.data IS NULL
+                        AND deleted.data IS NOT NULL AND referring.rowid != deleted.rowid
+                        AND NOT EXISTS (SELECT 1 FROM device_data other
+                            WHERE other.data_hash = deleted.data_hash
+                                AND other.data IS NOT NULL AND other.rowid != deleted.rowid)
+                    ORDER BY referring.rowid LIMIT 1)",
+            &backup,
+        )?;
+
+        match self.connection.execute("DELETE FROM device_data WHERE device = ?1 AND version = ?2", &backup)? {
+            0 => Err(Error::from(DatabaseError::BackupVersionNotFound { version })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the database `name` for damage, e.g. left by a backup which was interrupted or
+    /// a full disk: sqlite's integrity check, the database version, versions and chunks left
+    /// over, and the data and header of each version. A database of another version is only
+    /// checked for its integrity.
+    pub fn check(name: &str) -> Result<Vec<Problem>, Error> {
+        // opened read only, so it is neither migrated nor are devices added
+        let conn = Connection::open_with_flags(name, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut problems = integrity_problems(&conn)?;
+
+        let version = DatabaseMigrator::get_database_version(&conn)?;
+        if version != CURRENT_VERSION {
+            problems.push(Problem::WrongVersion { version });
+            return Ok(problems);
+        }
+
+        problems.extend(orphaned_versions(&conn)?);
+        let count = orphaned_chunks(&conn)?;
+        if count > 0 {
+            problems.push(Problem::OrphanedChunks { count });
+        }
+
+        for device in list_devices(&conn)? {
+            let db_manager = Self::open_read_only(name, &device, version)?;
+
+            for backup in db_manager.verify()? {
+                let version = backup.version;
+                let device = device.clone();
+
+                match backup.integrity {
+                    Integrity::Corrupt => problems.push(Problem::Corrupt { device, version }),
+                    Integrity::Missing => problems.push(Problem::Truncated { device, version }),
+                    Integrity::Intact | Integrity::Unhashed => {
+                        if let Err(error) = BackupHeader::read(&mut BufReader::new(db_manager.content(version)?)) {
+                            problems.push(Problem::InvalidHeader { device, version, error: error.to_string() });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Repairs the problems of the database `name` which are not about a single backup, a
+    /// database of an older version is migrated and what is left over by unfinished backups
+    /// is removed. Broken backups are taken care of by `quarantine` before.
+    pub fn repair(name: &str, problems: &[Problem]) -> Result<(), Error> {
+        let (conn, _, _) = Self::open_database(name)?;
+
+        let orphans = problems.iter().any(|problem| match *problem {
+            Problem::OrphanedVersion { .. } | Problem::OrphanedChunks { .. } => true,
+            _ => false,
+        });
+        if orphans {
+            delete_orphans(&conn)?;
+        }
+
+        Ok(conn.execute_batch("VACUUM")?)
+    }
+
+    /// Moves `version` out of the database into `quarantine_file`, with as much of its data
+    /// as can still be read.
+    pub fn quarantine(&self, version: u32, quarantine_file: &Path) -> Result<(), Error> {
+        let mut file = File::create(quarantine_file)?;
+        if let Ok(mut content) = self.content(version) {
+            // the data is copied up to where it is missing
+            let _ = io::copy(&mut content, &mut file);
+        }
+
+        self.delete_backups(&[version])
+    }
+
+    /// Rebuilds the database file to give the space of deleted data back.
+    pub fn vacuum(&self) -> Result<(), Error> {
+        Ok(self.connection.execute_batch("VACUUM")?)
+    }
+
+    // the hash of a version with an inflated payload is the one of the backup deflated again,
+    // versions stored before it was recorded have the one of their content
+    fn verify_chunks(&self, chunks: Vec<String>, data_hash: &str, inflated: bool) -> Result<Integrity, Error> {
+        let mut content_hash = Sha256::new();
+
+        for chunk_hash in chunks.iter() {
+            let chunk = match load_chunk(&self.connection, &chunk_hash)? {
+                Some(chunk) => chunk,
+                None => return Ok(Integrity::Missing),
+            };
+
+            if sha256(&chunk) != *chunk_hash {
+                return Ok(Integrity::Corrupt);
+            }
+            content_hash.update(&chunk);
+        }
+
+        if format!("{:x}", content_hash.finalize()) == data_hash {
+            return Ok(Integrity::Intact);
+        }
+
+        let backup_hash = match inflated {
+            true => write_backup(ChunkReader::new(&self.connection, chunks), true, Sha256::new())?.finalize(),
+            false => return Ok(Integrity::Corrupt),
+        };
+
+        match format!("{:x}", backup_hash) == data_hash {
+            true => Ok(Integrity::Intact),
+            false => Ok(Integrity::Corrupt),
+        }
+    }
+}
+
+// the backup of a version from its stored content, an inflated payload is deflated again
+// at the level android uses
+fn write_backup<R: Read, W: Write>(content: R, inflated: bool, mut output: W) -> Result<W, Error> {
+    let mut content = BufReader::new(content);
+
+    if inflated {
+        let header = BackupHeader::read(&mut content)?;
+        output.write_all(&BackupHeader { compressed: true, ..header }.to_bytes())?;
+
+        let mut encoder = ZlibEncoder::new(output, Compression::best());
+        io::copy(&mut content, &mut encoder)?;
+        Ok(encoder.finish()?)
+    } else {
+        io::copy(&mut content, &mut output)?;
+        Ok(output)
+    }
+}
+
+// inflates the zlib payload of a backup, unlike ZlibDecoder it fails if the input ends
+// before the compressed stream does
+struct Inflater<R> {
+    input: R,
+    inflate: Decompress,
+    finished: bool,
+    failed: bool,
+}
+
+impl<R: BufRead> Inflater<R> {
+    fn new(input: R) -> Self {
+        Inflater { input, inflate: Decompress::new(true), finished: false, failed: false }
+    }
+
+    // whether the compressed stream was read to its end and nothing follows it
+    fn at_end(&mut self) -> io::Result<bool> {
+        Ok(self.finished && self.input.fill_buf()?.is_empty())
+    }
+
+    fn inflate(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (status, consumed, produced, eof) = {
+                let input = self.input.fill_buf()?;
+                let (total_in, total_out) = (self.inflate.total_in(), self.inflate.total_out());
+                let status = self.inflate.decompress(input, buf, FlushDecompress::None)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                (
+                    status,
+                    (self.inflate.total_in() - total_in) as usize,
+                    (self.inflate.total_out() - total_out) as usize,
+                    input.is_empty(),
+                )
+            };
+            self.input.consume(consumed);
+            self.finished = status == Status::StreamEnd;
+
+            if produced > 0 || self.finished {
+                return Ok(produced);
+            }
+            if eof {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the compressed payload is truncated"));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let read = self.inflate(buf);
+        self.failed = read.is_err();
+        read
+    }
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
 }
 
 #[cfg(test)]
 mod tests {
-    use database::management::DatabaseManager;
-    use database::migration::CURRENT_VERSION;
+    use database::management::{DatabaseManager, Integrity, StoredBackup, VerifiedBackup};
+    use database::check::Problem;
+    use database::compression::Codec;
+    use database::metadata::BackupMetadata;
+    use database::migration::{DatabaseMigrator, CURRENT_VERSION};
+    use rusqlite::Connection;
+    use chrono::NaiveDateTime;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use android_backup::entry::Domain;
+    use android_backup::reader::BackupReader;
+    use android_backup::summary::{BackupSummary, PackageSummary};
+    use devices::{DeviceInfo, Storage};
+    use apk_harvest::{HarvestedApk, HarvestedApp};
+    use tar;
     use std::fs::{copy, File, remove_file};
     use std::io::{Read, Write};
+    use std::path::Path;
 
     #[test]
     fn test_data_insertion_and_retrieval() {
@@ -97,20 +853,21 @@ mod tests {
         let output_data_file = "9c462f205ae62c436ca700332dd009b4";
 
         {
-            let db_manager = DatabaseManager::open_connection(temp_db).unwrap();
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
 
             let mut first_data = File::create(&first_data_file).unwrap();
             assert!(first_data.write(&vec![00, 01, 02]).is_ok());
 
-            assert!(db_manager.insert_data(&first_data_file).is_ok());
+            assert!(db_manager.insert_data(&first_data_file, None).is_ok());
 
             
             let mut second_data = File::create(&second_data_file).unwrap();
             assert!(second_data.write(&vec![03, 04, 05]).is_ok());
 
-            assert!(db_manager.insert_data(&second_data_file).is_ok());
+            assert!(db_manager.insert_data(&second_data_file, None).is_ok());
             
-            assert!(db_manager.get_latest_backup(&output_data_file).is_ok());
+            let version = db_manager.get_latest_version().unwrap();
+            assert!(db_manager.get_backup(version, &output_data_file).is_ok());
         }
 
         let mut file_wanted = File::open(&second_data_file).unwrap();
@@ -128,4 +885,676 @@ mod tests {
         assert!(remove_file(&second_data_file).is_ok());
         assert!(remove_file(&output_data_file).is_ok());
     }
+
+    #[test]
+    fn test_get_backup_by_version() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "b0059a54f83d2a758e7a4e98f60add7d.db"; // md5 of 'test_get_backup_by_version'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        let data_file = "57b3e5e920a2fe472b28feda4a961019"; // md5 of 'test_get_backup_by_version_data'
+        let output_data_file = "7794801e3c15f20f0953563002fc2a24"; // md5 of 'test_get_backup_by_version_output'
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            File::create(&data_file).unwrap().write_all(&vec![03, 04, 05]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            assert!(db_manager.get_backup(1, &output_data_file).is_ok());
+            assert_eq!(
+                format!("{}", db_manager.get_backup(3, &output_data_file).unwrap_err()),
+                "no backup with version 3 found"
+            );
+        }
+
+        let mut data_result = Vec::new();
+        File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![00, 01, 02]);
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    #[test]
+    fn test_list_backups() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "a8c9f91a37a30253070ad36097093621.db"; // md5 of 'test_list_backups'
+        let data_file = "35ce5381a23e46bb73a3d121912c32c4"; // md5 of 'test_list_backups_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+            assert_eq!(db_manager.list_backups().unwrap(), Vec::<StoredBackup>::new());
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            File::create(&data_file).unwrap().write_all(&vec![03, 04, 05, 06]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            let backups = db_manager.list_backups().unwrap();
+            assert_eq!(
+                backups.iter().map(|backup| (backup.version, backup.size)).collect::<Vec<(u32, u64)>>(),
+                vec![(1, 3), (2, 4)]
+            );
+            assert!(!backups[0].date_created.is_empty());
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_compressed_chunks() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "538dbf65f07ffcda46b59fc2c82e3b9a.db"; // md5 of 'test_compressed_chunks'
+        let data_file = "fe343fa80b81fe843b163d586f85e8cf"; // md5 of 'test_compressed_chunks_data'
+        let output_data_file = "732880c804290bdb65e4f38fe82b1dc0"; // md5 of 'test_compressed_chunks_output'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        let data = b"<map><string name=\"adbackup\">adbackup</string></map>\n".repeat(1000);
+        File::create(&data_file).unwrap().write_all(&data).unwrap();
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap()
+                .with_codec(Codec::Gzip);
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            // a chunk stored uncompressed before chunks were compressed
+            db_manager.connection.execute_batch(
+                "INSERT INTO chunks (hash, data, size) VALUES ('hash', X'000102', 3);
+                INSERT INTO device_data (device, data_hash, version) VALUES (1, 'hash', 2);
+                INSERT INTO backup_chunks VALUES (1, 2, 0, 'hash');"
+            ).unwrap();
+
+            let backups = db_manager.list_backups().unwrap();
+            assert_eq!(backups[0].size, data.len() as u64);
+            assert!(backups[0].stored_size < backups[0].size / 10);
+            assert_eq!((backups[1].size, backups[1].stored_size), (3, 3));
+
+            assert!(db_manager.get_backup(1, &output_data_file).is_ok());
+            let mut data_result = Vec::new();
+            File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+            assert_eq!(data_result, data);
+
+            assert!(db_manager.get_backup(2, &output_data_file).is_ok());
+            let mut data_result = Vec::new();
+            File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+            assert_eq!(data_result, vec![00, 01, 02]);
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    #[test]
+    fn test_backup_metadata() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "fae71e48f061b5c4f2e8da908c2780ff.db"; // md5 of 'test_backup_metadata'
+        let data_file = "80d0a5cedaba4f122bd355e50eee0712"; // md5 of 'test_backup_metadata_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        let metadata = BackupMetadata {
+            created_at: String::new(),
+            device_serial: String::from("emulator-5554"),
+            device_model: Some(String::from("Android_SDK_built_for_x86")),
+            android_version: None,
+            applications: true,
+            shared_storage: false,
+            system_apps: false,
+            packages: vec![String::from("org.cryptomator"), String::from("com.dropbox.android")],
+            adbackup_version: String::from("0.5.2"),
+            duration: 1500,
+            size: 3,
+            device_info: Some(DeviceInfo {
+                android_version: Some(String::from("8.1.0")),
+                sdk_level: Some(27),
+                storage: vec![Storage { mount_point: String::from("/data"), size: 4096, used: 1024, available: 3072 }],
+                battery_level: Some(85),
+                ..DeviceInfo::default()
+            }),
+            excluded_packages: vec![String::from("com.example.banking")],
+            harvested_apps: vec![HarvestedApp {
+                package: String::from("org.cryptomator"),
+                apks: vec![HarvestedApk { name: String::from("base.apk"), sha256: String::from("ae4b3280"), size: 3 }],
+                signatures: vec![String::from("5a5d2c36")],
+            }],
+            summary: Some(BackupSummary {
+                packages: vec![PackageSummary {
+                    package: String::from("org.cryptomator"),
+                    files: 2,
+                    size: 1024,
+                    domains: vec![Domain::Manifest, Domain::Other(String::from("x"))],
+                }],
+            }),
+        };
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert_eq!(db_manager.insert_data(&data_file, None).unwrap(), 1);
+            assert_eq!(db_manager.insert_data(&data_file, Some(&metadata)).unwrap(), 2);
+
+            let backups = db_manager.list_backups().unwrap();
+            assert_eq!(backups[0].metadata, None);
+
+            let stored = backups[1].metadata.clone().unwrap();
+            assert_eq!(stored.created_at.len(), "2018-06-01 08:00:00".len());
+            assert_eq!(BackupMetadata { created_at: String::new(), ..stored.clone() }, metadata);
+            assert_eq!(stored.options(), "-apk -noshared -nosystem org.cryptomator com.dropbox.android");
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_get_version_before() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "f8e75890d9c51645da649894c8742ae3.db"; // md5 of 'test_get_version_before'
+        let data_file = "5b3480bb0a82e34705d479a01d8083d2"; // md5 of 'test_get_version_before_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            for _ in 0..3 {
+                assert!(db_manager.insert_data(&data_file, None).is_ok());
+            }
+
+            let dates = vec![(1, "2018-06-01 08:00:00"), (2, "2018-06-03 20:15:00"), (3, "13:37:00")];
+            dates.into_iter().for_each(|(version, date)| {
+                db_manager.connection.execute(
+                    "UPDATE device_data SET date_created = ?1 WHERE version = ?2",
+                    &[&date, &version],
+                ).unwrap();
+            });
+
+            let date = |date: &str| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
+
+            assert_eq!(db_manager.get_version_before(&date("2018-06-04 00:00:00")).unwrap(), 2);
+            assert_eq!(db_manager.get_version_before(&date("2018-06-03 20:15:00")).unwrap(), 1);
+            assert_eq!(
+                format!("{}", db_manager.get_version_before(&date("2018-06-01 00:00:00")).unwrap_err()),
+                "no backup created before 2018-06-01 00:00:00 found"
+            );
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_identical_backups_are_stored_once() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "912c57b9a270e218e9e174db51cf2272.db"; // md5 of 'test_identical_backups_are_stored_once'
+        let data_file = "f342b417e0de3519095c6638610e60de"; // md5 of 'test_identical_backups_are_stored_once_data'
+        let output_data_file = "b1ffaf1d7cfd9f85446540422835ea40"; // md5 of 'test_identical_backups_are_stored_once_output'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            let (hashes, chunks): (u32, u32) = db_manager.connection.query_row(
+                "SELECT COUNT(DISTINCT data_hash), (SELECT COUNT(*) FROM chunks) FROM device_data",
+                &[],
+                |row| (row.get(0), row.get(1)),
+            ).unwrap();
+            assert_eq!((hashes, chunks), (1, 1));
+
+            let data_hash: String = db_manager.connection.query_row(
+                "SELECT data_hash FROM device_data WHERE version = 2",
+                &[],
+                |row| row.get(0),
+            ).unwrap();
+            // sha256 of [00, 01, 02]
+            assert_eq!(data_hash, "ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc");
+
+            assert!(db_manager.get_backup(2, &output_data_file).is_ok());
+            assert_eq!(db_manager.list_backups().unwrap()[1].size, 3);
+        }
+
+        let mut data_result = Vec::new();
+        File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![00, 01, 02]);
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    #[test]
+    fn test_verify() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "1b79c9ec28de8c71836f98756342112d.db"; // md5 of 'test_verify'
+        let data_file = "9f1695a3c25c4a8aaa85735edff1da27"; // md5 of 'test_verify_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            let contents = vec![vec![00, 01, 02], vec![00, 01, 02], vec![03, 04, 05], vec![06]];
+            contents.into_iter().for_each(|content| {
+                File::create(&data_file).unwrap().write_all(&content).unwrap();
+                assert!(db_manager.insert_data(&data_file, None).is_ok());
+            });
+
+            // sha256 of [00, 01, 02] and of [06]
+            db_manager.connection.execute_batch(
+                "UPDATE chunks SET data = X'000102FF'
+                    WHERE hash = 'ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc';
+                DELETE FROM chunks
+                    WHERE hash = '67586e98fad27da0b9968bc039a1ef34c939b9b8e523a8bef89d478608c5ecf6';
+                INSERT INTO device_data (device, data_hash, version, data) VALUES (1, 'const_hash', 5, X'07');
+                INSERT INTO device_data (device, data_hash, version, data) VALUES (
+                    1, 'beead77994cf573341ec17b58bbf7eb34d2711c993c1d976b128b3188dc1829a', 6, X'08');",
+            ).unwrap();
+
+            assert_eq!(
+                db_manager.verify().unwrap(),
+                vec![
+                    VerifiedBackup { version: 1, integrity: Integrity::Corrupt },
+                    VerifiedBackup { version: 2, integrity: Integrity::Corrupt },
+                    VerifiedBackup { version: 3, integrity: Integrity::Intact },
+                    VerifiedBackup { version: 4, integrity: Integrity::Missing },
+                    VerifiedBackup { version: 5, integrity: Integrity::Unhashed },
+                    VerifiedBackup { version: 6, integrity: Integrity::Intact },
+                ]
+            );
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_check() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "19fe42bc7cfdc37a2d88684013e66c7b.db"; // md5 of 'test_check'
+        let data_file = "99507b4fc5dca657628c72fe0096314f"; // md5 of 'test_check_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            let contents = vec![b"ANDROID BACKUP\n5\n0\nnone\n".to_vec(), b"garbage\n".to_vec(), vec![00, 01, 02]];
+            contents.into_iter().for_each(|content| {
+                File::create(&data_file).unwrap().write_all(&content).unwrap();
+                assert!(db_manager.insert_data(&data_file, None).is_ok());
+            });
+
+            // sha256 of [00, 01, 02]
+            db_manager.connection.execute_batch(
+                "UPDATE chunks SET data = X'000102FF'
+                    WHERE hash = 'ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc';
+                INSERT INTO device_data (device, data_hash, version) VALUES (1, 'hash', 4);
+                INSERT INTO backup_chunks VALUES (1, 4, 0, 'missing');
+                INSERT INTO chunks (hash, data) VALUES ('orphaned', X'00'), ('unused', X'00');
+                INSERT INTO backup_chunks VALUES (1, 5, 0, 'orphaned');",
+            ).unwrap();
+
+            let problems = DatabaseManager::check(temp_db).unwrap();
+            assert_eq!(problems, vec![
+                Problem::OrphanedVersion { device: String::from("emulator-5554"), version: 5 },
+                Problem::OrphanedChunks { count: 1 },
+                Problem::InvalidHeader {
+                    device: String::from("emulator-5554"),
+                    version: 2,
+                    error: String::from("not an android backup, header starts with 'garbage'"),
+                },
+                Problem::Corrupt { device: String::from("emulator-5554"), version: 3 },
+                Problem::Truncated { device: String::from("emulator-5554"), version: 4 },
+            ]);
+
+            DatabaseManager::repair(temp_db, &problems).unwrap();
+            assert_eq!(DatabaseManager::check(temp_db).unwrap().len(), 3);
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_check_of_older_version() {
+        let temp_db = "72750a051cc5a9fbd6e86d8bc8e67d1c.db"; // md5 of 'test_check_of_older_version'
+        assert!(copy("tests/test_databases/dummy_db_v4.db", temp_db).is_ok());
+
+        assert_eq!(DatabaseManager::check(temp_db).unwrap(), vec![Problem::WrongVersion { version: 4 }]);
+        // checking does not migrate the database
+        assert_eq!(DatabaseMigrator::get_database_version(&Connection::open(temp_db).unwrap()).unwrap(), 4);
+
+        DatabaseManager::repair(temp_db, &[Problem::WrongVersion { version: 4 }]).unwrap();
+        assert_eq!(DatabaseManager::check(temp_db).unwrap(), Vec::<Problem>::new());
+
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_delete_backups() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "2195acdf036bbb65f6af0e02e023ee93.db"; // md5 of 'test_delete_backups'
+        let data_file = "5f5c9ecd0a41802f4850aa6084964969"; // md5 of 'test_delete_backups_data'
+        let output_data_file = "24674e5931fe71a1c8734cf9fd84082c"; // md5 of 'test_delete_backups_output'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            let contents = vec![vec![00, 01, 02], vec![03, 04, 05], vec![00, 01, 02]];
+            contents.into_iter().for_each(|content| {
+                File::create(&data_file).unwrap().write_all(&content).unwrap();
+                assert!(db_manager.insert_data(&data_file, None).is_ok());
+            });
+
+            // versions stored as a single blob, the second one refers to the first's data
+            db_manager.connection.execute_batch(
+                "INSERT INTO device_data (device, data_hash, version, data) VALUES (1, 'blob_hash', 4, X'07');
+                INSERT INTO device_data (device, data_hash, version) VALUES (1, 'blob_hash', 5);",
+            ).unwrap();
+
+            assert!(db_manager.delete_backups(&[1, 2, 4]).is_ok());
+            assert!(db_manager.vacuum().is_ok());
+
+            let versions = db_manager
+                .list_backups()
+                .unwrap()
+                .iter()
+                .map(|backup| backup.version)
+                .collect::<Vec<u32>>();
+            assert_eq!(versions, vec![3, 5]);
+
+            let chunks: u32 = db_manager.connection.query_row(
+                "SELECT COUNT(*) FROM chunks", &[], |row| row.get(0),
+            ).unwrap();
+            assert_eq!(chunks, 1);
+
+            assert!(db_manager.get_backup(5, &output_data_file).is_ok());
+            assert_eq!(
+                format!("{}", db_manager.delete_backups(&[1]).unwrap_err()),
+                "no backup with version 1 found"
+            );
+
+            assert_eq!(db_manager.insert_data(&data_file, None).unwrap(), 6);
+        }
+
+        let mut data_result = Vec::new();
+        File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![07]);
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    #[test]
+    fn test_devices_share_the_database() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "ac68df3e68bec9a2b7cefc1a6d70f603.db"; // md5 of 'test_devices_share_the_database'
+        let data_file = "909a65c21c20c328c3c12a3e4f714ced"; // md5 of 'test_devices_share_the_database_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        {
+            let phone = DatabaseManager::open_connection(temp_db, "0123456789ABCDEF").unwrap();
+            let emulator = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert_eq!(phone.insert_data(&data_file, None).unwrap(), 1);
+            assert_eq!(phone.insert_data(&data_file, None).unwrap(), 2);
+            assert_eq!(emulator.insert_data(&data_file, None).unwrap(), 1);
+
+            assert_eq!(phone.list_backups().unwrap().len(), 2);
+            assert_eq!(emulator.list_backups().unwrap().len(), 1);
+
+            let chunks: u32 = phone.connection.query_row(
+                "SELECT COUNT(*) FROM chunks", &[], |row| row.get(0),
+            ).unwrap();
+            assert_eq!(chunks, 1);
+
+            assert!(phone.update_device("192.168.2.100:5555", Some("ONEPLUS_A3003")).is_ok());
+            assert_eq!(
+                DatabaseManager::find_device(temp_db, "192.168.2.100:5555").unwrap(),
+                Some(String::from("0123456789ABCDEF"))
+            );
+            assert_eq!(
+                DatabaseManager::find_device(temp_db, "emulator-5554").unwrap(),
+                Some(String::from("emulator-5554"))
+            );
+            assert_eq!(DatabaseManager::find_device(temp_db, "emulator-5556").unwrap(), None);
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_import_device_database() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "9dc73f68f566a6a83788c2fd8cff33c5.db"; // md5 of 'test_import_device_database'
+        let device_db = "557f44222996289bb6af946779bf8594.db"; // md5 of 'test_import_device_database_legacy'
+        let data_file = "4e41068cd0e4e567bcc12d8713b17822"; // md5 of 'test_import_device_database_data'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+        assert!(copy("tests/test_databases/dummy_db_v3.db", device_db).is_ok());
+
+        {
+            let connection = Connection::open(device_db).unwrap();
+            connection.execute_batch(
+                "INSERT INTO device_data (data_hash, version, data, date_created)
+                    VALUES ('const_hash', 1, X'0001', '2018-06-01 08:00:00');",
+            ).unwrap();
+        }
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            File::create(&data_file).unwrap().write_all(&vec![00, 01, 02]).unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            assert_eq!(db_manager.import_device_database(device_db).unwrap(), 1);
+
+            let backups = db_manager.list_backups().unwrap();
+            assert_eq!(
+                backups.iter().map(|backup| (backup.version, backup.size)).collect::<Vec<(u32, u64)>>(),
+                vec![(1, 3), (2, 2)]
+            );
+            assert_eq!(backups[1].date_created, "2018-06-01 08:00:00");
+
+            assert!(db_manager.get_backup(2, &data_file).is_ok());
+        }
+
+        let mut data_result = Vec::new();
+        File::open(&data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![00, 01]);
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&device_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+    }
+
+    #[test]
+    fn test_get_backup_stored_as_blob() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "bd1824c3466aca2df5972e97f487c3c5.db"; // md5 of 'test_get_backup_stored_as_blob'
+        let output_data_file = "78d44bf653f57df5fcd655a77033ab64"; // md5 of 'test_get_backup_stored_as_blob_output'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        let read_output = || {
+            let mut data_result = Vec::new();
+            File::open(&output_data_file).unwrap().read_to_end(&mut data_result).unwrap();
+            data_result
+        };
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            // versions as stored before the chunk store, the second one refers to the data
+            // of the first
+            db_manager.connection.execute_batch(
+                "INSERT INTO device_data (device, data_hash, version, data) VALUES (1, 'hash', 1, X'000102');
+                INSERT INTO device_data (device, data_hash, version, data) VALUES (1, 'hash', 2, NULL);
+                INSERT INTO device_data (device, data_hash, version, data) VALUES (1, 'gone', 3, NULL);",
+            ).unwrap();
+
+            assert!(db_manager.get_backup(1, &output_data_file).is_ok());
+            assert_eq!(read_output(), vec![00, 01, 02]);
+
+            assert!(db_manager.get_backup(2, &output_data_file).is_ok());
+            assert_eq!(read_output(), vec![00, 01, 02]);
+
+            assert_eq!(
+                format!("{}", db_manager.get_backup(3, &output_data_file).unwrap_err()),
+                "data of backup version 3 is missing"
+            );
+        }
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    fn compressed_backup(files: Vec<(&str, Vec<u8>)>, level: Compression) -> (Vec<u8>, Vec<u8>) {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        files.into_iter().for_each(|(path, data)| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, &data[..]).unwrap();
+        });
+        let tar = builder.into_inner().unwrap();
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(&tar).unwrap();
+
+        let mut backup = b"ANDROID BACKUP\n5\n1\nnone\n".to_vec();
+        backup.extend(encoder.finish().unwrap());
+
+        (backup, tar)
+    }
+
+    #[test]
+    fn test_similar_backups_share_chunks() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "7b41712dc1af5fdc8bbeed2a78ca2afd.db"; // md5 of 'test_similar_backups_share_chunks'
+        let output_data_file = "e4f2a7a940055118d5cf5ec74fac4225"; // md5 of 'test_similar_backups_share_chunks_output'
+
+        // deflated by zlib at the level android uses, the second backup only changes a
+        // setting after the database
+        let first_backup = "tests/test_backups/similar_1.ab";
+        let second_backup = "tests/test_backups/similar_2.ab";
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        let chunks = |db_manager: &DatabaseManager| -> (u32, u32, u32) {
+            db_manager.connection.query_row(
+                "SELECT (SELECT COUNT(*) FROM chunks), (SELECT COUNT(*) FROM backup_chunks),
+                    (SELECT SUM(LENGTH(data)) FROM chunks)",
+                &[],
+                |row| (row.get(0), row.get(1), row.get(2)),
+            ).unwrap()
+        };
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+
+            assert!(db_manager.insert_data(first_backup, None).is_ok());
+            let (first_chunks, _, first_size) = chunks(&db_manager);
+            assert!(first_chunks > 1);
+
+            assert!(db_manager.insert_data(second_backup, None).is_ok());
+            let (stored_chunks, referenced_chunks, stored_size) = chunks(&db_manager);
+            assert!(stored_chunks < first_chunks * 2);
+            assert!(referenced_chunks >= first_chunks * 2);
+            assert!(stored_size - first_size < first_size / 2);
+
+            let inflated: Vec<bool> = db_manager.connection
+                .prepare("SELECT payload_inflated FROM device_data ORDER BY version").unwrap()
+                .query_map(&[], |row| row.get(0)).unwrap()
+                .map(|inflated| inflated.unwrap())
+                .collect();
+            assert_eq!(inflated, vec![true, true]);
+
+            assert!(db_manager.get_backup(2, &output_data_file).is_ok());
+            assert!(db_manager.verify().unwrap().iter().all(|backup| backup.integrity == Integrity::Intact));
+        }
+
+        let tar = |backup: &str| {
+            let reader = BackupReader::open(Path::new(backup), None).unwrap();
+            assert!(reader.header().compressed);
+            let mut tar = Vec::new();
+            reader.into_tar().read_to_end(&mut tar).unwrap();
+            tar
+        };
+        assert_eq!(tar(output_data_file), tar(second_backup));
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_backup_is_stored_as_it_is() {
+        let current_db_name = format!("tests/test_databases/dummy_db_v{}.db", CURRENT_VERSION);
+        let temp_db = "5ad0bd25db86105be463d18114d1ea2d.db"; // md5 of 'test_truncated_backup_is_stored_as_it_is'
+        let data_file = "2caf96144b278a05c0dc891f59967fed"; // md5 of 'test_truncated_backup_is_stored_as_it_is_data'
+        let output_data_file = "2b2d963481af4c26d541b1cc6074d31e"; // md5 of 'test_truncated_backup_is_stored_as_it_is_output'
+
+        assert!(copy(current_db_name, temp_db).is_ok());
+
+        // a backup cancelled on the device ends within the compressed payload
+        let (mut backup, _) = compressed_backup(vec![
+            ("apps/org.cryptomator/sp/settings.xml", b"<map />".repeat(1000)),
+        ], Compression::best());
+        let truncated = backup.len() - 10;
+        backup.truncate(truncated);
+        File::create(&data_file).unwrap().write_all(&backup).unwrap();
+
+        {
+            let db_manager = DatabaseManager::open_connection(temp_db, "emulator-5554").unwrap();
+            assert!(db_manager.insert_data(&data_file, None).is_ok());
+
+            let inflated: bool = db_manager.connection.query_row(
+                "SELECT payload_inflated FROM device_data WHERE version = 1",
+                &[],
+                |row| row.get(0),
+            ).unwrap();
+            assert!(!inflated);
+
+            assert!(db_manager.get_backup(1, &output_data_file).is_ok());
+            assert_eq!(
+                db_manager.verify().unwrap(),
+                vec![VerifiedBackup { version: 1, integrity: Integrity::Intact }]
+            );
+        }
+
+        let mut output = Vec::new();
+        File::open(&output_data_file).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, backup);
+
+        assert!(remove_file(&temp_db).is_ok());
+        assert!(remove_file(&data_file).is_ok());
+        assert!(remove_file(&output_data_file).is_ok());
+    }
 }
@@ -0,0 +1,130 @@
+use rusqlite::{Connection, Error as SqliteError};
+use android_backup::summary::BackupSummary;
+use apk_harvest::HarvestedApp;
+use serde_json;
+use devices::DeviceInfo;
+use failure::Error;
+
+/// What was backed up from which device and how, recorded with each version.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    /// UTC, `YYYY-MM-DD HH:MM:SS`, set when the version is stored unless it is given, e.g.
+    /// for versions imported from another repository
+    pub created_at: String,
+    pub device_serial: String,
+    pub device_model: Option<String>,
+    pub android_version: Option<String>,
+    pub applications: bool,
+    pub shared_storage: bool,
+    pub system_apps: bool,
+    /// the only packages backed up, all of them if empty
+    pub packages: Vec<String>,
+    pub adbackup_version: String,
+    /// duration of the backup on the device in milliseconds
+    pub duration: u64,
+    /// size of the `.ab` file in bytes
+    pub size: u64,
+    /// what the device told about itself before the backup, none for versions stored before
+    /// it was recorded
+    #[serde(default)]
+    pub device_info: Option<DeviceInfo>,
+    /// packages `adb backup` left out as they do not allow it
+    #[serde(default)]
+    pub excluded_packages: Vec<String>,
+    /// apps whose apks were pulled into the backup
+    #[serde(default)]
+    pub harvested_apps: Vec<HarvestedApp>,
+    /// the packages and files of the backup, none if it was encrypted and made without the
+    /// backup password or stored before the contents were recorded
+    #[serde(default)]
+    pub summary: Option<BackupSummary>,
+}
+
+impl BackupMetadata {
+    /// The `adb backup` options the version was made with.
+    pub fn options(&self) -> String {
+        let mut options = vec![
+            if self.applications { "-apk" } else { "-noapk" },
+            if self.shared_storage { "-shared" } else { "-noshared" },
+            if self.system_apps { "-system" } else { "-nosystem" },
+        ];
+
+        match self.packages.is_empty() {
+            true => options.push("-all"),
+            false => options.extend(self.packages.iter().map(String::as_str)),
+        }
+
+        options.join(" ")
+    }
+}
+
+pub fn insert_metadata(connection: &Connection, device: u32, version: u32, metadata: &BackupMetadata) -> Result<(), Error> {
+    // json, so what is recorded about a device can grow without migrating the database
+    let device_info = match metadata.device_info {
+        Some(ref device_info) => Some(serde_json::to_string(device_info)?),
+        None => None,
+    };
+    let harvested_apps = serde_json::to_string(&metadata.harvested_apps)?;
+    let summary = match metadata.summary {
+        Some(ref summary) => Some(serde_json::to_string(summary)?),
+        None => None,
+    };
+
+    connection.execute("INSERT INTO backup_metadata (device, version, created_at, device_serial,
+        device_model, android_version, applications, shared_storage, system_apps, packages,
+        adbackup_version, duration, size, device_info, excluded_packages, harvested_apps, summary)
+        VALUES (?1, ?2, IFNULL(NULLIF(?13, ''), strftime('%Y-%m-%d %H:%M:%S', 'now')), ?3, ?4, ?5, ?6,
+            ?7, ?8, ?9, ?10, ?11, ?12, ?14, ?15, ?16, ?17)",
+        &[&device, &version, &metadata.device_serial, &metadata.device_model, &metadata.android_version,
+            &metadata.applications, &metadata.shared_storage, &metadata.system_apps,
+            &metadata.packages.join(" "), &metadata.adbackup_version,
+            &(metadata.duration as i64), &(metadata.size as i64), &metadata.created_at, &device_info,
+            &metadata.excluded_packages.join(" "), &harvested_apps, &summary])?;
+
+    Ok(())
+}
+
+/// The metadata of `version` of `device`, versions stored before metadata was recorded
+/// have none.
+pub fn get_metadata(connection: &Connection, device: u32, version: u32) -> Result<Option<BackupMetadata>, Error> {
+    let metadata = connection.query_row(
+        "SELECT created_at, device_serial, device_model, android_version, applications,
+            shared_storage, system_apps, packages, adbackup_version, duration, size, device_info,
+            excluded_packages, harvested_apps, summary FROM backup_metadata WHERE device = ?1 AND version = ?2",
+        &[&device, &version],
+        |row| BackupMetadata {
+            created_at: row.get(0),
+            device_serial: row.get(1),
+            device_model: row.get(2),
+            android_version: row.get(3),
+            applications: row.get(4),
+            shared_storage: row.get(5),
+            system_apps: row.get(6),
+            packages: row
+                .get::<_, String>(7)
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            adbackup_version: row.get(8),
+            duration: row.get::<_, i64>(9) as u64,
+            size: row.get::<_, i64>(10) as u64,
+            device_info: row
+                .get::<_, Option<String>>(11)
+                .and_then(|device_info| serde_json::from_str(&device_info).ok()),
+            excluded_packages: row
+                .get::<_, String>(12)
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            harvested_apps: serde_json::from_str(&row.get::<_, String>(13)).unwrap_or_default(),
+            summary: row
+                .get::<_, Option<String>>(14)
+                .and_then(|summary| serde_json::from_str(&summary).ok()),
+        });
+
+    match metadata {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(SqliteError::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
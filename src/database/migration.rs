@@ -1,7 +1,7 @@
-use database::rusqlite::Connection;
+use rusqlite::Connection;
 use failure::Error;
 
-pub static CURRENT_VERSION: u32 = 1;
+pub static CURRENT_VERSION: u32 = 9;
 
 #[derive(Debug, Fail)]
 pub enum MigratorError {
@@ -13,7 +13,12 @@ pub enum MigratorError {
     #[fail(display = "no migration function for version {} implemented", version)]
     NoMigrationFunction {
         version: u32,
-    }
+    },
+
+    #[fail(display = "a database of version {} keeps the backups of one device, it is only migrated when imported for that device", version)]
+    DeviceRequired {
+        version: u32,
+    },
 }
 
 pub struct DatabaseMigrator;
@@ -31,17 +36,42 @@ impl DatabaseMigrator {
     }
 
     pub fn migrate(conn: &Connection, current_version: u32) -> Result<(), Error> {
+        Self::migrate_device_database(conn, current_version, None)
+    }
+
+    /// Migrates a database of a version before all devices shared one database, whose
+    /// backups belong to the device with the hardware serial `device_serial`.
+    pub fn migrate_device_database(conn: &Connection, current_version: u32, device_serial: Option<&str>) -> Result<(), Error> {
         if current_version > CURRENT_VERSION {
             return Err(Error::from(MigratorError::UnknownDatabaseVersion { version: current_version }))
         }
 
+        // each step is done completely or not at all, an interrupted migration is resumed
+        // from the last version reached
         let mut ver = current_version;
         while ver < CURRENT_VERSION {
-            match ver {
-                0 => Self::to_one_from_none(conn)?,
-                _ => return Err(Error::from(MigratorError::NoMigrationFunction { version: ver }))
+            conn.execute_batch("BEGIN")?;
+            let migrated = match ver {
+                0 => Self::to_one_from_none(conn),
+                1 => Self::to_two_from_one(conn),
+                2 => Self::to_three_from_two(conn),
+                3 => Self::to_four_from_three(conn, device_serial),
+                4 => Self::to_five_from_four(conn),
+                5 => Self::to_six_from_five(conn),
+                6 => Self::to_seven_from_six(conn),
+                7 => Self::to_eight_from_seven(conn),
+                8 => Self::to_nine_from_eight(conn),
+                _ => Err(Error::from(MigratorError::NoMigrationFunction { version: ver }))
             };
 
+            match migrated {
+                Ok(_) => conn.execute_batch("COMMIT")?,
+                Err(e) => {
+                    conn.execute_batch("ROLLBACK")?;
+                    return Err(e);
+                }
+            }
+
             ver += 1;
         }
         Ok(())
@@ -61,13 +91,189 @@ impl DatabaseMigrator {
 
         Ok(())
     }
+
+    // v1 -> v2, content addressed chunks
+    fn to_two_from_one(conn: &Connection) -> Result<(), Error> {
+        conn.execute("CREATE TABLE chunks (
+            hash            TEXT NOT NULL PRIMARY KEY,
+            data            BLOB NOT NULL
+            )", &[])?;
+        conn.execute("CREATE TABLE backup_chunks (
+            version         INTEGER NOT NULL,
+            position        INTEGER NOT NULL,
+            chunk_hash      TEXT NOT NULL,
+            PRIMARY KEY(version, position)
+            )", &[])?;
+        conn.execute("CREATE INDEX backup_chunks_hash ON backup_chunks (chunk_hash)", &[])?;
+        conn.execute("ALTER TABLE device_data ADD COLUMN payload_inflated INTEGER NOT NULL DEFAULT 0", &[])?;
+        conn.execute("UPDATE adbackup_system SET version = 2", &[])?;
+
+        Ok(())
+    }
+
+    // v2 -> v3, metadata of each backup
+    fn to_three_from_two(conn: &Connection) -> Result<(), Error> {
+        conn.execute("CREATE TABLE backup_metadata (
+            version             INTEGER NOT NULL PRIMARY KEY,
+            created_at          TEXT NOT NULL,
+            device_serial       TEXT NOT NULL,
+            device_model        TEXT,
+            android_version     TEXT,
+            applications        INTEGER NOT NULL,
+            shared_storage      INTEGER NOT NULL,
+            system_apps         INTEGER NOT NULL,
+            packages            TEXT NOT NULL,
+            adbackup_version    TEXT NOT NULL,
+            duration            INTEGER NOT NULL,
+            size                INTEGER NOT NULL
+            )", &[])?;
+        conn.execute("UPDATE adbackup_system SET version = 3", &[])?;
+
+        Ok(())
+    }
+
+    // v3 -> v4, backups of many devices in one database. The backups stored before belong
+    // to the one device the database was kept for, whose serial was never recorded, so it
+    // has to be told.
+    fn to_four_from_three(conn: &Connection, device_serial: Option<&str>) -> Result<(), Error> {
+        let has_backups: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM device_data)", &[], |row| row.get(0))?;
+        conn.execute_batch("
+            CREATE TABLE devices (
+                id              INTEGER NOT NULL PRIMARY KEY,
+                serial          TEXT NOT NULL UNIQUE,
+                adb_serial      TEXT,
+                model           TEXT
+            );
+        ")?;
+        match (has_backups, device_serial) {
+            (false, _) => (),
+            (true, Some(device_serial)) => {
+                conn.execute("INSERT INTO devices (id, serial) VALUES (1, ?1)", &[&device_serial])?;
+            }
+            (true, None) => return Err(Error::from(MigratorError::DeviceRequired { version: 3 })),
+        }
+
+        conn.execute_batch("
+
+            ALTER TABLE device_data RENAME TO device_data_v3;
+            CREATE TABLE device_data (
+                device          INTEGER NOT NULL,
+                data_hash       TEXT NOT NULL,
+                version         INTEGER NOT NULL,
+                data            BLOB,
+                date_created    INTEGER NOT NULL DEFAULT CURRENT_TIME,
+                payload_inflated INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY(device, version)
+            );
+            INSERT INTO device_data (device, data_hash, version, data, date_created, payload_inflated)
+                SELECT 1, data_hash, version, data, date_created, payload_inflated FROM device_data_v3;
+            DROP TABLE device_data_v3;
+
+            ALTER TABLE backup_chunks RENAME TO backup_chunks_v3;
+            DROP INDEX backup_chunks_hash;
+            CREATE TABLE backup_chunks (
+                device          INTEGER NOT NULL,
+                version         INTEGER NOT NULL,
+                position        INTEGER NOT NULL,
+                chunk_hash      TEXT NOT NULL,
+                PRIMARY KEY(device, version, position)
+            );
+            CREATE INDEX backup_chunks_hash ON backup_chunks (chunk_hash);
+            INSERT INTO backup_chunks SELECT 1, version, position, chunk_hash FROM backup_chunks_v3;
+            DROP TABLE backup_chunks_v3;
+
+            ALTER TABLE backup_metadata RENAME TO backup_metadata_v3;
+            CREATE TABLE backup_metadata (
+                device              INTEGER NOT NULL,
+                version             INTEGER NOT NULL,
+                created_at          TEXT NOT NULL,
+                device_serial       TEXT NOT NULL,
+                device_model        TEXT,
+                android_version     TEXT,
+                applications        INTEGER NOT NULL,
+                shared_storage      INTEGER NOT NULL,
+                system_apps         INTEGER NOT NULL,
+                packages            TEXT NOT NULL,
+                adbackup_version    TEXT NOT NULL,
+                duration            INTEGER NOT NULL,
+                size                INTEGER NOT NULL,
+                PRIMARY KEY(device, version)
+            );
+            INSERT INTO backup_metadata SELECT 1, * FROM backup_metadata_v3;
+            DROP TABLE backup_metadata_v3;
+
+            UPDATE adbackup_system SET version = 4;
+        ")?;
+
+        Ok(())
+    }
+
+    // v4 -> v5, compressed chunks. The chunks stored before are uncompressed, their size is
+    // the one of their data.
+    fn to_five_from_four(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("
+            ALTER TABLE chunks ADD COLUMN codec TEXT NOT NULL DEFAULT 'none';
+            ALTER TABLE chunks ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+            UPDATE chunks SET size = length(data);
+
+            UPDATE adbackup_system SET version = 5;
+        ")?;
+
+        Ok(())
+    }
+
+    // v5 -> v6, what the device told about itself, as json
+    fn to_six_from_five(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("
+            ALTER TABLE backup_metadata ADD COLUMN device_info TEXT;
+
+            UPDATE adbackup_system SET version = 6;
+        ")?;
+
+        Ok(())
+    }
+
+    // v6 -> v7, the packages left out of a backup as they do not allow it, space delimited
+    // like the backed up packages
+    fn to_seven_from_six(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("
+            ALTER TABLE backup_metadata ADD COLUMN excluded_packages TEXT NOT NULL DEFAULT '';
+
+            UPDATE adbackup_system SET version = 7;
+        ")?;
+
+        Ok(())
+    }
+
+    // v7 -> v8, the apps whose apks were pulled into a backup, as json
+    fn to_eight_from_seven(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("
+            ALTER TABLE backup_metadata ADD COLUMN harvested_apps TEXT NOT NULL DEFAULT '[]';
+
+            UPDATE adbackup_system SET version = 8;
+        ")?;
+
+        Ok(())
+    }
+
+    // v8 -> v9, the packages and files of a backup as json, so listing backups does not
+    // need to read them
+    fn to_nine_from_eight(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch("
+            ALTER TABLE backup_metadata ADD COLUMN summary TEXT;
+
+            UPDATE adbackup_system SET version = 9;
+        ")?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use database::rusqlite::Connection;
+    use rusqlite::Connection;
     use database::migration::{DatabaseMigrator, CURRENT_VERSION};
-    use std::fs::remove_file;
+    use std::fs::{copy, remove_file};
 
     #[test]
     fn test_version_retrieval() {
@@ -86,7 +292,196 @@ mod tests {
 
         assert!(DatabaseMigrator::migrate(&conn, 0).is_ok());
 
-        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), 1);
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_two_from_one() {
+        let temp_db = "c03a7c05537fa8bb052f9fead355d661.db"; // md5 of 'test_migration_two_from_one'
+        assert!(copy("tests/test_databases/dummy_db_v1.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(DatabaseMigrator::migrate(&conn, 1).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        assert!(conn.execute("INSERT INTO chunks (hash, data) VALUES ('hash', X'00')", &[]).is_ok());
+        assert!(conn.execute(
+            "INSERT INTO backup_chunks (device, version, position, chunk_hash) VALUES (1, 1, 0, 'hash')",
+            &[]
+        ).is_ok());
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_three_from_two() {
+        let temp_db = "ad8b7e95f216138aadb56cdb911c2f9a.db"; // md5 of 'test_migration_three_from_two'
+        assert!(copy("tests/test_databases/dummy_db_v2.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(DatabaseMigrator::migrate(&conn, 2).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        assert!(conn.execute(
+            "INSERT INTO backup_metadata VALUES
+                (1, 1, '2018-06-01 08:00:00', 'emulator-5554', NULL, NULL, 0, 0, 0, '', '0.5.2', 0, 0, NULL, '', '[]', NULL)",
+            &[]
+        ).is_ok());
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_four_from_three() {
+        let temp_db = "04af5b0aa0d24d8fbd1e6109f14d9ffa.db"; // md5 of 'test_migration_four_from_three'
+        assert!(copy("tests/test_databases/dummy_db_v3.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.execute_batch(
+            "INSERT INTO device_data (data_hash, version) VALUES ('hash', 1);
+            INSERT INTO backup_chunks VALUES (1, 0, 'hash');
+            INSERT INTO backup_metadata VALUES
+                (1, '2018-06-01 08:00:00', 'emulator-5554', NULL, NULL, 0, 0, 0, '', '0.5.2', 0, 0);"
+        ).is_ok());
+        assert!(DatabaseMigrator::migrate_device_database(&conn, 3, Some("0123456789ABCDEF")).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        let (serial, backups): (String, u32) = conn.query_row(
+            "SELECT serial, (SELECT COUNT(*) FROM device_data JOIN backup_chunks USING (device, version)
+                JOIN backup_metadata USING (device, version)) FROM devices WHERE id = 1",
+            &[],
+            |row| (row.get(0), row.get(1)),
+        ).unwrap();
+        assert_eq!((serial.as_str(), backups), ("0123456789ABCDEF", 1));
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_four_from_three_without_device() {
+        let temp_db = "adb0fabd653a0feaaf4fcce7a18593d8.db"; // md5 of 'test_migration_four_from_three_without_device'
+        assert!(copy("tests/test_databases/dummy_db_v3.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.execute("INSERT INTO device_data (data_hash, version) VALUES ('hash', 1)", &[]).is_ok());
+
+        assert_eq!(
+            format!("{}", DatabaseMigrator::migrate(&conn, 3).unwrap_err()),
+            "a database of version 3 keeps the backups of one device, it is only migrated when imported for that device"
+        );
+
+        // the step is rolled back as a whole
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), 3);
+        assert!(conn.prepare("SELECT 1 FROM devices").is_err());
+        let backups: u32 = conn.query_row("SELECT COUNT(*) FROM device_data", &[], |row| row.get(0)).unwrap();
+        assert_eq!(backups, 1);
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_five_from_four() {
+        let temp_db = "5c9643c19d3450ecbc2c209b8ec2cd04.db"; // md5 of 'test_migration_five_from_four'
+        assert!(copy("tests/test_databases/dummy_db_v4.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.execute("INSERT INTO chunks VALUES ('hash', X'000102')", &[]).is_ok());
+        assert!(DatabaseMigrator::migrate(&conn, 4).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        let (codec, size): (String, i64) = conn.query_row(
+            "SELECT codec, size FROM chunks WHERE hash = 'hash'",
+            &[],
+            |row| (row.get(0), row.get(1)),
+        ).unwrap();
+        assert_eq!((codec.as_str(), size), ("none", 3));
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_six_from_five() {
+        let temp_db = "b9cefd64df13803975ec37393acd231d.db"; // md5 of 'test_migration_six_from_five'
+        assert!(copy("tests/test_databases/dummy_db_v5.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.prepare("SELECT device_info FROM backup_metadata").is_err());
+        assert!(DatabaseMigrator::migrate(&conn, 5).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        assert!(conn.prepare("SELECT device_info FROM backup_metadata").is_ok());
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_seven_from_six() {
+        let temp_db = "f77cd8e563d6bc1c64216f2b53fb65d3.db"; // md5 of 'test_migration_seven_from_six'
+        assert!(copy("tests/test_databases/dummy_db_v6.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.execute_batch(
+            "INSERT INTO backup_metadata VALUES
+                (1, 1, '2018-06-01 08:00:00', 'emulator-5554', NULL, NULL, 0, 0, 0, '', '0.5.2', 0, 0, NULL)"
+        ).is_ok());
+        assert!(DatabaseMigrator::migrate(&conn, 6).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        let excluded: String = conn.query_row(
+            "SELECT excluded_packages FROM backup_metadata WHERE device = 1 AND version = 1",
+            &[],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(excluded, "");
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_eight_from_seven() {
+        let temp_db = "015cab4ed42d76346e30b93953116e9c.db"; // md5 of 'test_migration_eight_from_seven'
+        assert!(copy("tests/test_databases/dummy_db_v7.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.execute_batch(
+            "INSERT INTO backup_metadata VALUES
+                (1, 1, '2018-06-01 08:00:00', 'emulator-5554', NULL, NULL, 0, 0, 0, '', '0.5.2', 0, 0, NULL, '')"
+        ).is_ok());
+        assert!(DatabaseMigrator::migrate(&conn, 7).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        let harvested: String = conn.query_row(
+            "SELECT harvested_apps FROM backup_metadata WHERE device = 1 AND version = 1",
+            &[],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(harvested, "[]");
+
+        assert!(conn.close().is_ok());
+        assert!(remove_file(&temp_db).is_ok());
+    }
+
+    #[test]
+    fn test_migration_nine_from_eight() {
+        let temp_db = "21849ccb0fa706dd394d87792e620348.db"; // md5 of 'test_migration_nine_from_eight'
+        assert!(copy("tests/test_databases/dummy_db_v8.db", temp_db).is_ok());
+
+        let conn = Connection::open(&temp_db).unwrap();
+        assert!(conn.prepare("SELECT summary FROM backup_metadata").is_err());
+        assert!(DatabaseMigrator::migrate(&conn, 8).is_ok());
+
+        assert_eq!(DatabaseMigrator::get_database_version(&conn).unwrap(), CURRENT_VERSION);
+        assert!(conn.prepare("SELECT summary FROM backup_metadata").is_ok());
 
         assert!(conn.close().is_ok());
         assert!(remove_file(&temp_db).is_ok());
@@ -1,4 +1,9 @@
-extern crate rusqlite;
-
+pub mod check;
+pub mod chunking;
+pub mod chunks;
+pub mod compression;
+pub mod devices;
 pub mod management;
+pub mod metadata;
 pub mod migration;
+pub mod retention;
@@ -0,0 +1,164 @@
+use chrono::{Datelike, NaiveDateTime};
+use std::collections::BTreeSet;
+
+/// Which backup versions to keep when pruning, in the style of restic's `forget`: the
+/// last n backups, and the latest backup of each of the last n days, weeks and months
+/// having a backup. A version is kept if any of the rules keeps it.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct RetentionPolicy {
+    keep_last: u32,
+    keep_daily: u32,
+    keep_weekly: u32,
+    keep_monthly: u32,
+}
+
+impl RetentionPolicy {
+    pub fn with_keep_last(self, keep_last: u32) -> Self {
+        RetentionPolicy { keep_last, ..self }
+    }
+
+    pub fn with_keep_daily(self, keep_daily: u32) -> Self {
+        RetentionPolicy { keep_daily, ..self }
+    }
+
+    pub fn with_keep_weekly(self, keep_weekly: u32) -> Self {
+        RetentionPolicy { keep_weekly, ..self }
+    }
+
+    pub fn with_keep_monthly(self, keep_monthly: u32) -> Self {
+        RetentionPolicy { keep_monthly, ..self }
+    }
+
+    /// A policy keeping nothing would remove every backup, which is never what is meant.
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0 && self.keep_daily == 0 && self.keep_weekly == 0
+            && self.keep_monthly == 0
+    }
+
+    /// The versions to keep out of `backups`, given as version and creation time. Backups
+    /// without a creation time can not be put into a day, week or month and are kept.
+    pub fn keep(&self, backups: &[(u32, Option<NaiveDateTime>)]) -> BTreeSet<u32> {
+        let mut newest_first = backups.to_vec();
+        newest_first.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let mut kept: BTreeSet<u32> = newest_first
+            .iter()
+            .take(self.keep_last as usize)
+            .map(|&(version, _)| version)
+            .collect();
+
+        kept.extend(
+            newest_first
+                .iter()
+                .filter(|&&(_, created)| created.is_none())
+                .map(|&(version, _)| version),
+        );
+
+        let dated = newest_first
+            .iter()
+            .filter_map(|&(version, created)| created.map(|created| (version, created)))
+            .collect::<Vec<(u32, NaiveDateTime)>>();
+
+        kept.extend(keep_latest_per_period(&dated, self.keep_daily, |created| {
+            (created.year(), created.ordinal())
+        }));
+        kept.extend(keep_latest_per_period(&dated, self.keep_weekly, |created| {
+            let week = created.iso_week();
+            (week.year(), week.week())
+        }));
+        kept.extend(keep_latest_per_period(&dated, self.keep_monthly, |created| {
+            (created.year(), created.month())
+        }));
+
+        kept
+    }
+}
+
+// the newest backup of each of the latest `periods` periods, `backups` are sorted newest
+// first
+fn keep_latest_per_period<F>(backups: &[(u32, NaiveDateTime)], periods: u32, period: F) -> Vec<u32>
+where
+    F: Fn(&NaiveDateTime) -> (i32, u32),
+{
+    let mut seen_periods = Vec::new();
+    let mut kept = Vec::new();
+
+    for &(version, ref created) in backups {
+        if seen_periods.len() >= periods as usize {
+            break;
+        }
+
+        let period = period(created);
+        if !seen_periods.contains(&period) {
+            seen_periods.push(period);
+            kept.push(version);
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use chrono::NaiveDateTime;
+    use database::retention::RetentionPolicy;
+
+    fn backups() -> Vec<(u32, Option<NaiveDateTime>)> {
+        let dates = vec![
+            None,
+            Some("2018-04-30 10:00:00"),
+            Some("2018-05-15 10:00:00"),
+            Some("2018-05-28 10:00:00"),
+            Some("2018-05-29 09:00:00"),
+            Some("2018-05-29 18:00:00"),
+            Some("2018-06-01 08:00:00"),
+            Some("2018-06-01 20:00:00"),
+        ];
+
+        dates
+            .into_iter()
+            .enumerate()
+            .map(|(index, date)| {
+                let created = date.map(|date| {
+                    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap()
+                });
+                (index as u32 + 1, created)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_keep_last() {
+        let kept = RetentionPolicy::default().with_keep_last(2).keep(&backups());
+
+        assert_that!(kept.into_iter().collect::<Vec<u32>>(), is(equal_to(vec![1, 7, 8])));
+    }
+
+    #[test]
+    fn test_keep_daily_weekly_monthly() {
+        let daily = RetentionPolicy::default().with_keep_daily(3).keep(&backups());
+        assert_that!(daily.into_iter().collect::<Vec<u32>>(), is(equal_to(vec![1, 4, 6, 8])));
+
+        // 2018-05-28 is the monday of the week ending with 2018-06-01
+        let weekly = RetentionPolicy::default().with_keep_weekly(2).keep(&backups());
+        assert_that!(weekly.into_iter().collect::<Vec<u32>>(), is(equal_to(vec![1, 3, 8])));
+
+        let monthly = RetentionPolicy::default().with_keep_monthly(12).keep(&backups());
+        assert_that!(monthly.into_iter().collect::<Vec<u32>>(), is(equal_to(vec![1, 2, 6, 8])));
+    }
+
+    #[test]
+    fn test_rules_are_combined() {
+        let policy = RetentionPolicy::default()
+            .with_keep_last(1)
+            .with_keep_monthly(2);
+
+        assert!(!policy.is_empty());
+        assert!(RetentionPolicy::default().is_empty());
+        assert_that!(
+            policy.keep(&backups()).into_iter().collect::<Vec<u32>>(),
+            is(equal_to(vec![1, 6, 8]))
+        );
+    }
+}
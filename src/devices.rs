@@ -1,11 +1,109 @@
 use failure::Error;
+use std::collections::HashMap;
+use std::fmt;
 
 use adb_command::AdbCommand;
 
+/// The state `adb devices` reports a device in, only devices in the `device` state can be
+/// backed up.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DeviceState {
+    Device,
+    Unauthorized,
+    Offline,
+    Recovery,
+    Sideload,
+    Bootloader,
+    NoPermissions,
+    Other(String),
+}
+
+impl DeviceState {
+    fn parse(state: &str) -> DeviceState {
+        match state {
+            "device" => DeviceState::Device,
+            "unauthorized" => DeviceState::Unauthorized,
+            "offline" => DeviceState::Offline,
+            "recovery" => DeviceState::Recovery,
+            "sideload" => DeviceState::Sideload,
+            "bootloader" => DeviceState::Bootloader,
+            "no permissions" => DeviceState::NoPermissions,
+            other => DeviceState::Other(other.to_string()),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        match *self {
+            DeviceState::Device => "device",
+            DeviceState::Unauthorized => "unauthorized",
+            DeviceState::Offline => "offline",
+            DeviceState::Recovery => "recovery",
+            DeviceState::Sideload => "sideload",
+            DeviceState::Bootloader => "bootloader",
+            DeviceState::NoPermissions => "no permissions",
+            DeviceState::Other(ref other) => other,
+        }
+    }
+
+    pub fn is_usable(&self) -> bool {
+        *self == DeviceState::Device
+    }
+
+    /// What to do to use a device in this state, None if it can be used.
+    pub fn hint(&self) -> Option<&'static str> {
+        match *self {
+            DeviceState::Device => None,
+            DeviceState::Unauthorized => Some("authorize the RSA prompt on the phone"),
+            DeviceState::Offline => Some("reconnect the device or restart adb with `adb kill-server`"),
+            DeviceState::Recovery | DeviceState::Sideload | DeviceState::Bootloader => {
+                Some("boot the device into android")
+            }
+            DeviceState::NoPermissions => Some("allow access to the usb device, e.g. with a udev rule"),
+            DeviceState::Other(_) => Some("the device can not be used in this state"),
+        }
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.token())
+    }
+}
+
+/// A device as listed by `adb devices -l`.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Device {
     pub id: String,
-    pub details: String,
+    pub state: DeviceState,
+    pub usb: Option<String>,
+    pub product: Option<String>,
+    pub model: Option<String>,
+    pub device: Option<String>,
+    pub transport_id: Option<u32>,
+}
+
+/// A file system of the device as reported by `df`, sizes in bytes.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Storage {
+    pub mount_point: String,
+    pub size: u64,
+    pub used: u64,
+    pub available: u64,
+}
+
+/// What a device tells about itself, recorded with each backup. Everything the device did
+/// not tell is left out.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub android_version: Option<String>,
+    pub sdk_level: Option<u32>,
+    pub manufacturer: Option<String>,
+    pub serial: Option<String>,
+    pub fingerprint: Option<String>,
+    /// `/data` and `/sdcard`
+    pub storage: Vec<Storage>,
+    /// in percent
+    pub battery_level: Option<u8>,
 }
 
 impl Device {
@@ -17,48 +115,248 @@ impl Device {
         return Ok(Device::parse_devices(output));
     }
 
-    fn parse_devices(unparsed_devices: String) -> Vec<Device> {
-        let mut devices: Vec<Device> = Vec::new();
+    /// Value of a system property of the device, e.g. `ro.build.version.release`.
+    pub fn get_property(device_id: &str, property: &str) -> Result<String, Error> {
+        let output = AdbCommand::command("shell")
+            .with_args(vec!["getprop", property])
+            .with_device_id(Some(device_id))
+            .execute()?;
+
+        Ok(output.trim().to_string())
+    }
+
+    /// The serial of the device's hardware, which stays the same however the device is
+    /// connected, unlike the id adb reaches it with. None if the device can not be asked
+    /// or does not tell.
+    pub fn hardware_serial(device_id: &str) -> Option<String> {
+        for property in &["ro.serialno", "ro.boot.serialno"] {
+            match Device::get_property(device_id, property) {
+                Ok(ref serial) if serial.is_empty() => continue,
+                Ok(serial) => return Some(serial),
+                Err(_) => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Android version, manufacturer, storage and battery of the device. Fails if the
+    /// properties of the device can not be read, storage and battery are left out if the
+    /// device does not report them.
+    pub fn info(device_id: &str) -> Result<DeviceInfo, Error> {
+        let properties = Device::parse_properties(
+            &AdbCommand::command("shell")
+                .with_arg("getprop")
+                .with_device_id(Some(device_id))
+                .execute()?,
+        );
+        let property = |name: &str| properties.get(name).filter(|value| !value.is_empty()).cloned();
 
+        let storage = AdbCommand::command("shell")
+            .with_args(vec!["df", "/data", "/sdcard"])
+            .with_device_id(Some(device_id))
+            .execute()
+            .map(|output| Device::parse_storage(&output))
+            .unwrap_or_default();
+        let battery_level = AdbCommand::command("shell")
+            .with_args(vec!["dumpsys", "battery"])
+            .with_device_id(Some(device_id))
+            .execute()
+            .ok()
+            .and_then(|output| Device::parse_battery_level(&output));
+
+        Ok(DeviceInfo {
+            android_version: property("ro.build.version.release"),
+            sdk_level: property("ro.build.version.sdk").and_then(|sdk_level| sdk_level.parse().ok()),
+            manufacturer: property("ro.product.manufacturer"),
+            serial: property("ro.serialno"),
+            fingerprint: property("ro.build.fingerprint"),
+            storage,
+            battery_level,
+        })
+    }
+
+    /// The details `adb devices -l` reports besides the state, e.g.
+    /// `product:lineage_oneplus3 model:ONEPLUS_A3003 device:OnePlus3T transport_id:8`.
+    pub fn details(&self) -> String {
+        let transport_id = self.transport_id.map(|transport_id| transport_id.to_string());
+        let details = [
+            ("usb", &self.usb),
+            ("product", &self.product),
+            ("model", &self.model),
+            ("device", &self.device),
+            ("transport_id", &transport_id),
+        ];
+
+        details
+            .iter()
+            .filter_map(|&(key, value)| value.as_ref().map(|value| format!("{}:{}", key, value)))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn parse_devices(unparsed_devices: String) -> Vec<Device> {
         unparsed_devices
             .lines()
-            .filter(|x| !x.contains("List of devices attached"))
-            .for_each(|unparsed_device| {
-                let splitted_device = unparsed_device.split("device ").collect::<Vec<&str>>();
-
-                if let Some(device_id) = splitted_device.get(0) {
-                    if let Some(device_details) = splitted_device.get(1) {
-                        let device = Device {
-                            id: device_id.trim().to_string(),
-                            details: device_details.trim().to_string(),
-                        };
-                        devices.push(device);
+            .filter(|line| !line.contains("List of devices attached"))
+            .filter_map(Device::parse_device)
+            .collect()
+    }
+
+    // `<id> <state> <key>:<value>...`, where the state of a device adb may not access is
+    // `no permissions` followed by an explanation
+    fn parse_device(unparsed_device: &str) -> Option<Device> {
+        let mut fields = unparsed_device.split_whitespace();
+        let id = fields.next()?.to_string();
+
+        let state = match fields.next()? {
+            "no" => {
+                fields.next();
+                DeviceState::NoPermissions
+            }
+            state => DeviceState::parse(state),
+        };
+
+        let mut device = Device {
+            id,
+            state,
+            usb: None,
+            product: None,
+            model: None,
+            device: None,
+            transport_id: None,
+        };
+
+        for field in fields {
+            let mut detail = field.splitn(2, ':');
+            let (key, value) = match (detail.next(), detail.next()) {
+                (Some(key), Some(value)) => (key, Some(value.to_string())),
+                _ => continue,
+            };
+
+            match key {
+                "usb" => device.usb = value,
+                "product" => device.product = value,
+                "model" => device.model = value,
+                "device" => device.device = value,
+                "transport_id" => device.transport_id = value.and_then(|value| value.parse().ok()),
+                _ => {}
+            }
+        }
+
+        Some(device)
+    }
+
+    // `[<name>]: [<value>]` per line, as `getprop` lists all properties
+    fn parse_properties(output: &str) -> HashMap<String, String> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut property = line.trim().splitn(2, "]: [");
+                match (property.next(), property.next()) {
+                    (Some(name), Some(value)) if name.starts_with('[') && value.ends_with(']') => {
+                        Some((name[1..].to_string(), value[..value.len() - 1].to_string()))
                     }
+                    _ => None,
                 }
-            });
-        devices
+            })
+            .collect()
+    }
+
+    // toybox prints `Filesystem 1K-blocks Used Available Use% Mounted on`, the toolbox of
+    // android before 6.0 `Filesystem Size Used Free Blksize` with sizes like `1.2G` and the
+    // mount point as file system
+    fn parse_storage(output: &str) -> Vec<Storage> {
+        output
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields = line.split_whitespace().collect::<Vec<&str>>();
+                let (mount_point, sizes) = match fields.len() {
+                    6 => (fields[5], &fields[1..4]),
+                    5 => (fields[0], &fields[1..4]),
+                    _ => return None,
+                };
+                let sizes = sizes.iter().map(|size| Device::parse_size(size)).collect::<Option<Vec<u64>>>()?;
+
+                Some(Storage {
+                    mount_point: mount_point.to_string(),
+                    size: sizes[0],
+                    used: sizes[1],
+                    available: sizes[2],
+                })
+            })
+            .collect()
+    }
+
+    // in bytes, a size without unit is in 1K blocks
+    fn parse_size(size: &str) -> Option<u64> {
+        let (number, factor) = match size.chars().last()? {
+            'K' => (&size[..size.len() - 1], 1u64 << 10),
+            'M' => (&size[..size.len() - 1], 1u64 << 20),
+            'G' => (&size[..size.len() - 1], 1u64 << 30),
+            'T' => (&size[..size.len() - 1], 1u64 << 40),
+            _ => (size, 1u64 << 10),
+        };
+
+        number.parse::<f64>().ok().map(|number| (number * factor as f64) as u64)
+    }
+
+    // `level: <n>` of `scale: <n>`, which is 100 unless told otherwise
+    fn parse_battery_level(output: &str) -> Option<u8> {
+        let value = |key: &str| {
+            output
+                .lines()
+                .filter_map(|line| {
+                    let mut entry = line.trim().splitn(2, ':');
+                    match (entry.next(), entry.next()) {
+                        (Some(name), Some(value)) if name == key => value.trim().parse::<u32>().ok(),
+                        _ => None,
+                    }
+                })
+                .next()
+        };
+
+        let level = value("level")?;
+        let scale = value("scale").filter(|scale| *scale > 0).unwrap_or(100);
+
+        Some((level * 100 / scale).min(100) as u8)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use hamcrest::prelude::*;
-    use devices::Device;
+    use devices::{Device, DeviceState, Storage};
+
+    fn device(id: &str, state: DeviceState) -> Device {
+        Device {
+            id: id.to_string(),
+            state,
+            usb: None,
+            product: None,
+            model: None,
+            device: None,
+            transport_id: None,
+        }
+    }
 
     #[test]
     fn test_parse_mocked_devices() {
         let devices = vec![
             Device {
-                id: "emulator-5554".to_string(),
-                details: "product:sdk_google_phone_x86 model:Android_SDK_built_for_x86 \
-                          device:generic_x86 transport_id:9"
-                    .to_string(),
+                product: Some("sdk_google_phone_x86".to_string()),
+                model: Some("Android_SDK_built_for_x86".to_string()),
+                device: Some("generic_x86".to_string()),
+                transport_id: Some(9),
+                ..device("emulator-5554", DeviceState::Device)
             },
             Device {
-                id: "192.168.2.100:5555".to_string(),
-                details:
-                "product:lineage_oneplus3 model:ONEPLUS_A3003 device:OnePlus3T transport_id:8"
-                    .to_string(),
+                product: Some("lineage_oneplus3".to_string()),
+                model: Some("ONEPLUS_A3003".to_string()),
+                device: Some("OnePlus3T".to_string()),
+                transport_id: Some(8),
+                ..device("192.168.2.100:5555", DeviceState::Device)
             },
         ];
 
@@ -69,6 +367,69 @@ mod tests {
         assert_that!(Device::parse_devices(mocked_output), is(equal_to(devices)));
     }
 
+    #[test]
+    fn test_parse_mocked_unusable_devices() {
+        let devices = vec![
+            Device {
+                usb: Some("1-1".to_string()),
+                transport_id: Some(3),
+                ..device("0123456789ABCDEF", DeviceState::Unauthorized)
+            },
+            Device { transport_id: Some(4), ..device("emulator-5556", DeviceState::Offline) },
+            Device {
+                usb: Some("1-2".to_string()),
+                product: Some("OnePlus3".to_string()),
+                model: Some("ONEPLUS_A3003".to_string()),
+                device: Some("OnePlus3T".to_string()),
+                transport_id: Some(5),
+                ..device("a1b2c3d4", DeviceState::Recovery)
+            },
+            Device { usb: Some("1-3".to_string()), ..device("????????????", DeviceState::NoPermissions) },
+            device("b2c3d4e5", DeviceState::Bootloader),
+        ];
+
+        let mocked_output = "List of devices attached\n\
+            0123456789ABCDEF       unauthorized usb:1-1 transport_id:3\n\
+            emulator-5556          offline transport_id:4\n\
+            a1b2c3d4               recovery usb:1-2 product:OnePlus3 model:ONEPLUS_A3003 device:OnePlus3T transport_id:5\n\
+            ????????????           no permissions (user in plugdev group; are your udev rules wrong?); \
+            see [http://developer.android.com/tools/device.html] usb:1-3\n\
+            b2c3d4e5               bootloader\n\n".to_string();
+        assert_that!(Device::parse_devices(mocked_output), is(equal_to(devices)));
+    }
+
+    #[test]
+    fn test_device_details() {
+        let device = Device {
+            product: Some("lineage_oneplus3".to_string()),
+            model: Some("ONEPLUS_A3003".to_string()),
+            device: Some("OnePlus3T".to_string()),
+            transport_id: Some(8),
+            ..device("192.168.2.100:5555", DeviceState::Device)
+        };
+
+        assert_that!(
+            device.details(),
+            is(equal_to("product:lineage_oneplus3 model:ONEPLUS_A3003 device:OnePlus3T transport_id:8".to_string()))
+        );
+        assert_that!(
+            Device { model: None, transport_id: None, ..device }.details(),
+            is(equal_to("product:lineage_oneplus3 device:OnePlus3T".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_device_state() {
+        assert!(DeviceState::Device.is_usable());
+        assert!(!DeviceState::Unauthorized.is_usable());
+        assert_that!(DeviceState::Device.hint(), is(equal_to(None)));
+        assert_that!(
+            DeviceState::Unauthorized.hint(),
+            is(equal_to(Some("authorize the RSA prompt on the phone")))
+        );
+        assert_that!(DeviceState::parse("host"), is(equal_to(DeviceState::Other("host".to_string()))));
+    }
+
     #[test]
     fn test_parse_mocked_no_connected_device() {
         let mocked_output = "List of devices attached\n\n\n".to_string();
@@ -77,4 +438,89 @@ mod tests {
             is(equal_to(Vec::new()))
         );
     }
+
+    #[test]
+    fn test_parse_mocked_properties() {
+        let mocked_output = "[dalvik.vm.heapsize]: [512m]\n\
+            [ro.build.fingerprint]: [google/sdk_gphone_x86/generic_x86:8.1.0/OSM1.180201.007/4586646:user/release-keys]\n\
+            [ro.build.version.release]: [8.1.0]\n\
+            [ro.build.version.sdk]: [27]\n\
+            [ro.product.manufacturer]: [Google]\n\
+            [ro.serialno]: []\n";
+
+        let properties = Device::parse_properties(mocked_output);
+
+        assert_that!(properties.len(), is(equal_to(6)));
+        assert_that!(properties["ro.build.version.release"].as_str(), is(equal_to("8.1.0")));
+        assert_that!(properties["ro.product.manufacturer"].as_str(), is(equal_to("Google")));
+        assert_that!(
+            properties["ro.build.fingerprint"].as_str(),
+            is(equal_to("google/sdk_gphone_x86/generic_x86:8.1.0/OSM1.180201.007/4586646:user/release-keys"))
+        );
+        assert_that!(properties["ro.serialno"].as_str(), is(equal_to("")));
+    }
+
+    #[test]
+    fn test_parse_mocked_storage() {
+        let mocked_output = "Filesystem            1K-blocks    Used Available Use% Mounted on\n\
+            /dev/block/dm-0        52233476 10962416  41139604  22% /data\n\
+            /dev/fuse              52233476 10962416  41139604  22% /storage/emulated\n";
+
+        assert_that!(
+            Device::parse_storage(mocked_output),
+            is(equal_to(vec![
+                Storage {
+                    mount_point: "/data".to_string(),
+                    size: 52233476 * 1024,
+                    used: 10962416 * 1024,
+                    available: 41139604 * 1024,
+                },
+                Storage {
+                    mount_point: "/storage/emulated".to_string(),
+                    size: 52233476 * 1024,
+                    used: 10962416 * 1024,
+                    available: 41139604 * 1024,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_mocked_toolbox_storage() {
+        let mocked_output = "Filesystem               Size     Used     Free   Blksize\n\
+            /data                    5.9G     1.5G     4.4G   4096\n\
+            /sdcard: Permission denied\n";
+
+        assert_that!(
+            Device::parse_storage(mocked_output),
+            is(equal_to(vec![Storage {
+                mount_point: "/data".to_string(),
+                size: (5.9 * (1u64 << 30) as f64) as u64,
+                used: 3 * (1u64 << 29),
+                available: (4.4 * (1u64 << 30) as f64) as u64,
+            }]))
+        );
+    }
+
+    #[test]
+    fn test_parse_mocked_battery_level() {
+        let mocked_output = "Current Battery Service state:\n  \
+            AC powered: false\n  \
+            USB powered: true\n  \
+            Wireless powered: false\n  \
+            Max charging current: 500000\n  \
+            status: 2\n  \
+            health: 2\n  \
+            present: true\n  \
+            level: 85\n  \
+            scale: 100\n  \
+            voltage: 4158\n";
+
+        assert_that!(Device::parse_battery_level(mocked_output), is(equal_to(Some(85))));
+        assert_that!(Device::parse_battery_level("  level: 100\n  scale: 200\n"), is(equal_to(Some(50))));
+        assert_that!(
+            Device::parse_battery_level("Can't find service: battery\n"),
+            is(equal_to(None))
+        );
+    }
 }
@@ -15,6 +15,16 @@ impl FileTransfer {
         Ok(())
     }
 
+    /// Pulls the file at `path` on the device into `target` on the pc.
+    pub fn pull_to(device_id: Option<&str>, path: &str, target: &str) -> Result<(), Error> {
+        AdbCommand::command("pull")
+            .with_args(vec![path, target, "-a"])
+            .with_device_id(device_id)
+            .execute()?;
+
+        Ok(())
+    }
+
     pub fn push(
         device_id: Option<&str>,
         src_path: &str,
@@ -31,31 +41,38 @@ impl FileTransfer {
 
 #[cfg(test)]
 mod tests {
-    use devices::Device;
+    use std::rc::Rc;
+    use adb_runner::{set_runner, AdbOutput, ScriptedRunner};
     use file_transfer::FileTransfer;
 
-    //#[test]
-    #[allow(dead_code)]
+    #[test]
     fn test_simple_pull() {
-        if Device::list_devices().unwrap().len() > 0 {
-            assert!(
-                FileTransfer::pull(Some("emulator-5554"), "/sdcard/la/")
-                    .is_ok()
-            )
-        }
+        let runner = ScriptedRunner::new().expect(
+            &["-s", "emulator-5554", "pull", "/sdcard/la/", "-a"],
+            AdbOutput::stdout("/sdcard/la/: 1 file pulled."),
+        );
+        set_runner(Rc::new(runner));
+
+        assert!(
+            FileTransfer::pull(Some("emulator-5554"), "/sdcard/la/")
+                .is_ok()
+        )
     }
 
-    //#[test]
-    #[allow(dead_code)]
+    #[test]
     fn test_simple_push() {
-        if Device::list_devices().unwrap().len() > 0 {
-            assert!(
-                FileTransfer::push(
-                    Some("emulator-5554"),
-                    "Cargo.toml",
-                    "/sdcard/la/",
-                ).is_ok()
-            )
-        }
+        let runner = ScriptedRunner::new().expect(
+            &["-s", "emulator-5554", "push", "Cargo.toml", "/sdcard/la/"],
+            AdbOutput::stdout("Cargo.toml: 1 file pushed."),
+        );
+        set_runner(Rc::new(runner));
+
+        assert!(
+            FileTransfer::push(
+                Some("emulator-5554"),
+                "Cargo.toml",
+                "/sdcard/la/",
+            ).is_ok()
+        )
     }
 }
@@ -1,10 +1,18 @@
 mod devices;
+mod apps;
+mod apk_harvest;
 mod logging;
 mod backup;
+mod bundle;
 mod restore;
 mod adb_command;
+mod adb_client;
+mod adb_runner;
 mod file_transfer;
 mod database;
+mod repository;
+mod store;
+mod android_backup;
 
 extern crate chrono;
 extern crate fern;
@@ -18,12 +26,55 @@ extern crate hamcrest;
 
 extern crate failure;
 #[macro_use] extern crate failure_derive;
+#[macro_use] extern crate serde_derive;
 
+extern crate aes;
+extern crate argon2;
+extern crate chacha20poly1305;
+extern crate flate2;
+extern crate hex;
+extern crate hmac;
+extern crate pbkdf2;
+extern crate rand;
+extern crate rusqlite;
+extern crate serde_json;
+extern crate sha1;
+extern crate sha2;
+extern crate tar;
+extern crate ureq;
+#[cfg(feature = "xz2")]
+extern crate xz2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+use android_backup::encryption::EncryptionError;
+use apk_harvest::{ApkHarvest, HarvestedApp};
+use apps::{App, Eligibility};
 use backup::Backup;
-use database::management::DatabaseManager;
-use devices::Device;
+use chrono::{NaiveDate, NaiveDateTime};
+use database::management::{Integrity, StoredBackup};
+use database::metadata::BackupMetadata;
+use devices::{Device, DeviceInfo};
 use failure::{err_msg, Error};
 use restore::Restore;
+use std::fs::{metadata, remove_file, File};
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+use store::BackupStore;
+
+pub use android_backup::entry::{BackupEntry, Domain};
+pub use apps::AppFilter;
+pub use android_backup::header::{BackupHeader, Encryption};
+pub use android_backup::encryption::EncryptionParameters;
+pub use android_backup::reader::BackupReader;
+pub use android_backup::summary::{BackupSummary, PackageSummary};
+pub use android_backup::writer::BackupWriter;
+pub use database::compression::Codec;
+pub use database::retention::RetentionPolicy;
+pub use store::{StoreKind, StoreStats};
+pub use adb_runner::{AdbOutput, AdbRunner, ProcessRunner, ScriptedRunner, ServerRunner};
 
 pub fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -33,6 +84,43 @@ pub fn setup_logging(verbosity: u64) {
     logging::setup_logging(verbosity).expect("failed to initialize logging.");
 }
 
+/// Sets how adb is driven on the current thread, by default the adb server is talked
+/// to directly (`ServerRunner`).
+pub fn set_adb_runner<R: AdbRunner + 'static>(runner: R) {
+    adb_runner::set_runner(Rc::new(runner));
+}
+
+/// Sets the directory the backups of all devices are kept in on the current thread, by
+/// default the one in `ADBACKUP_REPO` or the current directory.
+pub fn set_repository(path: &Path) {
+    repository::set_repository(path);
+}
+
+/// Sets the passphrase of an encrypted repository on the current thread, the content of a
+/// key file is used as passphrase as well.
+pub fn set_passphrase(passphrase: &str) {
+    repository::set_passphrase(passphrase);
+}
+
+/// Sets how the backups are kept in the repository on the current thread, by default in
+/// one sqlite database (`StoreKind::Sqlite`).
+pub fn set_store(kind: StoreKind) {
+    repository::set_store_kind(kind);
+}
+
+/// Sets whether the base and split apks of the backed up apps are pulled into backups made
+/// on the current thread. Restoring such a backup installs the apps before their data.
+pub fn set_apk_harvest(enabled: bool) {
+    apk_harvest::set_enabled(enabled);
+}
+
+/// Sets the codec backups are compressed with when stored on the current thread, by default
+/// zstd if adbackup is built with it, else gzip. Stored backups keep their codec. Backups in
+/// an encrypted repository are not compressed, setting a codec for one is an error.
+pub fn set_compression(codec: Codec) {
+    repository::set_compression(codec);
+}
+
 pub fn get_printable_device_list() -> Result<String, Error> {
     let devices = Device::list_devices()?;
     
@@ -43,7 +131,15 @@ pub fn get_printable_device_list() -> Result<String, Error> {
         let mut device_list = format!("{}\r\n", devices_found);
 
         devices.into_iter().for_each(|device| {
-            let device_info = format!("Id: '{}', details: '{}'", device.id, device.details);
+            let mut device_info = format!(
+                "Id: '{}', state: {}, details: '{}'",
+                device.id,
+                device.state,
+                device.details()
+            );
+            if let Some(hint) = device.state.hint() {
+                device_info = format!("{} ({})", device_info, hint);
+            }
             info!("{}", device_info);
             device_list = format!("{}\r\n{}", device_list, device_info)
         });
@@ -64,7 +160,7 @@ pub fn get_device_id() -> Result<String, Error> {
 
     let devices = Device::list_devices()?;
     
-    if let Some(first_device) = devices.first() {
+    if let Some(first_device) = devices.iter().find(|device| device.state.is_usable()) {
         Ok(first_device.id.clone())
     } else {
         let no_device_found =
@@ -76,10 +172,27 @@ pub fn get_device_id() -> Result<String, Error> {
     }
 }
 
-pub fn get_printable_app_list(device_id: Option<&str>) -> Result<String, Error> {
+/// Android version, manufacturer, storage and battery of the device, the only connected
+/// one unless `device_id` is given.
+pub fn get_printable_device_info(device_id: Option<&str>) -> Result<String, Error> {
+    let device_id = match device_id {
+        Some(device_id) => String::from(device_id),
+        None => get_device_id()?,
+    };
+
+    let device_info = Device::info(&device_id)?;
+    let printable_info = format!("Device: '{}'\r\n{}", device_id, format_device_info(&device_info));
+    info!("{}", printable_info);
+
+    Ok(printable_info)
+}
+
+/// The apps installed on the device which pass `filter`, with their version, kind,
+/// installer, uid and apk path.
+pub fn get_printable_app_list(device_id: Option<&str>, filter: &AppFilter) -> Result<String, Error> {
     check_too_many_devices(&device_id)?;
 
-    let apps = Backup::list_apps(device_id)?;
+    let apps = App::list(device_id, filter)?;
 
     if apps.len() > 0 {
         let app_found = "Found the following app(s) on device:";
@@ -88,9 +201,9 @@ pub fn get_printable_app_list(device_id: Option<&str>) -> Result<String, Error>
         let mut app_list = format!("{}\r\n", app_found);
 
         apps.into_iter().for_each(|app| {
-            let app_name = format!("{}\n", app);
-            info!("{}", app_name);
-            app_list = format!("{}{}", app_list, app_name)
+            let app_info = format!("{}\n", format_app(&app));
+            info!("{}", app_info);
+            app_list = format!("{}{}", app_list, app_info)
         });
 
         Ok(app_list)
@@ -102,12 +215,44 @@ pub fn get_printable_app_list(device_id: Option<&str>) -> Result<String, Error>
     }
 }
 
+/// Whether `adb backup` backs up the apps installed on the device which pass `filter`.
+pub fn get_printable_backup_eligibility(device_id: Option<&str>, filter: &AppFilter) -> Result<String, Error> {
+    check_too_many_devices(&device_id)?;
+
+    let apps = App::list(device_id, filter)?;
+
+    if apps.len() > 0 {
+        let eligibility_found = "Backup eligibility of the app(s) on device:";
+        info!("{}", eligibility_found);
+
+        let mut eligibility_list = format!("{}\r\n", eligibility_found);
+
+        apps.into_iter().for_each(|app| {
+            let eligibility = match app.eligibility {
+                Some(eligibility) => eligibility.to_string(),
+                None => String::from("unknown, the device did not tell the flags of the package"),
+            };
+            let app_eligibility = format!("{}: {}\n", app.package, eligibility);
+            info!("{}", app_eligibility);
+            eligibility_list = format!("{}{}", eligibility_list, app_eligibility)
+        });
+
+        Ok(eligibility_list)
+    } else {
+        let no_apps_found = "No packages found.";
+        warn!("{}", no_apps_found);
+
+        Ok(String::from(no_apps_found))
+    }
+}
+
 pub fn backup(
     device_id: &str,
     apk: Option<&str>,
     shared: Option<&str>,
     system: Option<&str>,
     only_specified: Option<&str>,
+    password: Option<&str>,
 ) -> Result<String, Error> {
     check_too_many_devices(&Some(device_id))?;
 
@@ -126,10 +271,56 @@ pub fn backup(
         backup_options = backup_options.with_only_specified_app(only_specified);
     }
 
-    Backup::backup(backup_options)?;
+    // recorded with the backup, a device which can not be asked does not stop the backup
+    let device_model = Device::list_devices()
+        .ok()
+        .and_then(|devices| devices.into_iter().find(|device| device.id == device_id))
+        .and_then(|device| device.model);
+    let device_info = Device::info(device_id).ok();
+
+    let excluded_apps = excluded_apps(device_id, only_specified, system.is_some());
+    excluded_apps.iter().for_each(|&(ref package, ref eligibility)| {
+        let not_backed_up = format!("{} will not be backed up: {}", package, eligibility);
+        match only_specified {
+            Some(_) => warn!("{}", not_backed_up),
+            None => info!("{}", not_backed_up),
+        }
+    });
+
+    // completed once the backup is made
+    let mut backup_metadata = BackupMetadata {
+        created_at: String::new(),
+        device_serial: String::from(device_id),
+        device_model,
+        android_version: device_info.as_ref().and_then(|device_info| device_info.android_version.clone()),
+        applications: apk.is_some(),
+        shared_storage: shared.is_some(),
+        system_apps: system.is_some(),
+        packages: only_specified
+            .map(|apps| apps.split_whitespace().map(String::from).collect())
+            .unwrap_or_default(),
+        adbackup_version: String::from(version()),
+        duration: 0,
+        size: 0,
+        device_info,
+        excluded_packages: excluded_apps.into_iter().map(|(package, _)| package).collect(),
+        harvested_apps: Vec::new(),
+        summary: None,
+    };
+
+    let backup_file = format!("{}.ab", device_id);
 
-    let db_manager = DatabaseManager::open_connection(&device_id)?;
-    db_manager.insert_data(&format!("{}.ab", device_id))?;
+    let started = Instant::now();
+    let stored = Backup::backup(backup_options).and_then(|_| {
+        let duration = started.elapsed();
+        backup_metadata.duration = duration.as_secs() * 1000 + u64::from(duration.subsec_millis());
+
+        store_backup(device_id, &backup_file, only_specified, password, backup_metadata)
+    });
+    // the backup is only kept in the repository, also when it could not be stored there
+    let removed = remove_file(&backup_file);
+    stored?;
+    removed?;
 
     let backup_finished = "Backup finished.";
     info!("{}", backup_finished);
@@ -137,13 +328,64 @@ pub fn backup(
     Ok(String::from(backup_finished))
 }
 
-pub fn restore(device_id: &str) -> Result<String, Error> {
+fn store_backup(
+    device_id: &str,
+    backup_file: &str,
+    only_specified: Option<&str>,
+    password: Option<&str>,
+    mut backup_metadata: BackupMetadata,
+) -> Result<(), Error> {
+    check_backup_password(backup_file, password)?;
+
+    if apk_harvest::is_enabled() {
+        let packages = backed_up_packages(device_id, only_specified, backup_metadata.system_apps)?;
+        backup_metadata.harvested_apps = ApkHarvest::harvest(device_id, &packages, Path::new(backup_file), password)?;
+    }
+    // the summary only tells what the backup holds, one which can not be read does not stop
+    // the backup
+    backup_metadata.summary = read_backup_summary(backup_file, password).unwrap_or_else(|e| {
+        warn!("The contents of the backup could not be read: {}", e);
+        None
+    });
+    backup_metadata.size = metadata(backup_file)?.len();
+
+    let (store, serial) = repository::open(device_id)?;
+    // registered once stored, as a device is only added to the repository with a backup
+    store.put(&serial, Path::new(backup_file), Some(&backup_metadata))?;
+    store.register_device(&serial, device_id, backup_metadata.device_model.as_ref().map(String::as_str))?;
+
+    Ok(())
+}
+
+/// Restores the backup with `version`, or the latest one created before `before` (UTC,
+/// `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`). Without either the latest backup is restored.
+pub fn restore(
+    device_id: &str,
+    version: Option<u32>,
+    before: Option<&str>,
+    password: Option<&str>,
+) -> Result<String, Error> {
     check_too_many_devices(&Some(device_id))?;
 
-    let db_manager = DatabaseManager::open_connection(&device_id)?;
-    db_manager.get_latest_backup(&format!("{}.ab", device_id))?;
+    let (store, serial) = repository::open(device_id)?;
+    let version = match (version, before) {
+        (Some(_), Some(_)) => return Err(err_msg("Only one of version and date can be given")),
+        (Some(version), None) => version,
+        (None, Some(before)) => store.get_version_before(&serial, &parse_backup_date(before)?)?,
+        (None, None) => store.get_latest_version(&serial)?,
+    };
+    let harvested_apps = store
+        .get_stored_backup(&serial, version)?
+        .metadata
+        .map(|metadata| metadata.harvested_apps)
+        .unwrap_or_default();
+
+    let backup_file = format!("{}.ab", device_id);
+    store.get(&serial, version, Path::new(&backup_file))?;
 
-    Restore::restore(device_id)?;
+    let restored = restore_backup(device_id, &backup_file, &harvested_apps, password);
+    remove_file(&backup_file)?;
+    restored?;
 
     let restore_finished = "Restore finished.";
     info!("{}", restore_finished);
@@ -151,6 +393,361 @@ pub fn restore(device_id: &str) -> Result<String, Error> {
     Ok(String::from(restore_finished))
 }
 
+fn restore_backup(
+    device_id: &str,
+    backup_file: &str,
+    harvested_apps: &[HarvestedApp],
+    password: Option<&str>,
+) -> Result<(), Error> {
+    check_backup_password(backup_file, password)?;
+
+    // the apps are installed first, android only restores the data of installed apps
+    if !harvested_apps.is_empty() {
+        // but only once the whole backup could be read, no app is installed for a backup
+        // android would not restore
+        read_backup_summary(backup_file, password)?.ok_or(EncryptionError::PasswordRequired)?;
+
+        let installed = ApkHarvest::install(device_id, harvested_apps, Path::new(backup_file), password)?;
+        info!("Installed {} of {} app(s) from the backup", installed.len(), harvested_apps.len());
+    }
+
+    Restore::restore(device_id)
+}
+
+/// Unpacks a stored backup into the directory `target`, or into an uncompressed tar if
+/// `target` ends with `.tar`. Without `version` the latest backup is used.
+pub fn extract(
+    device_id: &str,
+    version: Option<u32>,
+    target: &str,
+    password: Option<&str>,
+) -> Result<String, Error> {
+    let (store, serial) = repository::open(device_id)?;
+
+    let version = match version {
+        Some(version) => version,
+        None => store.get_latest_version(&serial)?,
+    };
+    let backup_file = format!("{}.extract.ab", device_id);
+    store.get(&serial, version, Path::new(&backup_file))?;
+
+    let extracted = extract_backup(&backup_file, target, password);
+    remove_file(&backup_file)?;
+    let extract_finished = extracted?;
+
+    info!("{}", extract_finished);
+
+    Ok(extract_finished)
+}
+
+/// Lists the stored backups of a device with the summary recorded when they were stored,
+/// the backups themselves are not read.
+pub fn get_printable_backup_list(device_id: &str) -> Result<String, Error> {
+    let (store, serial) = repository::open(device_id)?;
+    let backups = store.list(&serial)?;
+
+    if backups.len() > 0 {
+        let backups_found = "Found the following backup(s) of device:";
+        info!("{}", backups_found);
+
+        let mut backup_list = format!("{}\r\n", backups_found);
+
+        for backup in backups {
+            let summary = backup.metadata.as_ref().and_then(|metadata| metadata.summary.clone());
+            let backup_info = format_stored_backup(&backup, &summary);
+            info!("{}", backup_info);
+            backup_list = format!("{}\r\n{}", backup_list, backup_info)
+        }
+
+        Ok(backup_list)
+    } else {
+        let no_backups_found = "No backups found.";
+        warn!("{}", no_backups_found);
+
+        Ok(String::from(no_backups_found))
+    }
+}
+
+pub fn get_printable_backup(
+    device_id: &str,
+    version: u32,
+    password: Option<&str>,
+) -> Result<String, Error> {
+    let (store, serial) = repository::open(device_id)?;
+    let backup = store.get_stored_backup(&serial, version)?;
+
+    // versions whose contents were not recorded are read
+    let summary = match backup.metadata.as_ref().and_then(|metadata| metadata.summary.clone()) {
+        Some(summary) => Some(summary),
+        None => summarize_backup(&*store, &serial, &backup, device_id, password)?,
+    };
+    let mut backup_info = format_stored_backup(&backup, &summary);
+
+    if let Some(ref metadata) = backup.metadata {
+        backup_info = format!(
+            "{}\r\nDevice: '{}' ({}), android: {}, duration: {}.{:03} s, adbackup: {}",
+            backup_info,
+            metadata.device_model.as_ref().map(String::as_str).unwrap_or("unknown"),
+            metadata.device_serial,
+            metadata.android_version.as_ref().map(String::as_str).unwrap_or("unknown"),
+            metadata.duration / 1000,
+            metadata.duration % 1000,
+            metadata.adbackup_version
+        );
+
+        if let Some(ref device_info) = metadata.device_info {
+            backup_info = format!("{}\r\n{}", backup_info, format_device_info(device_info));
+        }
+        if !metadata.harvested_apps.is_empty() {
+            let harvested = metadata
+                .harvested_apps
+                .iter()
+                .map(|app| format!("{} ({} apk(s))", app.package, app.apks.len()))
+                .collect::<Vec<String>>();
+            backup_info = format!("{}\r\nApks: {}", backup_info, harvested.join(", "));
+        }
+        if !metadata.excluded_packages.is_empty() {
+            backup_info = format!(
+                "{}\r\nNot backed up as they do not allow it: {}",
+                backup_info,
+                metadata.excluded_packages.join(", ")
+            );
+        }
+    }
+
+    if let Some(summary) = summary {
+        backup_info = format!("{}\r\n\r\nPackages:", backup_info);
+
+        summary.packages.iter().for_each(|package| {
+            let domains = package
+                .domains
+                .iter()
+                .map(|domain| domain.token())
+                .collect::<Vec<&str>>()
+                .join(", ");
+
+            backup_info = format!(
+                "{}\r\n{}: {} file(s), {} bytes ({})",
+                backup_info, package.package, package.files, package.size, domains
+            )
+        });
+    }
+
+    info!("{}", backup_info);
+
+    Ok(backup_info)
+}
+
+/// Checks the stored backups of a device against their hashes, corrupted or missing
+/// backups are reported as error.
+pub fn verify(device_id: &str) -> Result<String, Error> {
+    let (store, serial) = repository::open(device_id)?;
+    let verified = store.verify(&serial)?;
+
+    let mut damaged = 0;
+    let mut report = String::new();
+
+    verified.iter().for_each(|backup| {
+        let problem = match backup.integrity {
+            Integrity::Intact => return,
+            Integrity::Corrupt => "data does not match its hash, the backup is corrupted",
+            Integrity::Missing => "data is missing",
+            Integrity::Unhashed => "stored without hash, can not be verified",
+        };
+
+        if backup.integrity != Integrity::Unhashed {
+            damaged += 1;
+        }
+
+        let backup_info = format!("Version: {}, {}", backup.version, problem);
+        warn!("{}", backup_info);
+        report = format!("{}\r\n{}", report, backup_info);
+    });
+
+    if damaged > 0 {
+        return Err(err_msg(format!(
+            "{} of {} backup(s) damaged:{}",
+            damaged,
+            verified.len(),
+            report
+        )));
+    }
+
+    let verify_finished = format!("Verified {} backup(s).{}", verified.len(), report);
+    info!("{}", verify_finished);
+
+    Ok(verify_finished)
+}
+
+/// Removes the backups of a device not kept by `policy` and gives their space back, with
+/// `dry_run` the backups are only listed.
+pub fn prune(device_id: &str, policy: &RetentionPolicy, dry_run: bool) -> Result<String, Error> {
+    if policy.is_empty() {
+        return Err(err_msg("At least one of keep-last, keep-daily, keep-weekly and keep-monthly must be given"));
+    }
+
+    let (store, serial) = repository::open(device_id)?;
+    let backups = store.list(&serial)?;
+
+    let created = backups
+        .iter()
+        .map(|backup| (backup.version, backup.created()))
+        .collect::<Vec<(u32, Option<NaiveDateTime>)>>();
+    let kept = policy.keep(&created);
+
+    let removed = backups
+        .iter()
+        .filter(|backup| !kept.contains(&backup.version))
+        .collect::<Vec<&StoredBackup>>();
+
+    if removed.is_empty() {
+        let nothing_to_remove = format!("No backups to remove, keeping {} backup(s).", backups.len());
+        info!("{}", nothing_to_remove);
+
+        return Ok(nothing_to_remove);
+    }
+
+    let mut report = String::new();
+    removed.iter().for_each(|backup| {
+        report = format!(
+            "{}\r\nVersion: {}, created: '{}', size: {} bytes",
+            report, backup.version, backup.date_created, backup.size
+        );
+    });
+
+    let prune_finished = match dry_run {
+        true => format!("Would remove {} of {} backup(s):{}", removed.len(), backups.len(), report),
+        false => {
+            let size_before = store.stats()?.size;
+
+            let versions = removed.iter().map(|backup| backup.version).collect::<Vec<u32>>();
+            store.delete(&serial, &versions)?;
+
+            let size_after = store.stats()?.size;
+
+            format!(
+                "Removed {} of {} backup(s), {} bytes freed:{}",
+                removed.len(),
+                backups.len(),
+                size_before.saturating_sub(size_after),
+                report
+            )
+        }
+    };
+    info!("{}", prune_finished);
+
+    Ok(prune_finished)
+}
+
+/// Prepares the repository, with `encrypt` the backups kept in it are encrypted with a key
+/// sealed by the passphrase set with `set_passphrase`.
+pub fn init(encrypt: bool) -> Result<String, Error> {
+    repository::init(encrypt)?;
+
+    let init_finished = format!(
+        "Initialized {}repository at {}.",
+        if encrypt { "encrypted " } else { "" },
+        repository::repository_path().display()
+    );
+    info!("{}", init_finished);
+
+    Ok(init_finished)
+}
+
+/// Changes the passphrase of an encrypted repository from the one set with `set_passphrase`
+/// to `new_passphrase`.
+pub fn change_passphrase(new_passphrase: &str) -> Result<String, Error> {
+    repository::change_passphrase(new_passphrase)?;
+
+    let passphrase_changed = "Changed the passphrase of the repository.";
+    info!("{}", passphrase_changed);
+
+    Ok(String::from(passphrase_changed))
+}
+
+/// Writes `versions` of the backups of a device, all of them if empty, into the new bundle
+/// file `bundle`, from which `import` merges them into another repository.
+pub fn export(device_id: &str, versions: &[u32], bundle: &str) -> Result<String, Error> {
+    let (store, serial) = repository::open(device_id)?;
+    let exported = bundle::export(&*store, &serial, versions, Path::new(bundle))?;
+
+    let export_finished = format!("Exported {} backup(s) of device {} to {}.", exported, serial, bundle);
+    info!("{}", export_finished);
+
+    Ok(export_finished)
+}
+
+/// Merges the backups in the bundle file `bundle` into the repository, backups it holds
+/// already are skipped.
+pub fn import(bundle: &str) -> Result<String, Error> {
+    let store = repository::open_store()?;
+    let devices = bundle::import(&*store, Path::new(bundle))?;
+
+    let mut import_finished = format!("Imported the backups of {} device(s) from {}:", devices.len(), bundle);
+    for device in devices {
+        import_finished = format!(
+            "{}\r\nDevice: {}, imported: {}, already in the repository: {}",
+            import_finished, device.device, device.imported, device.skipped
+        );
+    }
+    info!("{}", import_finished);
+
+    Ok(import_finished)
+}
+
+/// Checks the repository for damage, e.g. left by a backup which was interrupted or a full
+/// disk. With `repair` broken backups are moved out of the repository into its quarantine
+/// and what unfinished backups left over is removed.
+pub fn check(repair: bool) -> Result<String, Error> {
+    let store = repository::open_store()?;
+    let problems = store.check()?;
+
+    if problems.is_empty() {
+        let check_finished = "Checked the repository, no problems found.";
+        info!("{}", check_finished);
+
+        return Ok(String::from(check_finished));
+    }
+
+    let mut report = String::new();
+    problems.iter().for_each(|problem| {
+        warn!("{}", problem);
+        report = format!("{}\r\n{}", report, problem);
+    });
+
+    if !repair {
+        return Err(err_msg(format!("Found {} problem(s) in the repository:{}", problems.len(), report)));
+    }
+
+    let repaired = problems.iter().filter(|problem| problem.is_repairable()).count();
+    if repaired > 0 {
+        store.repair(&problems)?;
+    }
+
+    let repair_finished = format!(
+        "Repaired {} of {} problem(s) in the repository:{}",
+        repaired,
+        problems.len(),
+        report
+    );
+    info!("{}", repair_finished);
+
+    Ok(repair_finished)
+}
+
+/// How many backups of how many devices the repository holds and the space they take.
+pub fn get_printable_store_stats() -> Result<String, Error> {
+    let stats = repository::open_store()?.stats()?;
+
+    let store_stats = format!(
+        "Stored {} backup(s) of {} device(s), {} bytes.",
+        stats.backups, stats.devices, stats.size
+    );
+    info!("{}", store_stats);
+
+    Ok(store_stats)
+}
+
 pub fn pull(device_id: Option<&str>, path: &str) -> Result<String, Error> {
     check_too_many_devices(&device_id)?;
 
@@ -173,8 +770,242 @@ pub fn push(device_id: Option<&str>, src_path: &str, dst_path: &str) -> Result<S
     Ok(String::from(push_finished))
 }
 
+fn parse_backup_date(date: &str) -> Result<NaiveDateTime, Error> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|date| date.and_hms(0, 0, 0)))
+        .map_err(|_| {
+            err_msg(format!(
+                "Invalid date: {}, expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS",
+                date
+            ))
+        })
+}
+
+// the password of an encrypted backup is entered on the device, so a wrong one is only
+// noticed here by unlocking the backup's master key with it
+fn check_backup_password(backup_file: &str, password: Option<&str>) -> Result<(), Error> {
+    if let Some(password) = password {
+        BackupReader::open(Path::new(backup_file), Some(password))?;
+    }
+
+    Ok(())
+}
+
+fn extract_backup(backup_file: &str, target: &str, password: Option<&str>) -> Result<String, Error> {
+    let mut reader = BackupReader::open(Path::new(backup_file), password)?;
+
+    if target.ends_with(".tar") {
+        let mut tar = File::create(target)?;
+        io::copy(&mut reader.into_tar(), &mut tar)?;
+
+        Ok(format!("Extracted backup to {}.", target))
+    } else {
+        let files = reader.unpack(Path::new(target))?;
+
+        Ok(format!("Extracted {} file(s) of backup to {}.", files, target))
+    }
+}
+
+// the contents of an encrypted backup can only be listed with its password, without one
+// there is no summary
+fn summarize_backup(
+    store: &dyn BackupStore,
+    serial: &str,
+    backup: &StoredBackup,
+    device_id: &str,
+    password: Option<&str>,
+) -> Result<Option<BackupSummary>, Error> {
+    let backup_file = format!("{}.inspect.ab", device_id);
+    store.get(serial, backup.version, Path::new(&backup_file))?;
+
+    let summary = read_backup_summary(&backup_file, password);
+    remove_file(&backup_file)?;
+
+    summary
+}
+
+fn read_backup_summary(backup_file: &str, password: Option<&str>) -> Result<Option<BackupSummary>, Error> {
+    let header = BackupHeader::read(&mut io::BufReader::new(File::open(backup_file)?))?;
+    if header.encryption != Encryption::None && password.is_none() {
+        return Ok(None);
+    }
+
+    let mut reader = BackupReader::open(Path::new(backup_file), password)?;
+
+    Ok(Some(BackupSummary::read(&mut reader)?))
+}
+
+// the given apps, or else all third party and optionally system apps
+fn backed_up_packages(device_id: &str, only_specified: Option<&str>, system_apps: bool) -> Result<Vec<String>, Error> {
+    if let Some(packages) = only_specified {
+        return Ok(packages.split_whitespace().map(String::from).collect());
+    }
+
+    let mut filter = AppFilter::default().with_third_party();
+    if system_apps {
+        filter = filter.with_system();
+    }
+
+    Ok(App::list(Some(device_id), &filter)?.into_iter().map(|app| app.package).collect())
+}
+
+// the apps `adb backup` leaves out of a backup of the given apps, or else of all third party
+// and optionally system apps. A device which can not be asked leaves out none.
+fn excluded_apps(device_id: &str, only_specified: Option<&str>, system_apps: bool) -> Vec<(String, Eligibility)> {
+    let eligibility: Vec<(String, Eligibility)> = match only_specified {
+        Some(packages) => packages
+            .split_whitespace()
+            .filter_map(|package| {
+                App::eligibility(Some(device_id), package)
+                    .ok()
+                    .and_then(|eligibility| eligibility)
+                    .map(|eligibility| (String::from(package), eligibility))
+            })
+            .collect(),
+        None => {
+            let mut filter = AppFilter::default().with_third_party();
+            if system_apps {
+                filter = filter.with_system();
+            }
+
+            App::list(Some(device_id), &filter)
+                .map(|apps| {
+                    apps.into_iter()
+                        .filter_map(|app| match app.eligibility {
+                            Some(eligibility) => Some((app.package, eligibility)),
+                            None => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+    };
+
+    eligibility
+        .into_iter()
+        .filter(|&(_, ref eligibility)| !eligibility.is_eligible())
+        .collect()
+}
+
+fn format_app(app: &App) -> String {
+    let version = match (&app.version_name, app.version_code) {
+        (&Some(ref name), Some(code)) => format!("{} ({})", name, code),
+        (&Some(ref name), None) => name.clone(),
+        (&None, Some(code)) => format!("({})", code),
+        (&None, None) => String::from("unknown"),
+    };
+
+    format!(
+        "{}, version: {}, {}{}, installer: {}, uid: {}, apk: {}",
+        app.package,
+        version,
+        if app.system { "system" } else { "third party" },
+        if app.enabled { "" } else { ", disabled" },
+        app.installer.as_ref().map(String::as_str).unwrap_or("none"),
+        app.uid.map_or(String::from("unknown"), |uid| uid.to_string()),
+        app.apk_path
+    )
+}
+
+fn format_device_info(device_info: &DeviceInfo) -> String {
+    let unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("unknown"));
+
+    let mut lines = vec![
+        format!(
+            "Manufacturer: {}, android: {}, sdk: {}",
+            unknown(&device_info.manufacturer),
+            unknown(&device_info.android_version),
+            unknown(&device_info.sdk_level.map(|sdk_level| sdk_level.to_string()))
+        ),
+        format!("Serial: {}", unknown(&device_info.serial)),
+        format!("Fingerprint: {}", unknown(&device_info.fingerprint)),
+    ];
+
+    lines.extend(device_info.storage.iter().map(|storage| {
+        format!(
+            "Storage: {}, used: {} bytes, available: {} bytes of {} bytes",
+            storage.mount_point, storage.used, storage.available, storage.size
+        )
+    }));
+    lines.push(format!(
+        "Battery: {}",
+        device_info.battery_level.map_or(String::from("unknown"), |level| format!("{}%", level))
+    ));
+
+    lines.join("\r\n")
+}
+
+fn format_stored_backup(backup: &StoredBackup, summary: &Option<BackupSummary>) -> String {
+    let (created, size) = match backup.metadata {
+        Some(ref metadata) => (metadata.created_at.as_str(), metadata.size),
+        None => (backup.date_created.as_str(), backup.size),
+    };
+
+    let mut stored = format!(
+        "Version: {}, created: '{}', size: {} bytes",
+        backup.version, created, size
+    );
+
+    // the ratio is the one of the stored content, for compressed android backups that is
+    // their inflated payload
+    if backup.stored_size > 0 {
+        stored = format!(
+            "{}, stored: {} bytes (ratio {:.2})",
+            stored,
+            backup.stored_size,
+            backup.size as f64 / backup.stored_size as f64
+        );
+    }
+
+    // the options of backups stored without metadata are told from their contents, then
+    // whether system apps were included can not be told apart
+    let options = match (&backup.metadata, summary) {
+        (&Some(ref metadata), _) => Some(metadata.options()),
+        (&None, &Some(ref summary)) => Some(format!(
+            "{} {}",
+            if summary.includes_apks() { "-apk" } else { "-noapk" },
+            if summary.includes_shared_storage() { "-shared" } else { "-noshared" }
+        )),
+        (&None, &None) => None,
+    };
+
+    if let Some(options) = options {
+        stored = format!("{}, options: '{}'", stored, options);
+    }
+
+    match *summary {
+        Some(ref summary) => format!(
+            "{}, packages: {}, files: {}",
+            stored,
+            summary.packages.len(),
+            summary.files()
+        ),
+        None => format!("{}, contents not recorded", stored),
+    }
+}
+
 fn check_too_many_devices(device_id: &Option<&str>) -> Result<(), Error> {
-    if device_id.is_none() && devices::Device::list_devices()?.len() > 1 {
+    let devices = devices::Device::list_devices()?;
+
+    if let Some(device_id) = *device_id {
+        if devices.iter().any(|device| device.id == device_id && device.state.is_usable()) {
+            return Ok(());
+        }
+
+        let error = format!(
+            "Device {} is not connected or can not be used in its state.\n \
+             Please execute adbackup again, with `--device` and one of the following device ids:\n",
+            device_id
+        );
+
+        let error_message = format!("{}\n{}", error, get_printable_device_list()?);
+        info!("{}", error_message);
+        return Err(err_msg(error_message));
+    }
+
+    let usable = devices.iter().filter(|device| device.state.is_usable()).count();
+
+    if usable > 1 {
         let error =
             "More than one device connected and no device provided.\n \
              Please execute adbackup again, with `--device` and one of the following device ids:\n";
@@ -184,5 +1015,13 @@ fn check_too_many_devices(device_id: &Option<&str>) -> Result<(), Error> {
         return Err(err_msg(error_message));
     }
 
+    if usable == 0 && !devices.is_empty() {
+        let error = "No usable device connected, the connected devices can not be backed up in their state:\n";
+
+        let error_message = format!("{}\n{}", error, get_printable_device_list()?);
+        info!("{}", error_message);
+        return Err(err_msg(error_message));
+    }
+
     Ok(())
 }
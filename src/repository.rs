@@ -0,0 +1,154 @@
+use failure::Error;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::path::{Path, PathBuf};
+
+use database::compression::Codec;
+use devices::Device;
+use store::encryption::{EncryptedStore, KeyFile, RepositoryEncryptionError, KEY_FILE};
+use store::{self, BackupStore, StoreKind};
+
+/// Environment variable naming the repository directory if none is set explicitly.
+pub static REPOSITORY_VARIABLE: &str = "ADBACKUP_REPO";
+
+thread_local! {
+    static REPOSITORY: RefCell<Option<PathBuf>> = RefCell::new(None);
+    static STORE_KIND: Cell<StoreKind> = Cell::new(StoreKind::Sqlite);
+    static PASSPHRASE: RefCell<Option<String>> = RefCell::new(None);
+    static COMPRESSION: Cell<Option<Codec>> = Cell::new(None);
+}
+
+#[derive(Debug, Fail)]
+pub enum RepositoryError {
+    #[fail(display = "the repository is already encrypted")]
+    AlreadyEncrypted,
+
+    #[fail(display = "the repository is not encrypted")]
+    NotEncrypted,
+
+    #[fail(display = "the repository already holds backups, only an empty repository can be encrypted")]
+    NotEmpty,
+
+    #[fail(display = "backups are not compressed in an encrypted repository, encrypted data does not compress")]
+    CompressionOfEncrypted,
+}
+
+/// Sets the directory the backups of all devices are kept in on the current thread.
+pub fn set_repository(path: &Path) {
+    REPOSITORY.with(|repository| *repository.borrow_mut() = Some(path.to_path_buf()));
+}
+
+/// Sets how the backups are kept in the repository on the current thread.
+pub fn set_store_kind(kind: StoreKind) {
+    STORE_KIND.with(|store_kind| store_kind.set(kind));
+}
+
+/// Sets the passphrase, or content of the key file, of an encrypted repository on the
+/// current thread.
+pub fn set_passphrase(passphrase: &str) {
+    PASSPHRASE.with(|current| *current.borrow_mut() = Some(String::from(passphrase)));
+}
+
+/// Sets the codec backups put into the repository are compressed with on the current thread,
+/// an encrypted repository does not take one.
+pub fn set_compression(codec: Codec) {
+    COMPRESSION.with(|compression| compression.set(Some(codec)));
+}
+
+/// The set repository directory, else the one in `ADBACKUP_REPO`, else the current
+/// directory.
+pub fn repository_path() -> PathBuf {
+    REPOSITORY
+        .with(|repository| repository.borrow().clone())
+        .or_else(|| env::var_os(REPOSITORY_VARIABLE).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Opens the store of the repository as set on the current thread, backups are encrypted
+/// and decrypted by it if the repository is encrypted. The store only sees encrypted
+/// backups then, so they are neither compressed nor share any data.
+pub fn open_store() -> Result<Box<dyn BackupStore>, Error> {
+    let store = open_plain_store()?;
+
+    match read_key_file(&*store)? {
+        Some(key_file) => {
+            if COMPRESSION.with(Cell::get).is_some() {
+                return Err(Error::from(RepositoryError::CompressionOfEncrypted));
+            }
+
+            let key = key_file.unlock(&passphrase()?)?;
+            Ok(Box::new(EncryptedStore::new(open_plain_store_with(Codec::None)?, key)))
+        }
+        None => Ok(store),
+    }
+}
+
+/// Prepares the repository to keep backups in, with `encrypt` all backups put into it are
+/// encrypted with a new key sealed by the set passphrase.
+pub fn init(encrypt: bool) -> Result<(), Error> {
+    let store = open_plain_store()?;
+
+    if encrypt {
+        if read_key_file(&*store)?.is_some() {
+            return Err(Error::from(RepositoryError::AlreadyEncrypted));
+        }
+        if store.stats()?.backups > 0 {
+            return Err(Error::from(RepositoryError::NotEmpty));
+        }
+
+        let (key_file, _) = KeyFile::generate(&passphrase()?);
+        store.write_file(KEY_FILE, &key_file.to_bytes()?)?;
+    }
+
+    Ok(())
+}
+
+/// Seals the key of the encrypted repository with `new_passphrase` instead of the set
+/// passphrase, the backups stay as they are.
+pub fn change_passphrase(new_passphrase: &str) -> Result<(), Error> {
+    let store = open_plain_store()?;
+
+    let key_file = read_key_file(&*store)?.ok_or(RepositoryError::NotEncrypted)?;
+    let key = key_file.unlock(&passphrase()?)?;
+    store.write_file(KEY_FILE, &KeyFile::seal(&key, new_passphrase).to_bytes()?)?;
+
+    Ok(())
+}
+
+fn open_plain_store() -> Result<Box<dyn BackupStore>, Error> {
+    open_plain_store_with(COMPRESSION.with(Cell::get).unwrap_or_default())
+}
+
+fn open_plain_store_with(codec: Codec) -> Result<Box<dyn BackupStore>, Error> {
+    store::open_store(STORE_KIND.with(Cell::get), &repository_path(), codec)
+}
+
+fn read_key_file(store: &dyn BackupStore) -> Result<Option<KeyFile>, Error> {
+    match store.read_file(KEY_FILE)? {
+        Some(key_file) => Ok(Some(KeyFile::read(&key_file)?)),
+        None => Ok(None),
+    }
+}
+
+fn passphrase() -> Result<String, Error> {
+    PASSPHRASE
+        .with(|passphrase| passphrase.borrow().clone())
+        .ok_or_else(|| Error::from(RepositoryEncryptionError::PassphraseRequired))
+}
+
+/// Opens the store of the repository for the backups of the device adb reaches as
+/// `device_id`, together with the serial the device's backups are kept by. That is the
+/// device's hardware serial, so the backups stay together however the device is connected.
+/// A device which can not be asked is looked up by the serials it was known by before.
+pub fn open(device_id: &str) -> Result<(Box<dyn BackupStore>, String), Error> {
+    let store = open_store()?;
+
+    let serial = match Device::hardware_serial(device_id) {
+        Some(serial) => serial,
+        None => store
+            .find_device(device_id)?
+            .unwrap_or_else(|| String::from(device_id)),
+    };
+
+    Ok((store, serial))
+}
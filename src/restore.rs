@@ -18,14 +18,35 @@ impl Restore {
 
 #[cfg(test)]
 mod tests {
-    use devices::Device;
+    use std::rc::Rc;
+    use hamcrest::prelude::*;
+    use adb_runner::{set_runner, AdbOutput, ScriptedRunner};
     use restore::Restore;
 
-    //#[test]
-    #[allow(dead_code)]
+    #[test]
     fn test_simple_restore() {
-        if Device::list_devices().unwrap().len() > 0 {
-            assert!(Restore::restore("emulator-5554").is_ok())
-        }
+        let runner = ScriptedRunner::new().expect(
+            &["-s", "emulator-5554", "restore", "emulator-5554.ab"],
+            AdbOutput::stdout(""),
+        );
+        set_runner(Rc::new(runner));
+
+        assert!(Restore::restore("emulator-5554").is_ok())
+    }
+
+    #[test]
+    fn test_failed_restore() {
+        let runner = ScriptedRunner::new().expect(
+            &["-s", "emulator-5554", "restore", "emulator-5554.ab"],
+            AdbOutput::error(1, "adb: unable to open file emulator-5554.ab"),
+        );
+        set_runner(Rc::new(runner));
+
+        assert_that!(
+            format!("{}", Restore::restore("emulator-5554").unwrap_err()),
+            is(equal_to(String::from(
+                "Error executing restore.\n adb: unable to open file emulator-5554.ab"
+            )))
+        );
     }
 }
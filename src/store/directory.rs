@@ -0,0 +1,274 @@
+use failure::Error;
+use std::fs::{self, create_dir_all, read_dir, remove_file, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use database::management::{DatabaseError, Integrity, StoredBackup, VerifiedBackup};
+use database::metadata::BackupMetadata;
+use serde_json;
+use sha2::{Digest, Sha256};
+use store::{backup_file_stem, creation_time, device_path_name, read_repository_file, write_repository_file,
+            BackupStore, Sidecar, StoreStats};
+
+/// Keeps each backup as a plain file, `<repository>/<device>/<timestamp>.ab`, with what is
+/// known about it in `<timestamp>.json` next to it, so the repository can be synced or
+/// inspected with any tool.
+pub struct DirectoryStore {
+    repository: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn open(repository: &Path) -> Result<DirectoryStore, Error> {
+        create_dir_all(repository)?;
+
+        Ok(DirectoryStore { repository: repository.to_path_buf() })
+    }
+
+    fn device_directory(&self, device: &str) -> PathBuf {
+        self.repository.join(device_path_name(device))
+    }
+
+    // the sidecars of a device by the stem of their files, sorted by version
+    fn sidecars(&self, device_directory: &Path) -> Result<Vec<(String, Sidecar)>, Error> {
+        if !device_directory.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut sidecars = Vec::new();
+        for entry in read_dir(device_directory)? {
+            let path = entry?.path();
+            if path.extension().map(|extension| extension != "json").unwrap_or(true) {
+                continue;
+            }
+
+            let sidecar: Sidecar = serde_json::from_reader(BufReader::new(File::open(&path)?))?;
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            sidecars.push((stem, sidecar));
+        }
+
+        sidecars.sort_by_key(|&(_, ref sidecar)| sidecar.version);
+
+        Ok(sidecars)
+    }
+
+    fn find_version(&self, device: &str, version: u32) -> Result<(PathBuf, String), Error> {
+        let device_directory = self.device_directory(device);
+
+        self.sidecars(&device_directory)?
+            .into_iter()
+            .find(|&(_, ref sidecar)| sidecar.version == version)
+            .map(|(stem, _)| (device_directory, stem))
+            .ok_or_else(|| Error::from(DatabaseError::BackupVersionNotFound { version }))
+    }
+
+    fn device_directories(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut directories = Vec::new();
+        for entry in read_dir(&self.repository)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                directories.push(path);
+            }
+        }
+
+        Ok(directories)
+    }
+}
+
+impl BackupStore for DirectoryStore {
+    fn put(&self, device: &str, backup_file: &Path, metadata: Option<&BackupMetadata>) -> Result<u32, Error> {
+        let device_directory = self.device_directory(device);
+        create_dir_all(&device_directory)?;
+
+        let sidecars = self.sidecars(&device_directory)?;
+        let version = sidecars.last().map(|&(_, ref sidecar)| sidecar.version).unwrap_or(0) + 1;
+
+        let created = creation_time(metadata);
+        let created_at = created.format("%Y-%m-%d %H:%M:%S").to_string();
+        let stems = sidecars.into_iter().map(|(stem, _)| stem).collect::<Vec<String>>();
+        let stem = backup_file_stem(&created, version, &stems);
+
+        let mut content_hash = Sha256::new();
+        io::copy(&mut File::open(backup_file)?, &mut content_hash)?;
+        fs::copy(backup_file, device_directory.join(format!("{}.ab", stem)))?;
+
+        let sidecar = Sidecar {
+            version,
+            created_at: created_at.clone(),
+            sha256: format!("{:x}", content_hash.finalize()),
+            metadata: metadata.map(|metadata| BackupMetadata {
+                created_at,
+                ..metadata.clone()
+            }),
+            device: Some(String::from(device)),
+        };
+        // written last, a backup without its sidecar is not listed
+        serde_json::to_writer_pretty(File::create(device_directory.join(format!("{}.json", stem)))?, &sidecar)?;
+
+        Ok(version)
+    }
+
+    fn get(&self, device: &str, version: u32, output_file: &Path) -> Result<(), Error> {
+        let (device_directory, stem) = self.find_version(device, version)?;
+        fs::copy(device_directory.join(format!("{}.ab", stem)), output_file)?;
+
+        Ok(())
+    }
+
+    fn list(&self, device: &str) -> Result<Vec<StoredBackup>, Error> {
+        let device_directory = self.device_directory(device);
+
+        self.sidecars(&device_directory)?
+            .into_iter()
+            .map(|(stem, sidecar)| {
+                let size = fs::metadata(device_directory.join(format!("{}.ab", stem)))
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+                Ok(sidecar.to_stored_backup(size))
+            })
+            .collect()
+    }
+
+    fn delete(&self, device: &str, versions: &[u32]) -> Result<(), Error> {
+        let backups = versions
+            .iter()
+            .map(|&version| self.find_version(device, version))
+            .collect::<Result<Vec<(PathBuf, String)>, Error>>()?;
+
+        for (device_directory, stem) in backups {
+            remove_file(device_directory.join(format!("{}.json", stem)))?;
+            remove_file(device_directory.join(format!("{}.ab", stem)))?;
+        }
+
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        let mut stats = StoreStats { devices: 0, backups: 0, size: 0 };
+
+        for device_directory in self.device_directories()? {
+            let sidecars = self.sidecars(&device_directory)?;
+            if sidecars.is_empty() {
+                continue;
+            }
+
+            stats.devices += 1;
+            stats.backups += sidecars.len();
+            for (stem, _) in sidecars {
+                for extension in &["ab", "json"] {
+                    let file = device_directory.join(format!("{}.{}", stem, extension));
+                    stats.size += fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0);
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    fn find_device(&self, device_id: &str) -> Result<Option<String>, Error> {
+        for device_directory in self.device_directories()? {
+            let name = device_directory.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let sidecars = self.sidecars(&device_directory)?;
+
+            let reached_as_device_id = name == device_path_name(device_id) || sidecars.iter().any(|&(_, ref sidecar)| {
+                sidecar.metadata.as_ref().map(|metadata| metadata.device_serial == device_id).unwrap_or(false)
+            });
+            if reached_as_device_id {
+                let device = sidecars.into_iter().rev().filter_map(|(_, sidecar)| sidecar.device).next();
+                return Ok(Some(device.unwrap_or(name)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        read_repository_file(&self.repository.join(name))
+    }
+
+    fn write_file(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        write_repository_file(&self.repository.join(name), content)
+    }
+
+    fn verify(&self, device: &str) -> Result<Vec<VerifiedBackup>, Error> {
+        let device_directory = self.device_directory(device);
+
+        self.sidecars(&device_directory)?
+            .into_iter()
+            .map(|(stem, sidecar)| {
+                let integrity = match File::open(device_directory.join(format!("{}.ab", stem))) {
+                    Ok(mut file) => {
+                        let mut content_hash = Sha256::new();
+                        io::copy(&mut file, &mut content_hash)?;
+
+                        match format!("{:x}", content_hash.finalize()) == sidecar.sha256 {
+                            true => Integrity::Intact,
+                            false => Integrity::Corrupt,
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => Integrity::Missing,
+                    Err(e) => return Err(Error::from(e)),
+                };
+
+                Ok(VerifiedBackup { version: sidecar.version, integrity })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::management::{Integrity, VerifiedBackup};
+    use store::directory::DirectoryStore;
+    use store::BackupStore;
+    use std::fs::{remove_dir_all, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_directory_store() {
+        let repository = Path::new("a14c490f86dfee14e1624bb1bfa3cfa9"); // md5 of 'test_directory_store'
+        let data_file = repository.join("data");
+
+        let store = DirectoryStore::open(repository).unwrap();
+        File::create(&data_file).unwrap().write_all(&[00, 01, 02]).unwrap();
+
+        assert_eq!(store.put("192.168.2.100:5555", &data_file, None).unwrap(), 1);
+        assert_eq!(store.put("192.168.2.100:5555", &data_file, None).unwrap(), 2);
+        assert_eq!(store.put("emulator-5554", &data_file, None).unwrap(), 1);
+
+        let backups = store.list("192.168.2.100:5555").unwrap();
+        assert_eq!(
+            backups.iter().map(|backup| (backup.version, backup.size)).collect::<Vec<(u32, u64)>>(),
+            vec![(1, 3), (2, 3)]
+        );
+        assert!(repository.join("192.168.2.100_5555").is_dir());
+        assert_eq!(store.get_stored_backup("192.168.2.100:5555", 2).unwrap(), backups[1]);
+
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.devices, stats.backups), (2, 3));
+
+        assert!(store.delete("192.168.2.100:5555", &[1]).is_ok());
+        assert_eq!(
+            format!("{}", store.get("192.168.2.100:5555", 1, &data_file).unwrap_err()),
+            "no backup with version 1 found"
+        );
+        assert!(store.get("192.168.2.100:5555", 2, &data_file).is_ok());
+
+        let mut data_result = Vec::new();
+        File::open(&data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![00, 01, 02]);
+
+        assert_eq!(
+            store.verify("emulator-5554").unwrap(),
+            vec![VerifiedBackup { version: 1, integrity: Integrity::Intact }]
+        );
+        // the device as it was given, not the name of its directory
+        assert_eq!(
+            store.find_device("192.168.2.100_5555").unwrap(),
+            Some(String::from("192.168.2.100:5555"))
+        );
+        assert_eq!(store.find_device("emulator-5556").unwrap(), None);
+
+        assert!(remove_dir_all(repository).is_ok());
+    }
+}
@@ -0,0 +1,530 @@
+use chrono::NaiveDateTime;
+use failure::Error;
+use std::env;
+use std::fs::{remove_file, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use android_backup::header::BackupHeader;
+use database::check::Problem;
+use database::management::{StoredBackup, VerifiedBackup};
+use database::metadata::BackupMetadata;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hex;
+use rand::{thread_rng, RngCore};
+use serde_json;
+use store::{BackupStore, StoreStats};
+
+/// Name of the file the key of an encrypted repository is kept in.
+pub static KEY_FILE: &str = "adbackup.key";
+
+/// KiB of memory, passes and lanes the key sealing the repository key is derived from the
+/// passphrase with, the minimum argon2id configuration OWASP recommends.
+pub static ARGON2_MEMORY: u32 = 19 * 1024;
+pub static ARGON2_ITERATIONS: u32 = 2;
+pub static ARGON2_PARALLELISM: u32 = 1;
+
+static KDF: &str = "argon2id";
+static MAGIC: &[u8] = b"ADBACKUP ENCRYPTED 2\n";
+static KEY_DATA: &[u8] = b"adbackup repository key";
+static KEY_SIZE: usize = 32;
+static NONCE_SIZE: usize = 24;
+static TAG_SIZE: usize = 16;
+static SALT_SIZE: usize = 16;
+static FILE_ID_SIZE: usize = 16;
+static RECORD_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum RepositoryEncryptionError {
+    #[fail(display = "the repository is encrypted, its passphrase is required")]
+    PassphraseRequired,
+
+    #[fail(display = "wrong passphrase for the repository")]
+    WrongPassphrase,
+
+    #[fail(display = "encrypted backup in the repository is truncated or corrupt")]
+    CorruptData,
+
+    #[fail(display = "unsupported key file of the repository: {}", reason)]
+    UnsupportedKeyFile {
+        reason: String,
+    },
+}
+
+/// The key the backups of an encrypted repository are encrypted and authenticated with,
+/// using XChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct RepositoryKey {
+    key: Vec<u8>,
+}
+
+/// What is stored in `adbackup.key`: the random key of the repository, sealed with a key
+/// derived from the passphrase. Changing the passphrase only seals the same key again.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub kdf: String,
+    /// KiB of memory argon2id is run with
+    #[serde(default)]
+    pub memory: u32,
+    #[serde(default)]
+    pub iterations: u32,
+    #[serde(default)]
+    pub parallelism: u32,
+    /// hex
+    pub salt: String,
+    /// hex of the nonce, the encrypted key and its tag
+    pub key: String,
+}
+
+impl KeyFile {
+    /// Creates a new random repository key, sealed with `passphrase`.
+    pub fn generate(passphrase: &str) -> (KeyFile, RepositoryKey) {
+        let key = RepositoryKey { key: random_bytes(KEY_SIZE) };
+
+        (KeyFile::seal(&key, passphrase), key)
+    }
+
+    pub fn seal(key: &RepositoryKey, passphrase: &str) -> KeyFile {
+        let mut key_file = KeyFile {
+            kdf: String::from(KDF),
+            memory: ARGON2_MEMORY,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+            salt: hex::encode(random_bytes(SALT_SIZE)),
+            key: String::new(),
+        };
+
+        let passphrase_key = key_file
+            .derive(passphrase)
+            .expect("the recommended argon2id parameters are valid");
+        key_file.key = hex::encode(passphrase_key.seal(KEY_DATA, &key.key));
+
+        key_file
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<RepositoryKey, Error> {
+        if self.kdf != KDF {
+            return Err(unsupported_key_file(&format!("key derivation {}", self.kdf)));
+        }
+        let sealed = hex::decode(&self.key).map_err(|_| unsupported_key_file("key is not hex"))?;
+        if sealed.len() != NONCE_SIZE + KEY_SIZE + TAG_SIZE {
+            return Err(unsupported_key_file("key has the wrong length"));
+        }
+
+        self.derive(passphrase)?
+            .open(KEY_DATA, &sealed)
+            .map(|key| RepositoryKey { key })
+            .map_err(|_| Error::from(RepositoryEncryptionError::WrongPassphrase))
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<KeyFile, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    fn derive(&self, passphrase: &str) -> Result<RepositoryKey, Error> {
+        let salt = hex::decode(&self.salt).map_err(|_| unsupported_key_file("salt is not hex"))?;
+        let params = Params::new(self.memory, self.iterations, self.parallelism, Some(KEY_SIZE))
+            .map_err(|e| unsupported_key_file(&format!("argon2id {}", e)))?;
+
+        let mut key = vec![0u8; KEY_SIZE];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| unsupported_key_file(&format!("argon2id {}", e)))?;
+
+        Ok(RepositoryKey { key })
+    }
+}
+
+fn unsupported_key_file(reason: &str) -> Error {
+    Error::from(RepositoryEncryptionError::UnsupportedKeyFile { reason: String::from(reason) })
+}
+
+impl RepositoryKey {
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new_from_slice(&self.key).expect("repository keys are 32 bytes")
+    }
+
+    /// Encrypts `plaintext` with a random nonce, the nonce is put in front of the
+    /// ciphertext and its tag. `data` is authenticated along with it.
+    fn seal(&self, data: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = random_bytes(NONCE_SIZE);
+        let ciphertext = self
+            .cipher()
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: data })
+            .expect("records are small enough to be encrypted");
+
+        [nonce, ciphertext].concat()
+    }
+
+    fn open(&self, data: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        if sealed.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(Error::from(RepositoryEncryptionError::CorruptData));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+        self.cipher()
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: data })
+            .map_err(|_| Error::from(RepositoryEncryptionError::CorruptData))
+    }
+
+    /// Encrypts `input` to `output` in records of at most 64 KiB, each with a random
+    /// nonce. A record authenticates the random id of the file, its position and whether it
+    /// is the last one, so records can neither be moved, mixed between backups nor cut off.
+    pub fn encrypt_file(&self, input: &Path, output: &Path) -> Result<(), Error> {
+        let mut reader = BufReader::new(File::open(input)?);
+        let mut writer = BufWriter::new(File::create(output)?);
+
+        let file_id = random_bytes(FILE_ID_SIZE);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&file_id)?;
+
+        let mut record = vec![0u8; RECORD_SIZE];
+        for index in 0.. {
+            let length = read_record(&mut reader, &mut record)?;
+            let last = length < RECORD_SIZE;
+
+            writer.write_all(&(length as u32).to_be_bytes())?;
+            writer.write_all(&self.seal(&record_data(&file_id, index, last), &record[..length]))?;
+
+            if last {
+                break;
+            }
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Decrypts what `encrypt_file` wrote, a backup which does not decrypt completely is
+    /// not left in `output`.
+    pub fn decrypt_file(&self, input: &Path, output: &Path) -> Result<(), Error> {
+        let decrypted = self
+            .decrypt_records(BufReader::new(File::open(input)?))
+            .and_then(|records| {
+                let mut writer = BufWriter::new(File::create(output)?);
+                for record in records {
+                    writer.write_all(&record?)?;
+                }
+                writer.flush()?;
+                Ok(())
+            });
+        if decrypted.is_err() && output.exists() {
+            let _ = remove_file(output);
+        }
+
+        decrypted
+    }
+
+    /// The decrypted records of what `encrypt_file` wrote to `reader`, one after another.
+    fn decrypt_records<'a, R: Read>(&'a self, mut reader: R) -> Result<DecryptedRecords<'a, R>, Error> {
+        let mut magic = vec![0u8; MAGIC.len()];
+        let mut file_id = vec![0u8; FILE_ID_SIZE];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut file_id))
+            .map_err(|_| RepositoryEncryptionError::CorruptData)?;
+        if magic != MAGIC {
+            return Err(Error::from(RepositoryEncryptionError::CorruptData));
+        }
+
+        Ok(DecryptedRecords { key: self, reader, file_id, index: 0, finished: false })
+    }
+}
+
+struct DecryptedRecords<'a, R: Read> {
+    key: &'a RepositoryKey,
+    reader: R,
+    file_id: Vec<u8>,
+    index: u64,
+    finished: bool,
+}
+
+impl<'a, R: Read> DecryptedRecords<'a, R> {
+    fn next_record(&mut self) -> Result<Vec<u8>, Error> {
+        let corrupt = |_| Error::from(RepositoryEncryptionError::CorruptData);
+
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut length).map_err(corrupt)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > RECORD_SIZE {
+            return Err(Error::from(RepositoryEncryptionError::CorruptData));
+        }
+
+        let mut sealed = vec![0u8; NONCE_SIZE + length + TAG_SIZE];
+        self.reader.read_exact(&mut sealed).map_err(corrupt)?;
+
+        let last = length < RECORD_SIZE;
+        let record = self.key.open(&record_data(&self.file_id, self.index, last), &sealed)?;
+        self.index += 1;
+
+        if last {
+            if self.reader.read(&mut [0u8; 1])? != 0 {
+                return Err(Error::from(RepositoryEncryptionError::CorruptData));
+            }
+            self.finished = true;
+        }
+
+        Ok(record)
+    }
+}
+
+impl<'a, R: Read> Iterator for DecryptedRecords<'a, R> {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let record = self.next_record();
+        if record.is_err() {
+            self.finished = true;
+        }
+
+        Some(record)
+    }
+}
+
+fn record_data(file_id: &[u8], index: u64, last: bool) -> Vec<u8> {
+    [file_id, &index.to_be_bytes(), &[last as u8]].concat()
+}
+
+// fills `record` as far as `reader` goes
+fn read_record<R: Read>(reader: &mut R, record: &mut [u8]) -> Result<usize, Error> {
+    let mut length = 0;
+    while length < record.len() {
+        match reader.read(&mut record[length..])? {
+            0 => break,
+            read => length += read,
+        }
+    }
+
+    Ok(length)
+}
+
+/// Encrypts the backups put into a store and decrypts the ones taken out of it, what is
+/// recorded about the backups is kept as is. As every encryption takes new nonces, the
+/// store can neither compress nor deduplicate the backups.
+pub struct EncryptedStore {
+    store: Box<dyn BackupStore>,
+    key: RepositoryKey,
+}
+
+impl EncryptedStore {
+    pub fn new(store: Box<dyn BackupStore>, key: RepositoryKey) -> EncryptedStore {
+        EncryptedStore { store, key }
+    }
+
+    // only the first record is decrypted, the header of a backup is well within it, so no
+    // decrypted data is written anywhere
+    fn check_header(&self, device: &str, version: u32) -> Result<(), Error> {
+        let encrypted = env::temp_dir().join(format!("adbackup-{}.encrypted", hex::encode(random_bytes(8))));
+        let header = self.store.get(device, version, &encrypted).and_then(|_| {
+            let first_record = self
+                .key
+                .decrypt_records(BufReader::new(File::open(&encrypted)?))?
+                .next()
+                .unwrap_or_else(|| Err(Error::from(RepositoryEncryptionError::CorruptData)))?;
+            BackupHeader::read(&mut &first_record[..])
+        });
+        if encrypted.exists() {
+            remove_file(&encrypted)?;
+        }
+
+        header.map(|_| ())
+    }
+}
+
+fn encrypted_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.encrypted", path.display()))
+}
+
+impl BackupStore for EncryptedStore {
+    fn put(&self, device: &str, backup_file: &Path, metadata: Option<&BackupMetadata>) -> Result<u32, Error> {
+        let encrypted = encrypted_path(backup_file);
+        self.key.encrypt_file(backup_file, &encrypted)?;
+
+        let version = self.store.put(device, &encrypted, metadata);
+        let _ = remove_file(&encrypted);
+
+        version
+    }
+
+    fn get(&self, device: &str, version: u32, output_file: &Path) -> Result<(), Error> {
+        let encrypted = encrypted_path(output_file);
+        self.store.get(device, version, &encrypted)?;
+
+        let decrypted = self.key.decrypt_file(&encrypted, output_file);
+        remove_file(&encrypted)?;
+
+        decrypted
+    }
+
+    // the store only knows the hashes of the encrypted backups
+    fn list(&self, device: &str) -> Result<Vec<StoredBackup>, Error> {
+        Ok(self
+            .store
+            .list(device)?
+            .into_iter()
+            .map(|backup| StoredBackup { data_hash: None, ..backup })
+            .collect())
+    }
+
+    fn delete(&self, device: &str, versions: &[u32]) -> Result<(), Error> {
+        self.store.delete(device, versions)
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        self.store.stats()
+    }
+
+    fn find_device(&self, device_id: &str) -> Result<Option<String>, Error> {
+        self.store.find_device(device_id)
+    }
+
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.store.read_file(name)
+    }
+
+    fn write_file(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        self.store.write_file(name, content)
+    }
+
+    fn register_device(&self, device: &str, adb_serial: &str, model: Option<&str>) -> Result<(), Error> {
+        self.store.register_device(device, adb_serial, model)
+    }
+
+    fn verify(&self, device: &str) -> Result<Vec<VerifiedBackup>, Error> {
+        self.store.verify(device)
+    }
+
+    // the store only sees the encrypted backups, their headers are checked once decrypted
+    fn check(&self) -> Result<Vec<Problem>, Error> {
+        let mut problems = Vec::new();
+
+        for problem in self.store.check()? {
+            match problem {
+                Problem::InvalidHeader { device, version, .. } => {
+                    if let Err(error) = self.check_header(&device, version) {
+                        problems.push(Problem::InvalidHeader { device, version, error: error.to_string() });
+                    }
+                }
+                problem => problems.push(problem),
+            }
+        }
+
+        Ok(problems)
+    }
+
+    fn repair(&self, problems: &[Problem]) -> Result<(), Error> {
+        self.store.repair(problems)
+    }
+
+    fn get_stored_backup(&self, device: &str, version: u32) -> Result<StoredBackup, Error> {
+        Ok(StoredBackup { data_hash: None, ..self.store.get_stored_backup(device, version)? })
+    }
+
+    fn get_latest_version(&self, device: &str) -> Result<u32, Error> {
+        self.store.get_latest_version(device)
+    }
+
+    fn get_version_before(&self, device: &str, date: &NaiveDateTime) -> Result<u32, Error> {
+        self.store.get_version_before(device, date)
+    }
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use store::encryption::{KeyFile, FILE_ID_SIZE, MAGIC, NONCE_SIZE, RECORD_SIZE, TAG_SIZE};
+    use std::fs::{remove_file, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_key_file() {
+        let (key_file, key) = KeyFile::generate("secret");
+        let key_file = KeyFile::read(&key_file.to_bytes().unwrap()).unwrap();
+
+        assert_that!(
+            format!("{}", key_file.unlock("wrong").err().unwrap()),
+            is(equal_to(String::from("wrong passphrase for the repository")))
+        );
+
+        let unlocked = key_file.unlock("secret").unwrap();
+        assert_eq!(unlocked.key, key.key);
+
+        let resealed = KeyFile::seal(&unlocked, "changed");
+        assert!(resealed.unlock("secret").is_err());
+        assert_eq!(resealed.unlock("changed").unwrap().key, key.key);
+
+        let legacy = KeyFile::read(br#"{"kdf": "pbkdf2-sha256", "rounds": 100000, "salt": "00", "key": "00"}"#).unwrap();
+        assert_that!(
+            format!("{}", legacy.unlock("secret").err().unwrap()),
+            is(equal_to(String::from(
+                "unsupported key file of the repository: key derivation pbkdf2-sha256"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_encrypt_file() {
+        let plain_file = Path::new("15c1e28349e84e1ee1d332364b07cd12"); // md5 of 'test_encrypt_file'
+        let encrypted_file = plain_file.with_extension("encrypted");
+        let decrypted_file = plain_file.with_extension("decrypted");
+        let (_, key) = KeyFile::generate("secret");
+
+        let data = (0..100_000u32).flat_map(|i| i.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+        File::create(plain_file).unwrap().write_all(&data).unwrap();
+        key.encrypt_file(plain_file, &encrypted_file).unwrap();
+
+        let mut encrypted = Vec::new();
+        File::open(&encrypted_file).unwrap().read_to_end(&mut encrypted).unwrap();
+        assert!(encrypted.starts_with(MAGIC));
+        assert!(!encrypted.windows(16).any(|window| window == &data[..16]));
+
+        // every encryption takes new random nonces
+        key.encrypt_file(plain_file, &decrypted_file).unwrap();
+        let mut encrypted_again = Vec::new();
+        File::open(&decrypted_file).unwrap().read_to_end(&mut encrypted_again).unwrap();
+        assert_eq!(encrypted_again.len(), encrypted.len());
+        assert_ne!(encrypted_again, encrypted);
+
+        key.decrypt_file(&encrypted_file, &decrypted_file).unwrap();
+        let mut decrypted = Vec::new();
+        File::open(&decrypted_file).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, data);
+
+        let (_, other_key) = KeyFile::generate("secret");
+        assert_that!(
+            format!("{}", other_key.decrypt_file(&encrypted_file, &decrypted_file).unwrap_err()),
+            is(equal_to(String::from("encrypted backup in the repository is truncated or corrupt")))
+        );
+        assert!(!decrypted_file.exists());
+
+        let truncated = encrypted.len() - 10;
+        File::create(&encrypted_file).unwrap().write_all(&encrypted[..truncated]).unwrap();
+        assert!(key.decrypt_file(&encrypted_file, &decrypted_file).is_err());
+
+        // cut off after a whole record
+        let first_record = MAGIC.len() + FILE_ID_SIZE + 4 + NONCE_SIZE + RECORD_SIZE + TAG_SIZE;
+        File::create(&encrypted_file).unwrap().write_all(&encrypted[..first_record]).unwrap();
+        assert!(key.decrypt_file(&encrypted_file, &decrypted_file).is_err());
+        assert!(!decrypted_file.exists());
+
+        assert!(remove_file(plain_file).is_ok());
+        assert!(remove_file(&encrypted_file).is_ok());
+    }
+}
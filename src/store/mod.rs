@@ -0,0 +1,249 @@
+pub mod directory;
+pub mod encryption;
+pub mod s3;
+pub mod sqlite;
+pub mod tar_archive;
+
+use chrono::{NaiveDateTime, Utc};
+use failure::{err_msg, Error};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use database::check::Problem;
+use database::compression::Codec;
+use database::management::{DatabaseError, StoredBackup, VerifiedBackup};
+use database::metadata::BackupMetadata;
+use store::directory::DirectoryStore;
+use store::s3::{S3Config, S3Store};
+use store::sqlite::SqliteStore;
+use store::tar_archive::TarStore;
+
+/// How much a store holds.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StoreStats {
+    pub devices: usize,
+    pub backups: usize,
+    /// bytes the store takes on disk
+    pub size: u64,
+}
+
+/// Where the backup versions of devices are kept. Devices are given by their hardware
+/// serial, each device has its own versions counting up from 1.
+pub trait BackupStore {
+    /// Stores the backup in `backup_file` as the next version of `device`, together with
+    /// what it was made from if known. Returns the version.
+    fn put(&self, device: &str, backup_file: &Path, metadata: Option<&BackupMetadata>) -> Result<u32, Error>;
+
+    /// Writes `version` of `device` to `output_file`.
+    fn get(&self, device: &str, version: u32, output_file: &Path) -> Result<(), Error>;
+
+    /// The versions of `device`, oldest first.
+    fn list(&self, device: &str) -> Result<Vec<StoredBackup>, Error>;
+
+    /// Removes `versions` of `device` and gives their space back.
+    fn delete(&self, device: &str, versions: &[u32]) -> Result<(), Error>;
+
+    fn stats(&self) -> Result<StoreStats, Error>;
+
+    /// The hardware serial of the device with backups in the store which is known by the
+    /// hardware serial or the adb serial `device_id`.
+    fn find_device(&self, device_id: &str) -> Result<Option<String>, Error>;
+
+    /// The content of the file `name` kept in the repository next to the backups, like the
+    /// key of an encrypted repository.
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    fn write_file(&self, name: &str, content: &[u8]) -> Result<(), Error>;
+
+    /// Records that `device` was reached as `adb_serial`.
+    fn register_device(&self, _device: &str, _adb_serial: &str, _model: Option<&str>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Checks the stored data of `device` against the hashes recorded when storing it.
+    fn verify(&self, _device: &str) -> Result<Vec<VerifiedBackup>, Error> {
+        Err(err_msg("Verifying backups is only supported by the sqlite store"))
+    }
+
+    /// Checks the store for damage, e.g. left by a backup which was interrupted or a full
+    /// disk.
+    fn check(&self) -> Result<Vec<Problem>, Error> {
+        Err(err_msg("Checking the repository is only supported by the sqlite store"))
+    }
+
+    /// Repairs the `problems` found by `check` as far as possible, broken backups are moved
+    /// out of the store into its quarantine.
+    fn repair(&self, _problems: &[Problem]) -> Result<(), Error> {
+        Err(err_msg("Repairing the repository is only supported by the sqlite store"))
+    }
+
+    fn get_stored_backup(&self, device: &str, version: u32) -> Result<StoredBackup, Error> {
+        self.list(device)?
+            .into_iter()
+            .find(|backup| backup.version == version)
+            .ok_or_else(|| Error::from(DatabaseError::BackupVersionNotFound { version }))
+    }
+
+    fn get_latest_version(&self, device: &str) -> Result<u32, Error> {
+        self.list(device)?
+            .last()
+            .map(|backup| backup.version)
+            .ok_or_else(|| err_msg("No backups found."))
+    }
+
+    /// The version of the latest backup created before `date` (UTC). Backups stored
+    /// without a creation date are never considered.
+    fn get_version_before(&self, device: &str, date: &NaiveDateTime) -> Result<u32, Error> {
+        self.list(device)?
+            .into_iter()
+            .filter(|backup| backup.created().map(|created| created < *date).unwrap_or(false))
+            .map(|backup| backup.version)
+            .last()
+            .ok_or_else(|| {
+                Error::from(DatabaseError::NoBackupBefore {
+                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                })
+            })
+    }
+}
+
+/// The kinds of stores a repository can be kept in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StoreKind {
+    /// one sqlite database deduplicating the backups of all devices
+    Sqlite,
+    /// plain `<device>/<timestamp>.ab` files with a JSON sidecar each
+    Directory,
+    /// the files of the directory store in a single tar archive
+    Tar,
+    /// the files of the directory store as objects in an S3 compatible bucket, the
+    /// repository is its url
+    S3,
+}
+
+impl FromStr for StoreKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<StoreKind, Error> {
+        match kind {
+            "sqlite" => Ok(StoreKind::Sqlite),
+            "directory" => Ok(StoreKind::Directory),
+            "tar" => Ok(StoreKind::Tar),
+            "s3" => Ok(StoreKind::S3),
+            _ => Err(err_msg(format!(
+                "Unknown store: {}, expected sqlite, directory, tar or s3",
+                kind
+            ))),
+        }
+    }
+}
+
+/// Opens the store of `kind` kept in the directory `repository`, for S3 `repository` is the
+/// url of the bucket. Only the sqlite store compresses what it stores, with `codec`.
+pub fn open_store(kind: StoreKind, repository: &Path, codec: Codec) -> Result<Box<dyn BackupStore>, Error> {
+    Ok(match kind {
+        StoreKind::Sqlite => Box::new(SqliteStore::open(repository)?.with_codec(codec)),
+        StoreKind::Directory => Box::new(DirectoryStore::open(repository)?),
+        StoreKind::Tar => Box::new(TarStore::open(repository)?),
+        StoreKind::S3 => Box::new(S3Store::open(S3Config::from_env(&repository.to_string_lossy())?)?),
+    })
+}
+
+/// What the directory and tar stores keep next to each backup.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Sidecar {
+    pub version: u32,
+    /// UTC, `YYYY-MM-DD HH:MM:SS`
+    pub created_at: String,
+    pub sha256: String,
+    pub metadata: Option<BackupMetadata>,
+    /// the device as it was given, its directory name only keeps the characters valid in
+    /// paths
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+impl Sidecar {
+    fn to_stored_backup(&self, size: u64) -> StoredBackup {
+        StoredBackup {
+            version: self.version,
+            date_created: self.created_at.clone(),
+            size,
+            stored_size: size,
+            data_hash: Some(self.sha256.clone()),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+// the content of `path`, if it exists
+fn read_repository_file(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match File::open(path) {
+        Ok(mut file) => {
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            Ok(Some(content))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+fn write_repository_file(path: &Path, content: &[u8]) -> Result<(), Error> {
+    File::create(path)?.write_all(content)?;
+
+    Ok(())
+}
+
+// a directory or file name for the serial of a device, which for devices reached over the
+// network may be an address with a port
+fn device_path_name(device: &str) -> String {
+    device
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+// when a backup put with `metadata` was created, that is now unless the metadata tells it
+// was created before, e.g. for a backup imported from another repository
+fn creation_time(metadata: Option<&BackupMetadata>) -> NaiveDateTime {
+    metadata
+        .and_then(|metadata| NaiveDateTime::parse_from_str(&metadata.created_at, "%Y-%m-%d %H:%M:%S").ok())
+        .unwrap_or_else(|| Utc::now().naive_utc())
+}
+
+// `<timestamp>` of the files of a backup created at `created_at`, unique among `taken`
+fn backup_file_stem(created_at: &NaiveDateTime, version: u32, taken: &[String]) -> String {
+    let stem = created_at.format("%Y-%m-%dT%H-%M-%SZ").to_string();
+
+    match taken.contains(&stem) {
+        true => format!("{}_{}", stem, version),
+        false => stem,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use store::{device_path_name, StoreKind};
+
+    #[test]
+    fn test_store_kind() {
+        assert_that!("directory".parse::<StoreKind>().unwrap(), is(equal_to(StoreKind::Directory)));
+        assert_that!(
+            format!("{}", "zip".parse::<StoreKind>().unwrap_err()),
+            is(equal_to(String::from("Unknown store: zip, expected sqlite, directory, tar or s3")))
+        );
+    }
+
+    #[test]
+    fn test_device_path_name() {
+        assert_that!(device_path_name("192.168.2.100:5555"), is(equal_to(String::from("192.168.2.100_5555"))));
+        assert_that!(device_path_name("emulator-5554"), is(equal_to(String::from("emulator-5554"))));
+    }
+}
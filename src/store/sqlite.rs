@@ -0,0 +1,259 @@
+use failure::Error;
+use chrono::NaiveDateTime;
+use std::fs::{create_dir_all, metadata, rename};
+use std::path::{Path, PathBuf};
+
+use database::check::Problem;
+use database::compression::Codec;
+use database::management::{DatabaseError, DatabaseManager, StoredBackup, VerifiedBackup};
+use database::metadata::BackupMetadata;
+use store::{device_path_name, read_repository_file, write_repository_file, BackupStore, StoreStats};
+
+static DATABASE_NAME: &str = "adbackup.db";
+static QUARANTINE_DIRECTORY: &str = "quarantine";
+
+/// Keeps the backups of all devices in one sqlite database, `adbackup.db`, in which their
+/// content is deduplicated in chunks, which are compressed. Broken backups are moved into
+/// `quarantine/<device>-<version>.ab` when repairing the store.
+pub struct SqliteStore {
+    repository: PathBuf,
+    database: String,
+    codec: Codec,
+}
+
+impl SqliteStore {
+    pub fn open(repository: &Path) -> Result<SqliteStore, Error> {
+        create_dir_all(repository)?;
+
+        Ok(SqliteStore {
+            repository: repository.to_path_buf(),
+            database: repository.join(DATABASE_NAME).to_string_lossy().into_owned(),
+            codec: Codec::default(),
+        })
+    }
+
+    /// Compresses the chunks of the backups put from now on with `codec`, the chunks stored
+    /// before keep their codec.
+    pub fn with_codec(self, codec: Codec) -> Self {
+        SqliteStore { codec, ..self }
+    }
+
+    // reading the backups of a device which is not known does not add it
+    fn open_device(&self, device: &str) -> Result<DatabaseManager, Error> {
+        DatabaseManager::open_known_device(&self.database, device)
+    }
+
+    fn open_or_add_device(&self, device: &str) -> Result<DatabaseManager, Error> {
+        Ok(DatabaseManager::open_connection(&self.database, device)?.with_codec(self.codec))
+    }
+
+    fn has_device_databases(&self, serials: &[&str]) -> bool {
+        serials
+            .iter()
+            .any(|serial| self.repository.join(format!("{}.db", serial)).is_file())
+    }
+
+    // databases named after the serial of the device in the repository, as stored before
+    // all devices shared one database, are moved into it and kept as `<serial>.db.imported`
+    fn import_device_databases(&self, db_manager: &DatabaseManager, serials: &[&str]) -> Result<(), Error> {
+        for serial in serials {
+            let device_database = self.repository.join(format!("{}.db", serial));
+            if !device_database.is_file() {
+                continue;
+            }
+
+            let imported = db_manager.import_device_database(&device_database.to_string_lossy())?;
+            rename(&device_database, self.repository.join(format!("{}.db.imported", serial)))?;
+
+            info!(
+                "Imported {} backup(s) from {}",
+                imported,
+                device_database.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl BackupStore for SqliteStore {
+    fn put(&self, device: &str, backup_file: &Path, metadata: Option<&BackupMetadata>) -> Result<u32, Error> {
+        self.open_or_add_device(device)?
+            .insert_data(&backup_file.to_string_lossy(), metadata)
+    }
+
+    fn get(&self, device: &str, version: u32, output_file: &Path) -> Result<(), Error> {
+        self.open_device(device)?
+            .get_backup(version, &output_file.to_string_lossy())
+    }
+
+    fn list(&self, device: &str) -> Result<Vec<StoredBackup>, Error> {
+        match self.open_device(device) {
+            Ok(db_manager) => db_manager.list_backups(),
+            Err(ref e) if is_unknown_device(e) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, device: &str, versions: &[u32]) -> Result<(), Error> {
+        let db_manager = self.open_device(device)?;
+        db_manager.delete_backups(versions)?;
+        db_manager.vacuum()
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        let (devices, backups) = DatabaseManager::count_backups(&self.database)?;
+
+        Ok(StoreStats {
+            devices: devices as usize,
+            backups: backups as usize,
+            size: metadata(&self.database)?.len(),
+        })
+    }
+
+    fn find_device(&self, device_id: &str) -> Result<Option<String>, Error> {
+        DatabaseManager::find_device(&self.database, device_id)
+    }
+
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        read_repository_file(&self.repository.join(name))
+    }
+
+    fn write_file(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        write_repository_file(&self.repository.join(name), content)
+    }
+
+    // the database kept per device before is only taken over once it is sure which device
+    // it belongs to, i.e. when the device is registered. A device is only added with its
+    // backups, either those of that database or its first one put.
+    fn register_device(&self, device: &str, adb_serial: &str, model: Option<&str>) -> Result<(), Error> {
+        let serials = [adb_serial, device];
+        let db_manager = match self.open_device(device) {
+            Ok(db_manager) => db_manager,
+            Err(ref e) if is_unknown_device(e) && self.has_device_databases(&serials) => self.open_or_add_device(device)?,
+            Err(ref e) if is_unknown_device(e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        db_manager.update_device(adb_serial, model)?;
+
+        self.import_device_databases(&db_manager, &serials)
+    }
+
+    fn verify(&self, device: &str) -> Result<Vec<VerifiedBackup>, Error> {
+        self.open_device(device)?.verify()
+    }
+
+    fn check(&self) -> Result<Vec<Problem>, Error> {
+        if !Path::new(&self.database).exists() {
+            return Ok(Vec::new());
+        }
+
+        DatabaseManager::check(&self.database)
+    }
+
+    fn repair(&self, problems: &[Problem]) -> Result<(), Error> {
+        let quarantine = self.repository.join(QUARANTINE_DIRECTORY);
+        for (device, version) in problems.iter().filter_map(Problem::broken_backup) {
+            create_dir_all(&quarantine)?;
+
+            let quarantine_file = quarantine.join(format!("{}-{}.ab", device_path_name(device), version));
+            self.open_device(device)?.quarantine(version, &quarantine_file)?;
+
+            warn!("Moved version {} of device {} to {}", version, device, quarantine_file.display());
+        }
+
+        DatabaseManager::repair(&self.database, problems)
+    }
+
+    fn get_latest_version(&self, device: &str) -> Result<u32, Error> {
+        self.open_device(device)?.get_latest_version()
+    }
+
+    fn get_version_before(&self, device: &str, date: &NaiveDateTime) -> Result<u32, Error> {
+        self.open_device(device)?.get_version_before(date)
+    }
+}
+
+fn is_unknown_device(error: &Error) -> bool {
+    match error.downcast_ref::<DatabaseError>() {
+        Some(&DatabaseError::UnknownDevice { .. }) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest::prelude::*;
+    use std::fs::{create_dir_all, read_dir, remove_dir_all, File};
+    use std::io::Write;
+    use std::path::Path;
+    use store::sqlite::SqliteStore;
+    use store::BackupStore;
+
+    #[test]
+    fn test_repair() {
+        let directory = Path::new("e1977d34889eb1f750733ec48a52f722"); // md5 of 'test_repair'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        let store = SqliteStore::open(&directory.join("repository")).unwrap();
+        let file = directory.join("backup.ab");
+        assert_that!(store.check().unwrap().len(), is(equal_to(0)));
+
+        for content in &[&b"ANDROID BACKUP\n5\n0\nnone\n"[..], &b"garbage\n"[..]] {
+            File::create(&file).unwrap().write_all(content).unwrap();
+            store.put("0123456789ABCDEF", &file, None).unwrap();
+        }
+
+        let problems = store.check().unwrap();
+        assert_that!(problems.len(), is(equal_to(1)));
+        assert_that!(
+            problems[0].to_string(),
+            is(equal_to(String::from(
+                "Version: 2 of device 0123456789ABCDEF, invalid header: not an android backup, header starts with 'garbage'"
+            )))
+        );
+
+        store.repair(&problems).unwrap();
+        assert_that!(store.check().unwrap().len(), is(equal_to(0)));
+        assert_that!(store.list("0123456789ABCDEF").unwrap().len(), is(equal_to(1)));
+        assert_that!(
+            read_dir(directory.join("repository/quarantine"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<String>>(),
+            is(equal_to(vec![String::from("0123456789ABCDEF-2.ab")]))
+        );
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+
+    #[test]
+    fn test_reading_does_not_add_device() {
+        let directory = Path::new("282cce27021d7ddebf20c24a7791c100"); // md5 of 'test_reading_does_not_add_device'
+        let _ = remove_dir_all(directory);
+        create_dir_all(directory).unwrap();
+
+        let store = SqliteStore::open(&directory.join("repository")).unwrap();
+        let file = directory.join("backup.ab");
+
+        assert_that!(store.list("0123456789ABCDEF").unwrap().len(), is(equal_to(0)));
+        assert_that!(
+            store.get("0123456789ABCDEF", 1, &file).unwrap_err().to_string(),
+            is(equal_to(String::from("unknown device: 0123456789ABCDEF")))
+        );
+        assert!(store.verify("0123456789ABCDEF").is_err());
+        store.register_device("0123456789ABCDEF", "emulator-5554", None).unwrap();
+        assert_that!(store.find_device("emulator-5554").unwrap(), is(equal_to(None)));
+
+        File::create(&file).unwrap().write_all(b"ANDROID BACKUP\n5\n0\nnone\n").unwrap();
+        store.put("0123456789ABCDEF", &file, None).unwrap();
+        store.register_device("0123456789ABCDEF", "emulator-5554", None).unwrap();
+        assert_that!(
+            store.find_device("emulator-5554").unwrap(),
+            is(equal_to(Some(String::from("0123456789ABCDEF"))))
+        );
+
+        assert!(remove_dir_all(directory).is_ok());
+    }
+}
@@ -0,0 +1,331 @@
+use failure::Error;
+use std::fs::{self, rename, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use database::management::{DatabaseError, Integrity, StoredBackup, VerifiedBackup};
+use database::metadata::BackupMetadata;
+use serde_json;
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, Header};
+use store::{backup_file_stem, creation_time, device_path_name, read_repository_file, write_repository_file,
+            BackupStore, Sidecar, StoreStats};
+
+static ARCHIVE_NAME: &str = "adbackup.tar";
+
+/// Keeps the files of the directory store, `<device>/<timestamp>.ab` and its JSON sidecar,
+/// in the single tar archive `adbackup.tar`. Backups are appended, deleting them rewrites
+/// the archive. Other files of the repository are kept next to the archive.
+pub struct TarStore {
+    repository: PathBuf,
+    archive: PathBuf,
+}
+
+// a backup in the archive, by the path of its files without the extension
+struct ArchivedBackup {
+    device: String,
+    stem: String,
+    sidecar: Sidecar,
+    size: u64,
+}
+
+impl ArchivedBackup {
+    fn path(&self, extension: &str) -> String {
+        format!("{}/{}.{}", self.device, self.stem, extension)
+    }
+}
+
+impl TarStore {
+    pub fn open(repository: &Path) -> Result<TarStore, Error> {
+        fs::create_dir_all(repository)?;
+
+        Ok(TarStore {
+            repository: repository.to_path_buf(),
+            archive: repository.join(ARCHIVE_NAME),
+        })
+    }
+
+    fn backups(&self) -> Result<Vec<ArchivedBackup>, Error> {
+        if !self.archive.is_file() {
+            return Ok(Vec::new());
+        }
+
+        let mut sidecars = Vec::new();
+        let mut sizes = Vec::new();
+
+        let mut archive = Archive::new(File::open(&self.archive)?);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+
+            if path.ends_with(".json") {
+                let sidecar: Sidecar = serde_json::from_reader(entry)?;
+                sidecars.push((path.trim_end_matches(".json").to_string(), sidecar));
+            } else if path.ends_with(".ab") {
+                sizes.push((path.trim_end_matches(".ab").to_string(), entry.header().size()?));
+            }
+        }
+
+        let mut backups = sidecars
+            .into_iter()
+            .filter_map(|(path, sidecar)| {
+                let size = sizes.iter().find(|&&(ref ab_path, _)| *ab_path == path).map(|&(_, size)| size)?;
+                let mut parts = path.splitn(2, '/');
+                let device = parts.next()?.to_string();
+                let stem = parts.next()?.to_string();
+
+                Some(ArchivedBackup { device, stem, sidecar, size })
+            })
+            .collect::<Vec<ArchivedBackup>>();
+        backups.sort_by_key(|backup| backup.sidecar.version);
+
+        Ok(backups)
+    }
+
+    fn device_backups(&self, device: &str) -> Result<Vec<ArchivedBackup>, Error> {
+        let device = device_path_name(device);
+
+        Ok(self.backups()?.into_iter().filter(|backup| backup.device == device).collect())
+    }
+
+    fn find_version(&self, device: &str, version: u32) -> Result<ArchivedBackup, Error> {
+        self.device_backups(device)?
+            .into_iter()
+            .find(|backup| backup.sidecar.version == version)
+            .ok_or_else(|| Error::from(DatabaseError::BackupVersionNotFound { version }))
+    }
+
+    // appending starts where the end of archive marker of the last append begins
+    fn append_position(&self, file: &mut File) -> Result<u64, Error> {
+        let mut end = 0;
+
+        let mut archive = Archive::new(&*file);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            end = entry.raw_file_position() + (entry.header().entry_size()? + 511) / 512 * 512;
+        }
+
+        Ok(end)
+    }
+
+    // copies the content of the file at `path` in the archive to `writer`
+    fn copy_entry<W: io::Write>(&self, path: &str, writer: &mut W) -> Result<bool, Error> {
+        let mut archive = Archive::new(File::open(&self.archive)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.to_string_lossy() == path {
+                io::copy(&mut entry, writer)?;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl BackupStore for TarStore {
+    fn put(&self, device: &str, backup_file: &Path, metadata: Option<&BackupMetadata>) -> Result<u32, Error> {
+        let backups = self.device_backups(device)?;
+        let version = backups.last().map(|backup| backup.sidecar.version).unwrap_or(0) + 1;
+
+        let created = creation_time(metadata);
+        let created_at = created.format("%Y-%m-%d %H:%M:%S").to_string();
+        let stems = backups.into_iter().map(|backup| backup.stem).collect::<Vec<String>>();
+        let stem = backup_file_stem(&created, version, &stems);
+
+        let mut content_hash = Sha256::new();
+        io::copy(&mut File::open(backup_file)?, &mut content_hash)?;
+
+        let sidecar = Sidecar {
+            version,
+            created_at: created_at.clone(),
+            sha256: format!("{:x}", content_hash.finalize()),
+            metadata: metadata.map(|metadata| BackupMetadata {
+                created_at,
+                ..metadata.clone()
+            }),
+            device: Some(String::from(device)),
+        };
+        let sidecar = serde_json::to_vec_pretty(&sidecar)?;
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&self.archive)?;
+        let position = self.append_position(&mut file)?;
+        file.set_len(position)?;
+        file.seek(SeekFrom::Start(position))?;
+
+        let device = device_path_name(device);
+        let mut builder = Builder::new(file);
+
+        let mut backup = File::open(backup_file)?;
+        let mut header = Header::new_gnu();
+        header.set_metadata(&backup.metadata()?);
+        builder.append_data(&mut header, format!("{}/{}.ab", device, stem), &mut backup)?;
+
+        // appended last, a backup without its sidecar is not listed
+        let mut header = Header::new_gnu();
+        header.set_size(sidecar.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(created.timestamp() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, format!("{}/{}.json", device, stem), &sidecar[..])?;
+        builder.finish()?;
+
+        Ok(version)
+    }
+
+    fn get(&self, device: &str, version: u32, output_file: &Path) -> Result<(), Error> {
+        let backup = self.find_version(device, version)?;
+
+        let mut file = File::create(output_file)?;
+        match self.copy_entry(&backup.path("ab"), &mut file)? {
+            true => Ok(()),
+            false => Err(Error::from(DatabaseError::BackupDataMissing { version })),
+        }
+    }
+
+    fn list(&self, device: &str) -> Result<Vec<StoredBackup>, Error> {
+        Ok(self
+            .device_backups(device)?
+            .iter()
+            .map(|backup| backup.sidecar.to_stored_backup(backup.size))
+            .collect())
+    }
+
+    fn delete(&self, device: &str, versions: &[u32]) -> Result<(), Error> {
+        let removed = versions
+            .iter()
+            .map(|&version| self.find_version(device, version))
+            .collect::<Result<Vec<ArchivedBackup>, Error>>()?
+            .iter()
+            .flat_map(|backup| vec![backup.path("ab"), backup.path("json")])
+            .collect::<Vec<String>>();
+
+        let rewritten = self.archive.with_extension("tar.tmp");
+        {
+            let mut builder = Builder::new(File::create(&rewritten)?);
+
+            let mut archive = Archive::new(File::open(&self.archive)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let path = entry.path()?.to_string_lossy().into_owned();
+                if removed.contains(&path) {
+                    continue;
+                }
+
+                let mut header = entry.header().clone();
+                builder.append_data(&mut header, path, entry)?;
+            }
+
+            builder.finish()?;
+        }
+
+        rename(&rewritten, &self.archive)?;
+
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<StoreStats, Error> {
+        let backups = self.backups()?;
+
+        let mut devices = backups.iter().map(|backup| backup.device.as_str()).collect::<Vec<&str>>();
+        devices.sort();
+        devices.dedup();
+
+        Ok(StoreStats {
+            devices: devices.len(),
+            backups: backups.len(),
+            size: fs::metadata(&self.archive).map(|metadata| metadata.len()).unwrap_or(0),
+        })
+    }
+
+    fn find_device(&self, device_id: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .backups()?
+            .into_iter()
+            .find(|backup| {
+                backup.device == device_path_name(device_id) || backup
+                    .sidecar
+                    .metadata
+                    .as_ref()
+                    .map(|metadata| metadata.device_serial == device_id)
+                    .unwrap_or(false)
+            })
+            .map(|backup| backup.sidecar.device.unwrap_or(backup.device)))
+    }
+
+    fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        read_repository_file(&self.repository.join(name))
+    }
+
+    fn write_file(&self, name: &str, content: &[u8]) -> Result<(), Error> {
+        write_repository_file(&self.repository.join(name), content)
+    }
+
+    fn verify(&self, device: &str) -> Result<Vec<VerifiedBackup>, Error> {
+        self.device_backups(device)?
+            .into_iter()
+            .map(|backup| {
+                let mut content_hash = Sha256::new();
+
+                let integrity = match self.copy_entry(&backup.path("ab"), &mut content_hash)? {
+                    false => Integrity::Missing,
+                    true if format!("{:x}", content_hash.finalize()) == backup.sidecar.sha256 => {
+                        Integrity::Intact
+                    }
+                    true => Integrity::Corrupt,
+                };
+
+                Ok(VerifiedBackup { version: backup.sidecar.version, integrity })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::management::{Integrity, VerifiedBackup};
+    use store::tar_archive::TarStore;
+    use store::BackupStore;
+    use std::fs::{remove_dir_all, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_tar_store() {
+        let repository = Path::new("97d32e01f28831df09a2801d2c25b6b9"); // md5 of 'test_tar_store'
+        let data_file = repository.join("data");
+
+        let store = TarStore::open(repository).unwrap();
+        File::create(&data_file).unwrap().write_all(&[00, 01, 02]).unwrap();
+
+        assert_eq!(store.put("0123456789ABCDEF", &data_file, None).unwrap(), 1);
+        assert_eq!(store.put("emulator-5554", &data_file, None).unwrap(), 1);
+        File::create(&data_file).unwrap().write_all(&[03, 04]).unwrap();
+        assert_eq!(store.put("0123456789ABCDEF", &data_file, None).unwrap(), 2);
+
+        let backups = store.list("0123456789ABCDEF").unwrap();
+        assert_eq!(
+            backups.iter().map(|backup| (backup.version, backup.size)).collect::<Vec<(u32, u64)>>(),
+            vec![(1, 3), (2, 2)]
+        );
+
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.devices, stats.backups), (2, 3));
+
+        assert!(store.delete("0123456789ABCDEF", &[1]).is_ok());
+        assert_eq!(store.list("0123456789ABCDEF").unwrap().len(), 1);
+        assert_eq!(store.list("emulator-5554").unwrap().len(), 1);
+
+        assert!(store.get("0123456789ABCDEF", 2, &data_file).is_ok());
+        let mut data_result = Vec::new();
+        File::open(&data_file).unwrap().read_to_end(&mut data_result).unwrap();
+        assert_eq!(data_result, vec![03, 04]);
+
+        assert_eq!(
+            store.verify("emulator-5554").unwrap(),
+            vec![VerifiedBackup { version: 1, integrity: Integrity::Intact }]
+        );
+
+        assert!(remove_dir_all(repository).is_ok());
+    }
+}
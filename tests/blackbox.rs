@@ -5,11 +5,15 @@ fn test_help() {
     let empty_string = "";
     let mocked_message = "adbackup 0.5.2\nJulian Raufelder <julian.raufelder@htwg-konstanz.de>\
         :Jonas Reinwald <jonas.reinwald@htwg-konstanz.de>\nA backup tool for android using adb\n\n\
-        USAGE:\n adbackup-cli [FLAGS] [SUBCOMMAND]\n\n\
+        USAGE:\n adbackup-cli [FLAGS] [OPTIONS] [SUBCOMMAND]\n\n\
         FLAGS:\n\
          -h, --help Prints help information\n\
          -V, --version Prints version information\n\
          -v  Increases logging verbosity each use for up to 3 times\n\n\
+        OPTIONS:\n\
+         --passphrase-file <FILE> Passphrase or key file of an encrypted repository\n\
+         --repo <DIR> Repository of the backups, defaults to $ADBACKUP_REPO or the current directory\n\
+         --store <KIND> How the repository keeps the backups: sqlite (default), directory, tar or s3\n\n\
         SUBCOMMANDS:\n\
          backup Start backup of device\n\
          restore Restore android backup\n\
@@ -17,6 +21,16 @@ fn test_help() {
          pull Pull file/folder from android into current folder of your pc\n\
          push Push file/folder from the pc to a connected android device\n\
          apps List all installed apps on devices\n\
+         extract Extract a stored backup into a folder or tar file\n\
+         backups List and inspect stored backups\n\
+         verify Check stored backups for corruption\n\
+         prune Remove old backups according to a retention policy\n\
+         init Create the repository, optionally encrypted with the passphrase\n\
+         change-passphrase Change the passphrase of an encrypted repository\n\
+         export Export backups of a device into a bundle file\n\
+         import Import the backups of a bundle file into the repository\n\
+         check Check the repository for damage, e.g. by interrupted backups\n\
+         info Show android version, storage and battery of a device\n\
          help Prints this message or the help of the given subcommand(s)\n";
 
     let output = Command::new("target/debug/adbackup-cli")
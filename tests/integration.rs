@@ -0,0 +1,974 @@
+extern crate adbackup;
+extern crate failure;
+
+use adbackup::{AdbOutput, AdbRunner, AppFilter, Codec, RetentionPolicy, ScriptedRunner, StoreKind};
+use failure::Error;
+use std::cell::RefCell;
+use std::fs::{copy, create_dir_all, metadata, read_dir, remove_dir_all, remove_file, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+fn devices_output(device_ids: &[&str]) -> AdbOutput {
+    let devices = device_ids.iter().fold(
+        String::from("List of devices attached\n"),
+        |devices, device_id| {
+            format!(
+                "{}{}          device product:sdk_google_phone_x86 \
+                 model:Android_SDK_built_for_x86 device:generic_x86 transport_id:9\n",
+                devices, device_id
+            )
+        },
+    );
+
+    AdbOutput::stdout(&format!("{}\n", devices))
+}
+
+// answers like `runner` and keeps the backup adb is asked to restore, as the backup file is
+// removed once it is restored
+#[derive(Clone)]
+struct RestoreRecorder {
+    runner: ScriptedRunner,
+    restored: Rc<RefCell<Vec<u8>>>,
+}
+
+impl RestoreRecorder {
+    fn new(runner: ScriptedRunner) -> Self {
+        RestoreRecorder { runner, restored: Rc::new(RefCell::new(Vec::new())) }
+    }
+
+    fn restored(&self) -> Vec<u8> {
+        self.restored.borrow().clone()
+    }
+}
+
+impl AdbRunner for RestoreRecorder {
+    fn run(&self, args: &[&str]) -> Result<AdbOutput, Error> {
+        if args.len() == 4 && args[2] == "restore" {
+            let mut restored = self.restored.borrow_mut();
+            restored.clear();
+            File::open(args[3])?.read_to_end(&mut restored)?;
+        }
+
+        self.runner.run(args)
+    }
+}
+
+#[test]
+fn test_device_list() {
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&["emulator-5554"]));
+    adbackup::set_adb_runner(runner);
+
+    let device_list = adbackup::get_printable_device_list().unwrap();
+
+    assert!(device_list.starts_with("Found the following devices:"));
+    assert!(device_list.contains("Id: 'emulator-5554'"));
+    assert_eq!(adbackup::get_device_id().unwrap(), "emulator-5554");
+}
+
+#[test]
+fn test_too_many_devices() {
+    let runner = ScriptedRunner::new().expect(
+        &["devices", "-l"],
+        devices_output(&["emulator-5554", "192.168.2.100:5555"]),
+    );
+    adbackup::set_adb_runner(runner);
+
+    let error = adbackup::get_printable_app_list(None, &AppFilter::default()).unwrap_err();
+
+    assert!(format!("{}", error).starts_with("More than one device connected"));
+}
+
+#[test]
+fn test_unauthorized_device() {
+    let runner = ScriptedRunner::new().expect(
+        &["devices", "-l"],
+        AdbOutput::stdout(
+            "List of devices attached\n\
+             0123456789ABCDEF       unauthorized usb:1-1 transport_id:3\n\n",
+        ),
+    );
+    adbackup::set_adb_runner(runner);
+
+    let device_list = adbackup::get_printable_device_list().unwrap();
+    assert!(device_list.contains(
+        "Id: '0123456789ABCDEF', state: unauthorized, details: 'usb:1-1 transport_id:3' \
+         (authorize the RSA prompt on the phone)"
+    ));
+
+    let error = adbackup::get_device_id().unwrap_err();
+    assert!(format!("{}", error).starts_with("No usable device connected"));
+    assert!(format!("{}", error).contains("authorize the RSA prompt on the phone"));
+
+    // also when the device is given, or one is given which is not connected
+    let error = adbackup::backup("0123456789ABCDEF", None, None, None, None, None).unwrap_err();
+    assert!(format!("{}", error).starts_with("Device 0123456789ABCDEF is not connected or can not be used"));
+    assert!(format!("{}", error).contains("authorize the RSA prompt on the phone"));
+
+    let error = adbackup::restore("emulator-5554", None, None, None).unwrap_err();
+    assert!(format!("{}", error).starts_with("Device emulator-5554 is not connected or can not be used"));
+}
+
+#[test]
+fn test_device_info() {
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&["emulator-5554"]))
+        .expect(
+            &["-s", "emulator-5554", "shell", "getprop"],
+            AdbOutput::stdout(
+                "[ro.build.fingerprint]: [google/sdk_gphone_x86/generic_x86:8.1.0/OSM1.180201.007/4586646:user/release-keys]\n\
+                 [ro.build.version.release]: [8.1.0]\n\
+                 [ro.build.version.sdk]: [27]\n\
+                 [ro.product.manufacturer]: [Google]\n\
+                 [ro.serialno]: [EMULATOR28X0X23X0]\n",
+            ),
+        )
+        .expect(
+            &["-s", "emulator-5554", "shell", "df", "/data", "/sdcard"],
+            AdbOutput::stdout(
+                "Filesystem            1K-blocks    Used Available Use% Mounted on\n\
+                 /dev/block/dm-0            4096    1024      3072  25% /data\n",
+            ),
+        );
+    adbackup::set_adb_runner(runner);
+
+    let device_info = adbackup::get_printable_device_info(None).unwrap();
+
+    assert_eq!(
+        device_info,
+        "Device: 'emulator-5554'\r\n\
+         Manufacturer: Google, android: 8.1.0, sdk: 27\r\n\
+         Serial: EMULATOR28X0X23X0\r\n\
+         Fingerprint: google/sdk_gphone_x86/generic_x86:8.1.0/OSM1.180201.007/4586646:user/release-keys\r\n\
+         Storage: /data, used: 1048576 bytes, available: 3145728 bytes of 4194304 bytes\r\n\
+         Battery: unknown"
+    );
+}
+
+#[test]
+fn test_app_list() {
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&["emulator-5554"]))
+        .expect(
+            &["shell", "pm", "list", "packages", "-f", "-i", "-U"],
+            AdbOutput::stdout(
+                "package:/data/app/org.cryptomator-1/base.apk=org.cryptomator  \
+                 installer=com.android.vending uid:10085\n\
+                 package:/system/app/SmokeTest/SmokeTest.apk=com.android.smoketest  installer=null uid:10031\n\
+                 package:/system/app/Bloat/Bloat.apk=com.vendor.bloat  installer=null uid:10070\n",
+            ),
+        )
+        .expect(
+            &["shell", "pm", "list", "packages", "-3"],
+            AdbOutput::stdout("package:org.cryptomator\n"),
+        )
+        .expect(
+            &["shell", "pm", "list", "packages", "-d"],
+            AdbOutput::stdout("package:com.vendor.bloat\n"),
+        )
+        .expect(
+            &["shell", "dumpsys", "package", "packages"],
+            AdbOutput::stdout(
+                "Packages:\n  Package [org.cryptomator] (5d7f0e3):\n    \
+                 versionCode=1500 minSdk=21 targetSdk=28\n    versionName=1.5.0\n",
+            ),
+        );
+    adbackup::set_adb_runner(runner);
+
+    let app_list = adbackup::get_printable_app_list(None, &AppFilter::default()).unwrap();
+    assert_eq!(
+        app_list,
+        "Found the following app(s) on device:\r\n\
+         com.android.smoketest, version: unknown, system, installer: none, uid: 10031, \
+         apk: /system/app/SmokeTest/SmokeTest.apk\n\
+         com.vendor.bloat, version: unknown, system, disabled, installer: none, uid: 10070, \
+         apk: /system/app/Bloat/Bloat.apk\n\
+         org.cryptomator, version: 1.5.0 (1500), third party, installer: com.android.vending, uid: 10085, \
+         apk: /data/app/org.cryptomator-1/base.apk\n"
+    );
+
+    let app_list = adbackup::get_printable_app_list(None, &AppFilter::default().with_system().with_disabled()).unwrap();
+    assert_eq!(
+        app_list,
+        "Found the following app(s) on device:\r\n\
+         com.vendor.bloat, version: unknown, system, disabled, installer: none, uid: 10070, \
+         apk: /system/app/Bloat/Bloat.apk\n"
+    );
+
+    let app_list = adbackup::get_printable_app_list(None, &AppFilter::default().with_grep("dropbox")).unwrap();
+    assert_eq!(app_list, "No packages found.");
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let device_id = "490a37a80b3eafd103f78072b4766ae2"; // md5 of 'test_backup_and_restore'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        )
+        .expect(
+            &["-s", device_id, "restore", &backup_file],
+            AdbOutput::stdout("Now unlock your device and confirm the restore operation."),
+        );
+    let recorder = RestoreRecorder::new(runner.clone());
+    adbackup::set_adb_runner(recorder.clone());
+
+    // the scripted runner does not write a backup, so provide the file adb would have written
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+
+    // the backup is only kept in the repository
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    assert!(!Path::new(&backup_file).exists());
+
+    assert!(adbackup::restore(device_id, None, None, None).is_ok());
+    assert!(!Path::new(&backup_file).exists());
+
+    assert_eq!(&recorder.restored()[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+    // devices to check the device is usable before backup and restore, devices and getprop
+    // for the backup's metadata, pm for the apps left out of it, the hardware serial each
+    // time the repository is opened, backup and restore
+    assert_eq!(runner.calls().len(), 9);
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_backup_into_directory_store() {
+    let device_id = "177ad646db0702f836cd71015df7cc7d"; // md5 of 'test_backup_into_directory_store'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+    adbackup::set_store(StoreKind::Directory);
+
+    let backup_args = [
+        "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all", "-f",
+        &backup_file,
+    ];
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(&backup_args, AdbOutput::stdout("Now unlock your device and confirm the backup operation..."))
+        .expect(
+            &["-s", device_id, "restore", &backup_file],
+            AdbOutput::stdout("Now unlock your device and confirm the restore operation."),
+        );
+    let recorder = RestoreRecorder::new(runner);
+    adbackup::set_adb_runner(recorder.clone());
+
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n1\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    let device_directory = Path::new(&repository).join(device_id);
+    assert_eq!(
+        read_dir(&device_directory)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().unwrap() == "json")
+            .count(),
+        2
+    );
+    assert!(adbackup::get_printable_store_stats()
+        .unwrap()
+        .starts_with("Stored 2 backup(s) of 1 device(s), "));
+
+    assert!(adbackup::restore(device_id, Some(1), None, None).is_ok());
+    assert_eq!(&recorder.restored()[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_export_and_import_bundle() {
+    let device_id = "261a121b43cac9512ba1da9a16040100"; // md5 of 'test_export_and_import_bundle'
+    let backup_file = format!("{}.ab", device_id);
+    let bundle = format!("{}.adbk", device_id);
+    let source = format!("{}.repo", device_id);
+    let target = format!("{}.target.repo", device_id);
+    adbackup::set_repository(Path::new(&source));
+
+    let backup_args = [
+        "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all", "-f",
+        &backup_file,
+    ];
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(&backup_args, AdbOutput::stdout("Now unlock your device and confirm the backup operation..."))
+        .expect(&backup_args, AdbOutput::stdout("Now unlock your device and confirm the backup operation..."));
+    adbackup::set_adb_runner(runner);
+
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\nsecond")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    assert_eq!(
+        adbackup::export(device_id, &[2], &bundle).unwrap(),
+        format!("Exported 1 backup(s) of device {} to {}.", device_id, bundle)
+    );
+
+    adbackup::set_repository(Path::new(&target));
+    assert_eq!(
+        adbackup::import(&bundle).unwrap(),
+        format!(
+            "Imported the backups of 1 device(s) from {}:\r\nDevice: {}, imported: 1, already in the repository: 0",
+            bundle, device_id
+        )
+    );
+    assert!(adbackup::import(&bundle).unwrap().ends_with("imported: 0, already in the repository: 1"));
+
+    assert!(adbackup::get_printable_store_stats()
+        .unwrap()
+        .starts_with("Stored 1 backup(s) of 1 device(s), "));
+
+    assert!(remove_file(&bundle).is_ok());
+    assert!(remove_dir_all(&source).is_ok());
+    assert!(remove_dir_all(&target).is_ok());
+}
+
+#[test]
+fn test_check_repository() {
+    let device_id = "e67e70300444799a2d75e0473283f8c5"; // md5 of 'test_check_repository'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all", "-f",
+                &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    assert_eq!(adbackup::check(false).unwrap(), "Checked the repository, no problems found.");
+    assert_eq!(adbackup::check(true).unwrap(), "Checked the repository, no problems found.");
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_check_encrypted_repository() {
+    let device_id = "102dfcf46f2c203da4414e736296888f"; // md5 of 'test_check_encrypted_repository'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+    adbackup::set_passphrase("secret");
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all", "-f",
+                &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    assert!(adbackup::init(true).is_ok());
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    // the headers are checked without writing any decrypted backup
+    assert_eq!(adbackup::check(false).unwrap(), "Checked the repository, no problems found.");
+    assert!(!Path::new(&format!("{}-1.ab", device_id)).exists());
+    assert!(!Path::new(&backup_file).exists());
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_encrypted_repository() {
+    let device_id = "139e5c78a1185387fc252087d3c79dc6"; // md5 of 'test_encrypted_repository'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+    adbackup::set_store(StoreKind::Directory);
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        )
+        .expect(
+            &["-s", device_id, "restore", &backup_file],
+            AdbOutput::stdout("Now unlock your device and confirm the restore operation."),
+        );
+    let recorder = RestoreRecorder::new(runner);
+    adbackup::set_adb_runner(recorder.clone());
+
+    assert_eq!(
+        format!("{}", adbackup::init(true).unwrap_err()),
+        "the repository is encrypted, its passphrase is required"
+    );
+    adbackup::set_passphrase("secret");
+    assert!(adbackup::init(true).unwrap().starts_with("Initialized encrypted repository at "));
+    assert_eq!(
+        format!("{}", adbackup::init(true).unwrap_err()),
+        "the repository is already encrypted"
+    );
+
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    let stored = read_dir(Path::new(&repository).join(device_id))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().unwrap() == "ab")
+        .unwrap();
+    let mut stored_data = Vec::new();
+    File::open(stored).unwrap().read_to_end(&mut stored_data).unwrap();
+    assert!(stored_data.starts_with(b"ADBACKUP ENCRYPTED 2\n"));
+    assert!(!stored_data.windows(14).any(|window| window == b"ANDROID BACKUP"));
+
+    adbackup::set_passphrase("wrong");
+    assert_eq!(
+        format!("{}", adbackup::restore(device_id, None, None, None).unwrap_err()),
+        "wrong passphrase for the repository"
+    );
+
+    adbackup::set_passphrase("secret");
+    assert!(adbackup::change_passphrase("changed").is_ok());
+    assert!(adbackup::restore(device_id, None, None, None).is_err());
+
+    adbackup::set_passphrase("changed");
+    assert!(adbackup::restore(device_id, None, None, None).is_ok());
+    assert_eq!(&recorder.restored()[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+    assert!(!Path::new(&backup_file).exists());
+
+    // a backup which can not be stored is not left behind either
+    File::create(&backup_file)
+        .unwrap()
+        .write_all(b"ANDROID BACKUP\n5\n0\nnone\n")
+        .unwrap();
+    adbackup::set_compression(Codec::Gzip);
+    assert_eq!(
+        format!("{}", adbackup::backup(device_id, None, None, None, None, None).unwrap_err()),
+        "backups are not compressed in an encrypted repository, encrypted data does not compress"
+    );
+    assert!(!Path::new(&backup_file).exists());
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_restore_historical_version() {
+    let device_id = "7fbb83ba4423fc7ab95071d438d874f2"; // md5 of 'test_restore_historical_version'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        )
+        .expect(
+            &["-s", device_id, "restore", &backup_file],
+            AdbOutput::stdout("Now unlock your device and confirm the restore operation."),
+        );
+    let recorder = RestoreRecorder::new(runner.clone());
+    adbackup::set_adb_runner(recorder.clone());
+
+    let backups: Vec<&[u8]> = vec![b"ANDROID BACKUP\n5\n0\nnone\n", b"ANDROID BACKUP\n5\n1\nnone\n"];
+    backups.into_iter().for_each(|backup| {
+        File::create(&backup_file).unwrap().write_all(backup).unwrap();
+        assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    });
+
+    assert!(adbackup::restore(device_id, Some(1), None, None).is_ok());
+    assert_eq!(&recorder.restored()[..], &b"ANDROID BACKUP\n5\n0\nnone\n"[..]);
+
+    assert!(adbackup::restore(device_id, None, Some("2999-01-01"), None).is_ok());
+    assert_eq!(&recorder.restored()[..], &b"ANDROID BACKUP\n5\n1\nnone\n"[..]);
+
+    assert_eq!(
+        format!("{}", adbackup::restore(device_id, None, Some("2000-01-01"), None).unwrap_err()),
+        "no backup created before 2000-01-01 00:00:00 found"
+    );
+    assert_eq!(
+        format!("{}", adbackup::restore(device_id, None, Some("yesterday"), None).unwrap_err()),
+        "Invalid date: yesterday, expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS"
+    );
+    assert_eq!(
+        format!("{}", adbackup::restore(device_id, Some(3), None, None).unwrap_err()),
+        "no backup with version 3 found"
+    );
+    assert_eq!(runner.calls().len(), 24);
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_backup_of_apps_not_allowing_it() {
+    let device_id = "398ccfc5d541374952ee8f1a394ebcc8"; // md5 of 'test_backup_of_apps_not_allowing_it'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let dump = |package: &str, flags: &str| {
+        AdbOutput::stdout(&format!("Packages:\n  Package [{}] (5d7f0e3):\n    flags=[ {} ]\n", package, flags))
+    };
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &["-s", device_id, "shell", "dumpsys", "package", "org.cryptomator"],
+            dump("org.cryptomator", "HAS_CODE ALLOW_BACKUP"),
+        )
+        .expect(
+            &["-s", device_id, "shell", "dumpsys", "package", "com.example.banking"],
+            dump("com.example.banking", "HAS_CODE"),
+        )
+        .expect(
+            &["shell", "pm", "list", "packages", "-f", "-i", "-U"],
+            AdbOutput::stdout(
+                "package:/data/app/org.cryptomator-1/base.apk=org.cryptomator  installer=null uid:10085\n\
+                 package:/data/app/com.example.banking-1/base.apk=com.example.banking  installer=null uid:10086\n",
+            ),
+        )
+        .expect(
+            &["shell", "pm", "list", "packages", "-3"],
+            AdbOutput::stdout("package:org.cryptomator\npackage:com.example.banking\n"),
+        )
+        .expect(&["shell", "pm", "list", "packages", "-d"], AdbOutput::stdout(""))
+        .expect(
+            &["shell", "dumpsys", "package", "packages"],
+            AdbOutput::stdout(
+                "Packages:\n  Package [org.cryptomator] (5d7f0e3):\n    flags=[ HAS_CODE ALLOW_BACKUP ]\n  \
+                 Package [com.example.banking] (8e1c2a0):\n    flags=[ HAS_CODE ]\n",
+            ),
+        )
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk",
+                "org.cryptomator com.example.banking", "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    assert_eq!(
+        adbackup::get_printable_backup_eligibility(None, &AppFilter::default()).unwrap(),
+        "Backup eligibility of the app(s) on device:\r\n\
+         com.example.banking: excluded, the app does not allow backups\n\
+         org.cryptomator: eligible\n"
+    );
+
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(
+        device_id,
+        None,
+        None,
+        None,
+        Some("org.cryptomator com.example.banking"),
+        Some("adbackup")
+    ).is_ok());
+
+    let backup = adbackup::get_printable_backup(device_id, 1, Some("adbackup")).unwrap();
+    assert!(backup.contains("\r\nNot backed up as they do not allow it: com.example.banking\r\n"));
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_harvest_and_install_apks() {
+    let device_id = "11bb69701766bba5454c7af269a626a5"; // md5 of 'test_harvest_and_install_apks'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    let apk = format!("{}.apks/apps/org.cryptomator/a/base.apk", backup_file);
+    adbackup::set_repository(Path::new(&repository));
+    adbackup::set_apk_harvest(true);
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &["-s", device_id, "shell", "dumpsys", "package", "org.cryptomator"],
+            AdbOutput::stdout(
+                "Packages:\n  Package [org.cryptomator] (5d7f0e3):\n    flags=[ HAS_CODE ALLOW_BACKUP ]\n    \
+                 signatures=PackageSignatures{9fe2bd4 version:2, signatures:[5a5d2c36], past signatures:[]}\n",
+            ),
+        )
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "org.cryptomator",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        )
+        .expect(
+            &["-s", device_id, "shell", "pm", "path", "org.cryptomator"],
+            AdbOutput::stdout("package:/data/app/org.cryptomator-1/base.apk\n"),
+        )
+        .expect(
+            &["-s", device_id, "pull", "/data/app/org.cryptomator-1/base.apk", &apk, "-a"],
+            AdbOutput::stdout("/data/app/org.cryptomator-1/base.apk: 1 file pulled."),
+        )
+        .expect(&["-s", device_id, "install-multiple", "-r", &apk], AdbOutput::stdout("Success"))
+        .expect(&["-s", device_id, "restore", &backup_file], AdbOutput::stdout(""));
+    adbackup::set_adb_runner(runner.clone());
+
+    // the scripted pull leaves the apk where adb would put it
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    create_dir_all(Path::new(&apk).parent().unwrap()).unwrap();
+    File::create(&apk).unwrap().write_all(b"not really an apk").unwrap();
+
+    assert!(adbackup::backup(device_id, None, None, None, Some("org.cryptomator"), Some("adbackup")).is_ok());
+    assert!(!Path::new(&apk).exists());
+
+    let backup = adbackup::get_printable_backup(device_id, 1, Some("adbackup")).unwrap();
+    assert!(backup.contains("\r\nApks: org.cryptomator (1 apk(s))"));
+
+    // no app is installed for a backup which can not be read
+    assert_eq!(
+        format!("{}", adbackup::restore(device_id, None, None, None).unwrap_err()),
+        "backup is encrypted, the password entered on the device is required"
+    );
+    assert!(!runner.calls().iter().any(|call| call.contains(&"install-multiple".to_string())));
+
+    assert!(adbackup::restore(device_id, None, None, Some("adbackup")).is_ok());
+    let calls = runner.calls();
+    let install = calls.iter().position(|call| call.contains(&"install-multiple".to_string()));
+    let restore = calls.iter().position(|call| call.contains(&"restore".to_string()));
+    assert!(install.is_some() && install < restore);
+    assert!(!Path::new(&backup_file).exists());
+
+    // the apks are not even looked up for an encrypted backup made without its password
+    let apk_lookups = || runner.calls().iter().filter(|call| call.contains(&"path".to_string())).count();
+    let looked_up = apk_lookups();
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, Some("org.cryptomator"), None).is_ok());
+    assert_eq!(apk_lookups(), looked_up);
+
+    let backup = adbackup::get_printable_backup(device_id, 2, Some("adbackup")).unwrap();
+    assert!(!backup.contains("\r\nApks: "));
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_push_and_pull() {
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&["emulator-5554"]))
+        .expect(
+            &["push", "Cargo.toml", "/sdcard/"],
+            AdbOutput::stdout("Cargo.toml: 1 file pushed."),
+        )
+        .expect(
+            &["pull", "/sdcard/Cargo.toml", "-a"],
+            AdbOutput::error(1, "adb: error: remote object '/sdcard/Cargo.toml' does not exist"),
+        );
+    adbackup::set_adb_runner(runner);
+
+    assert!(adbackup::push(None, "Cargo.toml", "/sdcard/").is_ok());
+    assert!(adbackup::pull(None, "/sdcard/Cargo.toml").is_err());
+}
+
+#[test]
+fn test_restore_with_wrong_password() {
+    let device_id = "9b0bf43b276f4d7c63df1f35dd6e01e1"; // md5 of 'test_restore_with_wrong_password'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner.clone());
+
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, Some("adbackup")).is_ok());
+
+    let error = adbackup::restore(device_id, None, None, Some("wrong")).unwrap_err();
+
+    assert_eq!(format!("{}", error), "wrong password for encrypted backup");
+    assert_eq!(runner.calls().len(), 8);
+    assert!(!Path::new(&backup_file).exists());
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_extract_backup() {
+    let device_id = "0ae1b071e5bf321916bd56f045e68449"; // md5 of 'test_extract_backup'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+    let target_dir = format!("{}_extracted", device_id);
+    let target_tar = format!("{}.tar", device_id);
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+
+    assert!(adbackup::extract(device_id, Some(1), &target_dir, None).is_err());
+    assert_eq!(
+        adbackup::extract(device_id, Some(1), &target_dir, Some("adbackup")).unwrap(),
+        format!("Extracted 2 file(s) of backup to {}.", target_dir)
+    );
+    assert!(Path::new(&target_dir).join("apps/org.cryptomator/sp/settings.xml").is_file());
+
+    assert!(adbackup::extract(device_id, None, &target_tar, Some("adbackup")).is_ok());
+    assert!(metadata(&target_tar).unwrap().len() > 0);
+
+    assert_eq!(
+        format!("{}", adbackup::extract(device_id, Some(2), &target_tar, None).unwrap_err()),
+        "no backup with version 2 found"
+    );
+
+    assert!(remove_dir_all(&target_dir).is_ok());
+    assert!(remove_file(&target_tar).is_ok());
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_list_and_show_backups() {
+    let device_id = "73595d5dc6072f07602852338aa002b9"; // md5 of 'test_list_and_show_backups'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &["-s", device_id, "shell", "getprop"],
+            AdbOutput::stdout(
+                "[ro.build.version.release]: [9]\n\
+                 [ro.build.version.sdk]: [28]\n\
+                 [ro.product.manufacturer]: [Google]\n",
+            ),
+        )
+        .expect(
+            &["-s", device_id, "shell", "dumpsys", "battery"],
+            AdbOutput::stdout("Current Battery Service state:\n  level: 85\n  scale: 100\n"),
+        )
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    assert_eq!(
+        adbackup::get_printable_backup_list(device_id).unwrap(),
+        "No backups found."
+    );
+
+    // the contents of an encrypted backup are only recorded if it is made with its password
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+    assert!(adbackup::backup(device_id, None, None, None, None, Some("adbackup")).is_ok());
+
+    let backup_list = adbackup::get_printable_backup_list(device_id).unwrap();
+    assert!(backup_list.starts_with("Found the following backup(s) of device:"));
+    assert!(backup_list.contains("Version: 1, created: '"));
+    assert!(backup_list.contains(" bytes, stored: "));
+    assert!(backup_list.contains("options: '-noapk -noshared -nosystem -all', contents not recorded\r\n"));
+    assert!(backup_list.ends_with("options: '-noapk -noshared -nosystem -all', packages: 1, files: 2"));
+
+    // the recorded contents are shown without the password, the ones not recorded are read
+    let backup = adbackup::get_printable_backup(device_id, 2, None).unwrap();
+    assert!(backup.contains("\r\n\r\nPackages:\r\norg.cryptomator: 2 file(s), "));
+
+    let backup = adbackup::get_printable_backup(device_id, 1, Some("adbackup")).unwrap();
+    assert!(backup.contains(&format!(
+        "\r\nDevice: 'Android_SDK_built_for_x86' ({}), android: 9, duration: ",
+        device_id
+    )));
+    // the device info recorded with the backup, the device did not report its storage
+    assert!(backup.contains("\r\nManufacturer: Google, android: 9, sdk: 28\r\nSerial: unknown\r\n"));
+    assert!(backup.contains("\r\nBattery: 85%\r\n\r\nPackages:"));
+    assert!(backup.contains("\r\n\r\nPackages:\r\norg.cryptomator: 2 file(s), "));
+    assert!(backup.ends_with("bytes (_manifest, sp)"));
+
+    assert_eq!(
+        format!("{}", adbackup::get_printable_backup(device_id, 3, None).unwrap_err()),
+        "no backup with version 3 found"
+    );
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_verify_backups() {
+    let device_id = "9c79333b495af4807ed2eec5e91b9442"; // md5 of 'test_verify_backups'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    for _ in 0..2 {
+        copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+        assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    }
+
+    assert_eq!(adbackup::verify(device_id).unwrap(), "Verified 2 backup(s).");
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_prune_backups() {
+    let device_id = "72cc4b6e7a451816e707043410c6ef58"; // md5 of 'test_prune_backups'
+    let backup_file = format!("{}.ab", device_id);
+    let repository = format!("{}.repo", device_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let runner = ScriptedRunner::new()
+        .expect(&["devices", "-l"], devices_output(&[device_id]))
+        .expect(
+            &[
+                "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk", "-all",
+                "-f", &backup_file,
+            ],
+            AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+        );
+    adbackup::set_adb_runner(runner);
+
+    for _ in 0..3 {
+        copy("tests/test_backups/encrypted_v5.ab", &backup_file).unwrap();
+        assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    }
+
+    assert_eq!(
+        format!("{}", adbackup::prune(device_id, &RetentionPolicy::default(), false).unwrap_err()),
+        "At least one of keep-last, keep-daily, keep-weekly and keep-monthly must be given"
+    );
+
+    let policy = RetentionPolicy::default().with_keep_last(1);
+
+    let dry_run = adbackup::prune(device_id, &policy, true).unwrap();
+    assert!(dry_run.starts_with("Would remove 2 of 3 backup(s):\r\nVersion: 1, created: '"));
+    assert_eq!(adbackup::verify(device_id).unwrap(), "Verified 3 backup(s).");
+
+    let pruned = adbackup::prune(device_id, &policy, false).unwrap();
+    assert!(pruned.starts_with("Removed 2 of 3 backup(s), "));
+    assert_eq!(adbackup::verify(device_id).unwrap(), "Verified 1 backup(s).");
+
+    assert_eq!(
+        adbackup::prune(device_id, &policy, false).unwrap(),
+        "No backups to remove, keeping 1 backup(s)."
+    );
+
+    assert!(remove_dir_all(&repository).is_ok());
+}
+
+#[test]
+fn test_backups_of_a_device_stay_together() {
+    let usb_id = "487130e8d55566eaad50600fd457f48c"; // md5 of 'test_backups_of_a_device_stay_together'
+    let wifi_id = "192.168.2.100:5555";
+    let repository = format!("{}.repo", usb_id);
+    adbackup::set_repository(Path::new(&repository));
+
+    let mut runner = ScriptedRunner::new().expect(&["devices", "-l"], devices_output(&[usb_id, wifi_id]));
+    for device_id in &[usb_id, wifi_id] {
+        runner = runner
+            .expect(
+                &["-s", device_id, "shell", "getprop", "ro.serialno"],
+                AdbOutput::stdout(&format!("{}\n", usb_id)),
+            )
+            .expect(
+                &[
+                    "-s", device_id, "backup", "-noapk", "-noshared", "-nosystem", "-noapk",
+                    "-all", "-f", &format!("{}.ab", device_id),
+                ],
+                AdbOutput::stdout("Now unlock your device and confirm the backup operation..."),
+            );
+    }
+    adbackup::set_adb_runner(runner);
+
+    // the database kept for the device connected over wifi before
+    create_dir_all(&repository).unwrap();
+    copy("tests/test_databases/dummy_db_v3.db", Path::new(&repository).join("192.168.2.100:5555.db")).unwrap();
+
+    for device_id in &[usb_id, wifi_id] {
+        copy("tests/test_backups/encrypted_v5.ab", format!("{}.ab", device_id)).unwrap();
+        assert!(adbackup::backup(device_id, None, None, None, None, None).is_ok());
+    }
+    assert!(Path::new(&repository).join("192.168.2.100:5555.db.imported").is_file());
+
+    // the device can not be asked anymore, it is found by the id it was reached with last
+    adbackup::set_adb_runner(ScriptedRunner::new());
+
+    let backup_list = adbackup::get_printable_backup_list(wifi_id).unwrap();
+    assert!(backup_list.contains("Version: 1, created: '"));
+    assert!(backup_list.contains("Version: 2, created: '"));
+
+    assert!(remove_dir_all(&repository).is_ok());
+}